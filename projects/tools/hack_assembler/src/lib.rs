@@ -1,6 +1,6 @@
 mod instructions;
 mod parser;
-mod symbol_table;
+pub mod symbol_table;
 mod tokenizer;
 
-pub use parser::parse;
+pub use parser::{assemble, parse, Assembly, ParseError};
@@ -7,7 +7,7 @@ use tempfile::tempfile;
 
 use crate::{
     instructions::{AInstruction, CInstruction},
-    symbol_table::{HackRomSize, SymbolTable, SymbolTableError, START_CMP_INSTR},
+    symbol_table::{HackInstSize, HackRomSize, SymbolTable, SymbolTableError, START_CMP_INSTR},
     tokenizer::{tokenize, Token, TokenError},
 };
 
@@ -41,13 +41,36 @@ impl From<CInstWithSymbols<'_>> for u16 {
     }
 }
 
+///A program assembled in memory, along with the labels and variables it defined
+#[derive(Debug)]
+pub struct Assembly {
+    pub instructions: Vec<HackInstSize>,
+    pub symbols: SymbolTable,
+}
+
 pub fn parse<R, W>(source: &mut BufReader<R>, dest: &mut BufWriter<W>) -> Result<(), ParseError>
 where
     R: Read,
     W: Write,
 {
     let (symbols, mut parsed_source) = first_pass(source)?;
-    Ok(convert_to_bin(symbols, &mut parsed_source, dest)?)
+    convert_to_bin(symbols, &mut parsed_source, |instruction| {
+        Ok(writeln!(dest, "{}", bin_string(instruction))?)
+    })?;
+    Ok(())
+}
+
+pub fn assemble<R: Read>(source: &mut BufReader<R>) -> Result<Assembly, ParseError> {
+    let (symbols, mut parsed_source) = first_pass(source)?;
+    let mut instructions = Vec::new();
+    let symbols = convert_to_bin(symbols, &mut parsed_source, |instruction| {
+        instructions.push(instruction);
+        Ok(())
+    })?;
+    Ok(Assembly {
+        instructions,
+        symbols,
+    })
 }
 
 fn first_pass<R: Read>(
@@ -77,11 +100,14 @@ fn first_pass<R: Read>(
     Ok((symbols, BufReader::new(tmp_file)))
 }
 
-fn convert_to_bin<W: Write>(
+fn convert_to_bin<F>(
     mut symbols: SymbolTable,
     tokens: &mut BufReader<File>,
-    target: &mut BufWriter<W>,
-) -> Result<(), ParseError> {
+    mut emit: F,
+) -> Result<SymbolTable, ParseError>
+where
+    F: FnMut(HackInstSize) -> Result<(), ParseError>,
+{
     let mut line = String::new();
     while tokens.read_line(&mut line)? > 0 {
         let trimmed_line = line.trim();
@@ -104,12 +130,11 @@ fn convert_to_bin<W: Write>(
                 Token::CInstruction(ref cinstr) => Ok(CInstWithSymbols(cinstr, &symbols).into()),
                 token => Err(ParseError::NonCompilableToken(token.clone())),
             }?;
-            let bin = bin_string(token);
-            writeln!(target, "{}", bin)?;
+            emit(token)?;
         }
         line = "".to_owned();
     }
-    Ok(())
+    Ok(symbols)
 }
 
 fn bin_string(mut val: u16) -> String {
@@ -149,4 +174,16 @@ mod test {
         let actual = String::from_utf8(writer.into_inner().unwrap()).unwrap();
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn it_assembles_in_memory_with_symbols() {
+        let mut reader = setup(Path::new("./test_files/Max.asm"));
+        let assembly = assemble(&mut reader).unwrap();
+        let mut reader = setup(Path::new("./test_files/test-cmp.hack"));
+        let mut expected = String::new();
+        reader.read_to_string(&mut expected).unwrap();
+        let actual: Vec<_> = assembly.instructions.into_iter().map(bin_string).collect();
+        assert_eq!(actual, expected.lines().collect::<Vec<_>>());
+        assert_eq!(assembly.symbols.get_line_no("OUTPUT_D"), Some(12));
+    }
 }
@@ -31,16 +31,18 @@ const PREDEF_ALIASES: [(&str, HackMemSize); 23] = [
     ("SCREEN", SCREEN_MEM),
     ("KBD", KBD_MEM),
 ];
-const SCREEN_MEM: HackMemSize = 0x4000;
-const KBD_MEM: HackMemSize = 0x6000;
-
-const DEST_INSTR: [(&str, HackInstSize); 3] =
-    [("M", 0b001 << 3), ("D", 0b010 << 3), ("A", 0b100 << 3)];
-
-const JGT: HackInstSize = 0b001;
-const JEQ: HackInstSize = 0b010;
-const JLT: HackInstSize = 0b100;
-const JMP_INSTR: [(&str, HackInstSize); 7] = [
+pub const SCREEN_MEM: HackMemSize = 0x4000;
+pub const KBD_MEM: HackMemSize = 0x6000;
+
+pub const DEST_M: HackInstSize = 0b001 << 3;
+pub const DEST_D: HackInstSize = 0b010 << 3;
+pub const DEST_A: HackInstSize = 0b100 << 3;
+pub const DEST_INSTR: [(&str, HackInstSize); 3] = [("M", DEST_M), ("D", DEST_D), ("A", DEST_A)];
+
+pub const JGT: HackInstSize = 0b001;
+pub const JEQ: HackInstSize = 0b010;
+pub const JLT: HackInstSize = 0b100;
+pub const JMP_INSTR: [(&str, HackInstSize); 7] = [
     ("JGT", JGT),
     ("JEQ", JEQ),
     ("JLT", JLT),
@@ -50,15 +52,15 @@ const JMP_INSTR: [(&str, HackInstSize); 7] = [
     ("JMP", JLT | JGT | JEQ),
 ];
 
-const C6: u16 = 0b0000001 << 6;
-const C5: u16 = 0b0000010 << 6;
-const C4: u16 = 0b0000100 << 6;
-const C3: u16 = 0b0001000 << 6;
-const C2: u16 = 0b0010000 << 6;
-const C1: u16 = 0b0100000 << 6;
-const A_BIT: u16 = 0b1000000 << 6;
+pub const C6: u16 = 0b0000001 << 6;
+pub const C5: u16 = 0b0000010 << 6;
+pub const C4: u16 = 0b0000100 << 6;
+pub const C3: u16 = 0b0001000 << 6;
+pub const C2: u16 = 0b0010000 << 6;
+pub const C1: u16 = 0b0100000 << 6;
+pub const A_BIT: u16 = 0b1000000 << 6;
 
-const COMP_INSTR: [(&str, HackInstSize); 28] = [
+pub const COMP_INSTR: [(&str, HackInstSize); 28] = [
     ("0", C5 | C3 | C1),
     ("1", C6 | C5 | C4 | C3 | C2 | C1),
     ("-1", C5 | C3 | C2 | C1),
@@ -107,7 +109,7 @@ pub struct SymbolTable {
     comp_instr: HashMap<String, HackInstSize>,
 }
 
-impl<'a> Default for SymbolTable {
+impl Default for SymbolTable {
     fn default() -> Self {
         SymbolTable::new()
     }
@@ -170,6 +172,12 @@ impl SymbolTable {
         self.labels.get(label).copied()
     }
 
+    pub fn labels(&self) -> impl Iterator<Item = (&str, HackRomSize)> {
+        self.labels
+            .iter()
+            .map(|(label, line_no)| (label.as_str(), *line_no))
+    }
+
     pub fn get_jmp_instr(&self, jmp_instr: &str) -> Option<HackInstSize> {
         self.jmp_instr.get(jmp_instr).copied()
     }
@@ -132,7 +132,7 @@ fn extract_c_instruction(line: &str) -> Result<Option<Token>, TokenError> {
         Some(idx) => (Some(cmp_string[..idx].to_string()), Some(cmp_string[idx+1..].to_string())),
         None => (Some(cmp_string.to_string()), None)
     };
-    if cmp == None {
+    if cmp.is_none() {
         Err(TokenError::MissingCmpInstruction)
     } else {
         Ok(Some(Token::CInstruction(CInstruction::new(dest, cmp.unwrap_or_default(), jmp))))
@@ -140,11 +140,11 @@ fn extract_c_instruction(line: &str) -> Result<Option<Token>, TokenError> {
 }
 
 fn is_valid_symbol_first_char(c: char) -> bool {
-    is_valid_symbol(c) && !c.is_digit(10)
+    is_valid_symbol(c) && !c.is_ascii_digit()
 }
 
 fn is_valid_symbol(c: char) -> bool {
-    c.is_ascii() && (c.is_alphabetic() || c.is_digit(10) || 
+    c.is_ascii() && (c.is_alphabetic() || c.is_ascii_digit() || 
         c == '_' || c == '.' || c == '$' || c == ':'
     )
 }
@@ -0,0 +1,98 @@
+use hack_assembler::symbol_table::{HackMemSize, HackRomSize};
+
+use crate::{
+    instruction::Instruction,
+    memory::{MemoryError, Ram},
+};
+
+///The A, D and PC registers of the Hack CPU
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Cpu {
+    a: u16,
+    d: u16,
+    pc: HackRomSize,
+}
+
+impl Cpu {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn a(&self) -> u16 {
+        self.a
+    }
+
+    pub fn d(&self) -> u16 {
+        self.d
+    }
+
+    pub fn pc(&self) -> HackRomSize {
+        self.pc
+    }
+
+    pub fn reset(&mut self) {
+        self.pc = 0;
+    }
+
+    ///Executes a single instruction, as the CPU would in one clock cycle. The memory write and the
+    ///jump both use the value A held before the instruction ran
+    pub fn execute(&mut self, instruction: Instruction, ram: &mut Ram) -> Result<(), MemoryError> {
+        match instruction {
+            Instruction::A(value) => {
+                self.a = value;
+                self.pc = self.pc.wrapping_add(1);
+            }
+            Instruction::C(cinstr) => {
+                let addr: HackMemSize = self.a;
+                let y = if cinstr.reads_mem() {
+                    ram.read(addr)?
+                } else {
+                    self.a
+                };
+                let out = cinstr.compute(self.d, y);
+                if cinstr.writes_mem() {
+                    ram.write(addr, out)?;
+                }
+                if cinstr.writes_a_reg() {
+                    self.a = out;
+                }
+                if cinstr.writes_d_reg() {
+                    self.d = out;
+                }
+                self.pc = if cinstr.should_jump(out) {
+                    addr
+                } else {
+                    self.pc.wrapping_add(1)
+                };
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_loads_a_from_a_instructions() {
+        let mut cpu = Cpu::new();
+        let mut ram = Ram::new();
+        cpu.execute(Instruction::A(17), &mut ram).unwrap();
+        assert_eq!((cpu.a(), cpu.pc()), (17, 1));
+    }
+
+    #[test]
+    fn it_writes_memory_and_jumps_with_the_previous_a_value() {
+        let mut cpu = Cpu::new();
+        let mut ram = Ram::new();
+        ram.write(5, 9).unwrap();
+        cpu.execute(Instruction::A(5), &mut ram).unwrap();
+        //AM=M+1;JMP
+        cpu.execute(Instruction::from(0b1111_1101_1110_1111), &mut ram)
+            .unwrap();
+        assert_eq!(ram.read(5), Ok(10));
+        assert_eq!(cpu.a(), 10);
+        assert_eq!(cpu.pc(), 5);
+    }
+}
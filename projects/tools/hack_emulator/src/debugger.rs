@@ -0,0 +1,414 @@
+use std::io::{self, BufRead, Write};
+
+use hack_assembler::symbol_table::{HackMemSize, HackRomSize};
+
+use crate::{
+    disassembler::disassemble,
+    emulator::{Emulator, RunOutcome},
+    symbols::Symbols,
+};
+
+const PROMPT: &str = "(hdb) ";
+const WINDOW_BEFORE: HackRomSize = 3;
+const WINDOW_AFTER: HackRomSize = 4;
+const WORDS_PER_ROW: usize = 8;
+const HELP: &str = "\
+step|s [n]              run n instructions (default 1)
+next|n                  run until the instruction after this one
+continue|c [cycles]     run until a breakpoint, watchpoint or halt
+break|b <addr|label>    stop when the PC reaches a ROM address
+delete|d <addr|label>   remove a breakpoint
+watch|w <RAM[n]|symbol> stop when a RAM cell changes
+unwatch <RAM[n]|symbol> remove a watchpoint
+info registers|breakpoints|watchpoints
+x/<n> <RAM[n]|symbol>   show n RAM cells (default 1)
+list|l                  show the instructions around the PC
+display on|off          show registers and code after every stop
+reset                   set the PC back to 0
+quit|q                  leave the debugger
+An empty line repeats the last command";
+
+///Commands that can't run are reported at the prompt, only i/o errors end the session
+#[derive(thiserror::Error, Debug)]
+enum CommandError {
+    #[error("i/o error: {0}")]
+    Io(#[from] io::Error),
+    #[error("{0}")]
+    Invalid(String),
+}
+
+///A gdb-like command prompt over an emulator, using the program's symbols to name ROM and RAM
+///addresses
+pub struct Debugger {
+    emulator: Emulator,
+    symbols: Symbols,
+    max_cycles: u64,
+    last_command: String,
+    display: bool,
+}
+
+impl Debugger {
+    ///`max_cycles` bounds `continue` and `next` so that programs polling the keyboard still
+    ///return to the prompt
+    pub fn new(emulator: Emulator, symbols: Symbols, max_cycles: u64) -> Self {
+        Self {
+            emulator,
+            symbols,
+            max_cycles,
+            last_command: String::new(),
+            display: true,
+        }
+    }
+
+    pub fn emulator(&self) -> &Emulator {
+        &self.emulator
+    }
+
+    ///Reads commands until `quit` or the end of the input
+    pub fn repl<R: BufRead, W: Write>(&mut self, input: R, out: &mut W) -> io::Result<()> {
+        self.show_location(out)?;
+        write!(out, "{}", PROMPT)?;
+        out.flush()?;
+        for line in input.lines() {
+            let line = line?;
+            let command = match line.trim() {
+                "" => self.last_command.clone(),
+                command => command.to_owned(),
+            };
+            self.last_command = command.clone();
+            if !self.execute(&command, out)? {
+                return Ok(());
+            }
+            write!(out, "{}", PROMPT)?;
+            out.flush()?;
+        }
+        writeln!(out)
+    }
+
+    ///Runs a single command, returning false once the session should end
+    pub fn execute<W: Write>(&mut self, command: &str, out: &mut W) -> io::Result<bool> {
+        let mut words = command.split_whitespace();
+        let (name, arg) = (words.next().unwrap_or(""), words.next());
+        if matches!(name, "quit" | "q") {
+            return Ok(false);
+        }
+        match self.dispatch(name, arg, out) {
+            Ok(()) => {}
+            Err(CommandError::Io(err)) => return Err(err),
+            Err(CommandError::Invalid(err)) => writeln!(out, "error: {}", err)?,
+        }
+        Ok(true)
+    }
+
+    fn dispatch<W: Write>(
+        &mut self,
+        name: &str,
+        arg: Option<&str>,
+        out: &mut W,
+    ) -> Result<(), CommandError> {
+        match name {
+            "" => {}
+            "step" | "s" => self.run(parse_count(arg, 1)?, out)?,
+            "next" | "n" => self.next(out)?,
+            "continue" | "c" => self.run(parse_count(arg, self.max_cycles)?, out)?,
+            "break" | "b" => {
+                let addr = self.rom_addr(arg)?;
+                self.emulator.add_breakpoint(addr);
+                writeln!(out, "Breakpoint at {}", self.describe_rom(addr))?;
+            }
+            "delete" | "d" => {
+                let addr = self.rom_addr(arg)?;
+                if !self.emulator.remove_breakpoint(addr) {
+                    return Err(invalid(format!("no breakpoint at {}", addr)));
+                }
+            }
+            "watch" | "w" => {
+                let addr = self.ram_addr(arg)?;
+                self.emulator
+                    .add_watchpoint(addr)
+                    .map_err(|err| invalid(err.to_string()))?;
+                writeln!(out, "Watchpoint on RAM[{}]", addr)?;
+            }
+            "unwatch" => {
+                let addr = self.ram_addr(arg)?;
+                if !self.emulator.remove_watchpoint(addr) {
+                    return Err(invalid(format!("no watchpoint on RAM[{}]", addr)));
+                }
+            }
+            "info" | "i" => match arg {
+                Some("registers" | "r") => self.show_registers(out)?,
+                Some("breakpoints" | "b") => self.show_breakpoints(out)?,
+                Some("watchpoints" | "w") => self.show_watchpoints(out)?,
+                _ => {
+                    return Err(invalid(
+                        "expected info registers, breakpoints or watchpoints",
+                    ))
+                }
+            },
+            "list" | "l" => self.show_window(out)?,
+            "display" => match arg {
+                Some("on") | None => self.display = true,
+                Some("off") => self.display = false,
+                Some(other) => {
+                    return Err(invalid(format!("expected on or off, found '{}'", other)))
+                }
+            },
+            "reset" => {
+                self.emulator.reset();
+                self.show_location(out)?;
+            }
+            "help" | "h" => writeln!(out, "{}", HELP)?,
+            examine if examine == "x" || examine.starts_with("x/") => {
+                let count = parse_count(examine.strip_prefix("x/"), 1)?;
+                let addr = self.ram_addr(arg)?;
+                self.examine(addr, count, out)?;
+            }
+            unknown => {
+                return Err(invalid(format!("unknown command '{}', try help", unknown)));
+            }
+        }
+        Ok(())
+    }
+
+    fn run<W: Write>(&mut self, cycles: u64, out: &mut W) -> Result<(), CommandError> {
+        let outcome = self
+            .emulator
+            .run(cycles)
+            .map_err(|err| invalid(err.to_string()))?;
+        Ok(self.report(outcome, out)?)
+    }
+
+    ///Steps over the current instruction, which only differs from `step` on a jump back
+    fn next<W: Write>(&mut self, out: &mut W) -> Result<(), CommandError> {
+        let next = self.emulator.cpu().pc().wrapping_add(1);
+        let temporary = !self.emulator.breakpoints().any(|addr| addr == next);
+        if temporary {
+            self.emulator.add_breakpoint(next);
+        }
+        let outcome = self.emulator.run(self.max_cycles);
+        if temporary {
+            self.emulator.remove_breakpoint(next);
+        }
+        let outcome = match outcome.map_err(|err| invalid(err.to_string()))? {
+            RunOutcome::Breakpoint(addr) if temporary && addr == next => RunOutcome::CycleLimit,
+            outcome => outcome,
+        };
+        Ok(self.report(outcome, out)?)
+    }
+
+    fn report<W: Write>(&self, outcome: RunOutcome, out: &mut W) -> io::Result<()> {
+        match outcome {
+            RunOutcome::Halted => writeln!(
+                out,
+                "Program halted at PC {}",
+                self.describe_rom(self.emulator.cpu().pc())
+            )?,
+            RunOutcome::Breakpoint(addr) => {
+                writeln!(out, "Breakpoint at {}", self.describe_rom(addr))?
+            }
+            RunOutcome::Watchpoint(addr, old, new) => writeln!(
+                out,
+                "Watchpoint RAM[{}]: {} -> {}",
+                addr, old as i16, new as i16
+            )?,
+            RunOutcome::CycleLimit => {}
+        }
+        self.show_location(out)
+    }
+
+    fn show_location<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        if self.display {
+            self.show_registers(out)?;
+            self.show_window(out)?;
+        }
+        Ok(())
+    }
+
+    fn show_registers<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let cpu = self.emulator.cpu();
+        let m = self
+            .emulator
+            .ram()
+            .read(cpu.a())
+            .map_or_else(|_| "-".to_owned(), |m| (m as i16).to_string());
+        writeln!(
+            out,
+            "A={} D={} M={} PC={} cycles={}",
+            cpu.a(),
+            cpu.d() as i16,
+            m,
+            cpu.pc(),
+            self.emulator.cycles()
+        )
+    }
+
+    fn show_window<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let pc = self.emulator.cpu().pc();
+        let start = pc.saturating_sub(WINDOW_BEFORE);
+        let end = pc.saturating_add(WINDOW_AFTER);
+        for addr in start..=end {
+            let instruction = match self.emulator.rom().fetch(addr) {
+                Some(instruction) => instruction,
+                None => break,
+            };
+            for label in self.symbols.labels_at(addr) {
+                writeln!(out, "({})", label)?;
+            }
+            let marker = if addr == pc { "=>" } else { "  " };
+            writeln!(out, "{} {:>5}  {}", marker, addr, disassemble(instruction))?;
+        }
+        Ok(())
+    }
+
+    fn show_breakpoints<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        if self.emulator.breakpoints().next().is_none() {
+            return writeln!(out, "No breakpoints");
+        }
+        for addr in self.emulator.breakpoints() {
+            writeln!(out, "Breakpoint at {}", self.describe_rom(addr))?;
+        }
+        Ok(())
+    }
+
+    fn show_watchpoints<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        if self.emulator.watchpoints().next().is_none() {
+            return writeln!(out, "No watchpoints");
+        }
+        for addr in self.emulator.watchpoints() {
+            let value = self.emulator.ram().read(addr).unwrap_or_default();
+            writeln!(out, "Watchpoint on RAM[{}] = {}", addr, value as i16)?;
+        }
+        Ok(())
+    }
+
+    fn examine<W: Write>(&self, addr: HackMemSize, count: u64, out: &mut W) -> io::Result<()> {
+        let addrs: Vec<_> = (addr..=HackMemSize::MAX)
+            .take(count as usize)
+            .map_while(|addr| self.emulator.ram().read(addr).ok().map(|v| (addr, v)))
+            .collect();
+        if addrs.is_empty() {
+            return writeln!(out, "error: RAM[{}] is out of bounds", addr);
+        }
+        for row in addrs.chunks(WORDS_PER_ROW) {
+            write!(out, "RAM[{}]:", row[0].0)?;
+            for (_, value) in row {
+                write!(out, " {:>6}", *value as i16)?;
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    fn describe_rom(&self, addr: HackRomSize) -> String {
+        match self.symbols.enclosing_label(addr) {
+            Some((start, label)) if start == addr => format!("{} ({})", addr, label),
+            Some((start, label)) => format!("{} ({}+{})", addr, label, addr - start),
+            None => addr.to_string(),
+        }
+    }
+
+    fn rom_addr(&self, arg: Option<&str>) -> Result<HackRomSize, CommandError> {
+        let arg = arg.ok_or_else(|| invalid("expected a ROM address or label"))?;
+        arg.parse()
+            .ok()
+            .or_else(|| self.symbols.label_addr(arg))
+            .ok_or_else(|| invalid(format!("unknown label '{}'", arg)))
+    }
+
+    ///Accepts `256`, `RAM[256]` or a variable/predefined symbol such as `SP` or `R2`
+    fn ram_addr(&self, arg: Option<&str>) -> Result<HackMemSize, CommandError> {
+        let arg = arg.ok_or_else(|| invalid("expected a RAM address or symbol"))?;
+        let inner = arg
+            .strip_prefix("RAM[")
+            .and_then(|rest| rest.strip_suffix(']'))
+            .unwrap_or(arg);
+        inner
+            .parse()
+            .ok()
+            .or_else(|| self.symbols.ram_addr(inner))
+            .ok_or_else(|| invalid(format!("unknown RAM location '{}'", arg)))
+    }
+}
+
+fn parse_count(arg: Option<&str>, default: u64) -> Result<u64, CommandError> {
+    arg.map_or(Ok(default), |count| {
+        count
+            .parse()
+            .map_err(|_| invalid(format!("expected a count, found '{}'", count)))
+    })
+}
+
+fn invalid<S: Into<String>>(message: S) -> CommandError {
+    CommandError::Invalid(message.into())
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+    use crate::program::load_program;
+
+    fn max_debugger() -> Debugger {
+        let (rom, symbols) = load_program(Path::new("./test_files/Max.asm")).unwrap();
+        let mut emulator = Emulator::new(rom);
+        emulator.ram_mut().write(0, 3).unwrap();
+        emulator.ram_mut().write(1, 5).unwrap();
+        Debugger::new(emulator, symbols, 1000)
+    }
+
+    fn session(debugger: &mut Debugger, commands: &str) -> String {
+        let mut out = Vec::new();
+        debugger.repl(commands.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn it_stops_at_label_breakpoints() {
+        let mut debugger = max_debugger();
+        let out = session(&mut debugger, "display off\nb OUTPUT_D\nc\ninfo r\nq\n");
+        assert!(out.contains("Breakpoint at 12 (OUTPUT_D)\n(hdb) A=12 D=5 M=0 PC=12 cycles=10"));
+    }
+
+    #[test]
+    fn it_stops_on_watched_cells() {
+        let mut debugger = max_debugger();
+        let out = session(&mut debugger, "display off\nw R2\nc\nc\n");
+        assert!(out.contains("Watchpoint RAM[2]: 0 -> 5"));
+        assert!(out.contains("Program halted at PC 14 (INFINITE_LOOP)"));
+    }
+
+    #[test]
+    fn it_repeats_the_last_command_on_empty_lines() {
+        let mut debugger = max_debugger();
+        session(&mut debugger, "display off\ns 2\n\n\n");
+        assert_eq!(debugger.emulator().cycles(), 6);
+    }
+
+    #[test]
+    fn it_lists_code_around_the_pc() {
+        let mut debugger = max_debugger();
+        let out = session(&mut debugger, "display off\nb 12\nc\nl\n");
+        assert!(out.contains(
+            "    9  0;JMP\n(OUTPUT_FIRST)\n      10  @0\n      11  D=M\n(OUTPUT_D)\n=>    12  @2\n"
+        ));
+    }
+
+    #[test]
+    fn it_examines_ram() {
+        let mut debugger = max_debugger();
+        let out = session(&mut debugger, "display off\nx/10 RAM[0]\nx SP\n");
+        assert!(out.contains("RAM[0]:      3      5      0      0      0      0      0      0\n"));
+        assert!(out.contains("RAM[8]:      0      0\n"));
+        assert!(out.contains("RAM[0]:      3\n"));
+    }
+
+    #[test]
+    fn it_reports_bad_commands() {
+        let mut debugger = max_debugger();
+        let out = session(&mut debugger, "display off\nb NOWHERE\nx/ten 0\nfly\n");
+        assert!(out.contains("error: unknown label 'NOWHERE'"));
+        assert!(out.contains("error: expected a count, found 'ten'"));
+        assert!(out.contains("error: unknown command 'fly', try help"));
+    }
+}
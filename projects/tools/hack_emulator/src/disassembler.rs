@@ -0,0 +1,68 @@
+use hack_assembler::symbol_table::{COMP_INSTR, DEST_INSTR, JMP_INSTR};
+
+use crate::instruction::Instruction;
+
+const DEST_ORDER: [&str; 3] = ["A", "M", "D"];
+
+///Turns an instruction back into Hack assembly, using the mnemonics of the assembler's tables
+pub fn disassemble(instruction: Instruction) -> String {
+    match instruction {
+        Instruction::A(value) => format!("@{}", value),
+        Instruction::C(cinstr) => {
+            let comp = COMP_INSTR
+                .iter()
+                .find(|(_, bits)| *bits == cinstr.comp())
+                .map_or_else(
+                    || format!("<comp {:07b}>", cinstr.comp() >> 6),
+                    |(m, _)| m.to_string(),
+                );
+            let dest: String = DEST_ORDER
+                .iter()
+                .filter(|reg| {
+                    DEST_INSTR
+                        .iter()
+                        .any(|(m, bits)| m == *reg && cinstr.dest() & bits != 0)
+                })
+                .copied()
+                .collect();
+            let jump = JMP_INSTR
+                .iter()
+                .find(|(_, bits)| *bits == cinstr.jump())
+                .map(|(m, _)| *m);
+            match (dest.is_empty(), jump) {
+                (true, None) => comp,
+                (false, None) => format!("{}={}", dest, comp),
+                (true, Some(jump)) => format!("{};{}", comp, jump),
+                (false, Some(jump)) => format!("{}={};{}", dest, comp, jump),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::BufReader;
+
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("@17"; "a instruction")]
+    #[test_case("D=M"; "dest and comp")]
+    #[test_case("0;JMP"; "comp and jump")]
+    #[test_case("AMD=D+1;JEQ"; "all dests")]
+    #[test_case("MD=M-1"; "two dests")]
+    #[test_case("D|A"; "comp only")]
+    fn it_round_trips_assembly(asm: &str) {
+        let assembly = hack_assembler::assemble(&mut BufReader::new(asm.as_bytes())).unwrap();
+        assert_eq!(disassemble(assembly.instructions[0].into()), asm);
+    }
+
+    #[test]
+    fn it_shows_comp_bits_without_a_mnemonic() {
+        assert_eq!(
+            disassemble(Instruction::from(0b1110_0000_0100_0000)),
+            "<comp 0000001>"
+        );
+    }
+}
@@ -0,0 +1,116 @@
+use std::{fmt::Display, ops::Range, str::FromStr};
+
+use hack_assembler::symbol_table::HackMemSize;
+
+use crate::memory::{MemoryError, Ram};
+
+const COLUMN_WIDTH: usize = 8;
+
+///A range of RAM addresses, written either as a single address `256` or as `256..260`
+#[derive(Debug, PartialEq, Clone)]
+pub struct RamRange(Range<HackMemSize>);
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum RamRangeError {
+    #[error("invalid RAM range '{0}', expected an address or start..end")]
+    Invalid(String),
+}
+
+impl FromStr for RamRange {
+    type Err = RamRangeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || RamRangeError::Invalid(s.to_owned());
+        let range = match s.split_once("..") {
+            Some((start, end)) => {
+                start.trim().parse().map_err(|_| invalid())?
+                    ..end.trim().parse().map_err(|_| invalid())?
+            }
+            None => {
+                let addr: HackMemSize = s.trim().parse().map_err(|_| invalid())?;
+                addr..addr.checked_add(1).ok_or_else(invalid)?
+            }
+        };
+        if range.is_empty() {
+            Err(invalid())
+        } else {
+            Ok(RamRange(range))
+        }
+    }
+}
+
+impl IntoIterator for RamRange {
+    type Item = HackMemSize;
+    type IntoIter = Range<HackMemSize>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0
+    }
+}
+
+///A snapshot of RAM cells laid out like the `.cmp` files of the course, so that a dump can be
+///compared with them directly
+pub struct RamDump {
+    cells: Vec<(HackMemSize, u16)>,
+}
+
+impl RamDump {
+    pub fn new<I>(ram: &Ram, addrs: I) -> Result<Self, MemoryError>
+    where
+        I: IntoIterator<Item = HackMemSize>,
+    {
+        Ok(Self {
+            cells: addrs
+                .into_iter()
+                .map(|addr| ram.read(addr).map(|value| (addr, value)))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl Display for RamDump {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "|")?;
+        for (addr, _) in &self.cells {
+            let mut name = format!("RAM[{}]", addr);
+            name.truncate(COLUMN_WIDTH);
+            write!(f, "{:^width$}|", name, width = COLUMN_WIDTH)?;
+        }
+        write!(f, "\n|")?;
+        for (_, value) in &self.cells {
+            write!(f, " {:>width$} |", *value as i16, width = COLUMN_WIDTH - 2)?;
+        }
+        writeln!(f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("256", 256..257; "single address")]
+    #[test_case("256..260", 256..260; "range")]
+    fn it_parses_ram_ranges(range: &str, expected: Range<HackMemSize>) {
+        assert_eq!(range.parse(), Ok(RamRange(expected)));
+    }
+
+    #[test_case("260..256"; "reversed range")]
+    #[test_case("RAM[256]"; "not a number")]
+    fn it_rejects_invalid_ram_ranges(range: &str) {
+        assert!(range.parse::<RamRange>().is_err());
+    }
+
+    #[test]
+    fn it_formats_dumps_like_cmp_files() {
+        let mut ram = Ram::new();
+        ram.write(0, 257).unwrap();
+        ram.write(256, 6).unwrap();
+        ram.write(3006, (-36_i16) as u16).unwrap();
+        let dump = RamDump::new(&ram, [0, 256, 3006]).unwrap();
+        assert_eq!(
+            dump.to_string(),
+            "| RAM[0] |RAM[256]|RAM[3006|\n|    257 |      6 |    -36 |\n"
+        );
+    }
+}
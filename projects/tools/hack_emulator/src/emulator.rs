@@ -0,0 +1,325 @@
+use std::collections::BTreeSet;
+
+use hack_assembler::symbol_table::{HackMemSize, HackRomSize};
+
+use crate::{
+    cpu::Cpu,
+    instruction::Instruction,
+    keyboard::KeyScript,
+    memory::{MemoryError, Ram},
+    profiler::Profile,
+    rom::Rom,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum EmulatorError {
+    #[error("memory error at pc {0}: {1}")]
+    Memory(HackRomSize, MemoryError),
+    #[error("pc {0} is outside of ROM")]
+    InvalidPc(HackRomSize),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RunOutcome {
+    Halted,
+    CycleLimit,
+    Breakpoint(HackRomSize),
+    Watchpoint(HackMemSize, u16, u16),
+}
+
+pub struct Emulator {
+    cpu: Cpu,
+    rom: Rom,
+    ram: Ram,
+    cycles: u64,
+    breakpoints: BTreeSet<HackRomSize>,
+    watchpoints: BTreeSet<HackMemSize>,
+    key_script: KeyScript,
+    profile: Option<Profile>,
+}
+
+impl Emulator {
+    pub fn new(rom: Rom) -> Self {
+        Self {
+            cpu: Cpu::new(),
+            rom,
+            ram: Ram::new(),
+            cycles: 0,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            key_script: KeyScript::default(),
+            profile: None,
+        }
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn rom(&self) -> &Rom {
+        &self.rom
+    }
+
+    pub fn ram(&self) -> &Ram {
+        &self.ram
+    }
+
+    pub fn ram_mut(&mut self) -> &mut Ram {
+        &mut self.ram
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn add_breakpoint(&mut self, addr: HackRomSize) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: HackRomSize) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = HackRomSize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn add_watchpoint(&mut self, addr: HackMemSize) -> Result<(), MemoryError> {
+        self.ram.read(addr)?;
+        self.watchpoints.insert(addr);
+        Ok(())
+    }
+
+    pub fn remove_watchpoint(&mut self, addr: HackMemSize) -> bool {
+        self.watchpoints.remove(&addr)
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = HackMemSize> + '_ {
+        self.watchpoints.iter().copied()
+    }
+
+    ///Replaces the keyboard script, whose events are relative to the cycle count of the emulator
+    pub fn set_key_script(&mut self, key_script: KeyScript) {
+        self.key_script = key_script;
+    }
+
+    ///Starts counting the executions of every ROM address from now on
+    pub fn enable_profiling(&mut self) {
+        self.profile = Some(Profile::new(self.rom.len()));
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    ///Sets the PC back to 0, leaving the registers and memory as they are, like the reset pin
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+    }
+
+    pub fn step(&mut self) -> Result<(), EmulatorError> {
+        if let Some(key) = self.key_script.take_due(self.cycles) {
+            self.ram.set_keyboard(key.0);
+        }
+        let pc = self.cpu.pc();
+        let instruction = self.fetch(pc)?;
+        self.cpu
+            .execute(instruction, &mut self.ram)
+            .map_err(|err| EmulatorError::Memory(pc, err))?;
+        if let Some(profile) = &mut self.profile {
+            profile.record(pc, self.cpu.pc());
+        }
+        self.cycles += 1;
+        Ok(())
+    }
+
+    ///Runs until the program reaches a halt loop, a breakpoint, changes a watched RAM cell or
+    ///`max_cycles` more cycles have been executed. The instruction at the current PC always runs,
+    ///so that a run can resume from the breakpoint it last stopped at
+    pub fn run(&mut self, max_cycles: u64) -> Result<RunOutcome, EmulatorError> {
+        for cycle in 0..max_cycles {
+            if self.is_halted() {
+                return Ok(RunOutcome::Halted);
+            }
+            let pc = self.cpu.pc();
+            if cycle > 0 && self.breakpoints.contains(&pc) {
+                return Ok(RunOutcome::Breakpoint(pc));
+            }
+            let watched = self.watched_values();
+            self.step()?;
+            if let Some((addr, old, new)) = self.changed_watchpoint(watched) {
+                return Ok(RunOutcome::Watchpoint(addr, old, new));
+            }
+        }
+        if self.is_halted() {
+            Ok(RunOutcome::Halted)
+        } else {
+            Ok(RunOutcome::CycleLimit)
+        }
+    }
+
+    ///Whether the CPU is stuck in the canonical `(END) @END 0;JMP` loop, or in a jump to itself
+    pub fn is_halted(&self) -> bool {
+        let pc = self.cpu.pc();
+        match self.rom.fetch(pc) {
+            Some(Instruction::C(cinstr)) => {
+                self.cpu.a() == pc && is_halt_jmp(Some(Instruction::C(cinstr)))
+            }
+            Some(Instruction::A(addr)) => {
+                addr == pc && is_halt_jmp(self.rom.fetch(pc.wrapping_add(1)))
+            }
+            None => false,
+        }
+    }
+
+    fn watched_values(&self) -> Vec<(HackMemSize, u16)> {
+        self.watchpoints
+            .iter()
+            .filter_map(|addr| self.ram.read(*addr).ok().map(|value| (*addr, value)))
+            .collect()
+    }
+
+    fn changed_watchpoint(
+        &self,
+        watched: Vec<(HackMemSize, u16)>,
+    ) -> Option<(HackMemSize, u16, u16)> {
+        watched
+            .into_iter()
+            .find_map(|(addr, old)| match self.ram.read(addr) {
+                Ok(new) if new != old => Some((addr, old, new)),
+                _ => None,
+            })
+    }
+
+    fn fetch(&self, pc: HackRomSize) -> Result<Instruction, EmulatorError> {
+        self.rom.fetch(pc).ok_or(EmulatorError::InvalidPc(pc))
+    }
+}
+
+fn is_halt_jmp(instruction: Option<Instruction>) -> bool {
+    matches!(instruction, Some(Instruction::C(cinstr)) if cinstr.is_unconditional_jmp() && cinstr.dest() == 0)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs::File, io::BufReader};
+
+    use super::*;
+
+    fn load(path: &str) -> Emulator {
+        let mut reader = BufReader::new(File::open(path).unwrap());
+        Emulator::new(Rom::from_hack(&mut reader).unwrap())
+    }
+
+    fn cmp_rows() -> Vec<Vec<i32>> {
+        std::fs::read_to_string("./test_files/ComputerMax.cmp")
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|row| {
+                row.split('|')
+                    .map(str::trim)
+                    .filter(|col| !col.is_empty())
+                    .map(|col| col.parse().unwrap())
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn assert_row(emulator: &Emulator, row: &[i32]) {
+        let cpu = emulator.cpu();
+        let ram = emulator.ram();
+        assert_eq!(
+            [
+                cpu.a() as i16 as i32,
+                cpu.d() as i16 as i32,
+                cpu.pc() as i32,
+                ram.read(0).unwrap() as i16 as i32,
+                ram.read(1).unwrap() as i16 as i32,
+                ram.read(2).unwrap() as i16 as i32,
+            ],
+            row[2..],
+            "at time {}",
+            row[0]
+        );
+    }
+
+    #[test]
+    fn it_matches_the_reference_computer_cycle_by_cycle() {
+        let mut emulator = load("./test_files/Max.hack");
+        let rows = cmp_rows();
+        emulator.ram_mut().write(0, 3).unwrap();
+        emulator.ram_mut().write(1, 5).unwrap();
+        for row in &rows[..15] {
+            assert_row(&emulator, row);
+            emulator.step().unwrap();
+        }
+        emulator.reset();
+        emulator.ram_mut().write(0, 23456).unwrap();
+        emulator.ram_mut().write(1, 12345).unwrap();
+        for row in &rows[16..] {
+            assert_row(&emulator, row);
+            emulator.step().unwrap();
+        }
+    }
+
+    #[test]
+    fn it_stops_at_the_halt_loop() {
+        let mut emulator = load("./test_files/Max.hack");
+        emulator.ram_mut().write(0, 3).unwrap();
+        emulator.ram_mut().write(1, 5).unwrap();
+        assert_eq!(emulator.run(1000).unwrap(), RunOutcome::Halted);
+        assert_eq!(emulator.cpu().pc(), 14);
+        assert_eq!(emulator.ram().read(2), Ok(5));
+    }
+
+    #[test]
+    fn it_stops_at_the_cycle_limit() {
+        let mut emulator = load("./test_files/Max.hack");
+        assert_eq!(emulator.run(3).unwrap(), RunOutcome::CycleLimit);
+        assert_eq!(emulator.cycles(), 3);
+    }
+
+    #[test]
+    fn it_stops_at_breakpoints_and_resumes_from_them() {
+        let mut emulator = load("./test_files/Max.hack");
+        emulator.ram_mut().write(0, 3).unwrap();
+        emulator.ram_mut().write(1, 5).unwrap();
+        emulator.add_breakpoint(12);
+        assert_eq!(emulator.run(1000).unwrap(), RunOutcome::Breakpoint(12));
+        assert_eq!(emulator.cycles(), 10);
+        assert_eq!(emulator.run(1000).unwrap(), RunOutcome::Halted);
+        assert!(emulator.remove_breakpoint(12));
+    }
+
+    #[test]
+    fn it_stops_when_a_watched_cell_changes() {
+        let mut emulator = load("./test_files/Max.hack");
+        emulator.ram_mut().write(0, 3).unwrap();
+        emulator.ram_mut().write(1, 5).unwrap();
+        emulator.add_watchpoint(2).unwrap();
+        assert_eq!(emulator.run(1000).unwrap(), RunOutcome::Watchpoint(2, 0, 5));
+        assert_eq!(emulator.cpu().pc(), 14);
+        assert!(emulator.add_watchpoint(0x7000).is_err());
+    }
+
+    #[test]
+    fn it_detects_a_jump_to_itself() {
+        //@0, 0;JMP
+        let rom = Rom::new(vec![0, 0b1110_1010_1000_0111]).unwrap();
+        let mut emulator = Emulator::new(rom);
+        assert_eq!(emulator.run(10).unwrap(), RunOutcome::Halted);
+        assert_eq!(emulator.cycles(), 0);
+    }
+
+    #[test]
+    fn it_reports_invalid_memory_access() {
+        //@32767, D=M
+        let rom = Rom::new(vec![0x7fff, 0b1111_1100_0001_0000]).unwrap();
+        let mut emulator = Emulator::new(rom);
+        assert!(matches!(
+            emulator.run(10),
+            Err(EmulatorError::Memory(1, MemoryError::OutOfBounds(0x7fff)))
+        ));
+    }
+}
@@ -0,0 +1,200 @@
+use std::fmt::Display;
+
+use hack_assembler::symbol_table::{
+    HackInstSize, HackMemSize, A_BIT, C1, C2, C3, C4, C5, C6, DEST_A, DEST_D, DEST_M, JEQ, JGT,
+    JLT, START_CMP_INSTR,
+};
+
+const C_INSTR_BIT: HackInstSize = 0b1 << 15;
+const COMP_MASK: HackInstSize = A_BIT | C1 | C2 | C3 | C4 | C5 | C6;
+const DEST_MASK: HackInstSize = DEST_A | DEST_D | DEST_M;
+const JMP_MASK: HackInstSize = JLT | JEQ | JGT;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Instruction {
+    A(HackMemSize),
+    C(CInstruction),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct CInstruction {
+    bits: HackInstSize,
+}
+
+impl From<HackInstSize> for Instruction {
+    fn from(bits: HackInstSize) -> Self {
+        if bits & C_INSTR_BIT == 0 {
+            Instruction::A(bits)
+        } else {
+            Instruction::C(CInstruction { bits })
+        }
+    }
+}
+
+impl From<Instruction> for HackInstSize {
+    fn from(instruction: Instruction) -> Self {
+        match instruction {
+            Instruction::A(value) => value,
+            Instruction::C(cinstr) => cinstr.bits,
+        }
+    }
+}
+
+impl CInstruction {
+    pub fn new(comp: HackInstSize, dest: HackInstSize, jump: HackInstSize) -> Self {
+        Self {
+            bits: START_CMP_INSTR | (comp & COMP_MASK) | (dest & DEST_MASK) | (jump & JMP_MASK),
+        }
+    }
+
+    pub fn comp(&self) -> HackInstSize {
+        self.bits & COMP_MASK
+    }
+
+    pub fn dest(&self) -> HackInstSize {
+        self.bits & DEST_MASK
+    }
+
+    pub fn jump(&self) -> HackInstSize {
+        self.bits & JMP_MASK
+    }
+
+    pub fn reads_mem(&self) -> bool {
+        self.bits & A_BIT != 0
+    }
+
+    pub fn writes_mem(&self) -> bool {
+        self.bits & DEST_M != 0
+    }
+
+    pub fn writes_a_reg(&self) -> bool {
+        self.bits & DEST_A != 0
+    }
+
+    pub fn writes_d_reg(&self) -> bool {
+        self.bits & DEST_D != 0
+    }
+
+    pub fn is_unconditional_jmp(&self) -> bool {
+        self.jump() == JMP_MASK
+    }
+
+    ///Runs the ALU with the control bits c1..c6 of the instruction, x being the D register and
+    ///y being either A or M depending on the a-bit
+    pub fn compute(&self, x: u16, y: u16) -> u16 {
+        let x = if self.bits & C1 != 0 { 0 } else { x };
+        let x = if self.bits & C2 != 0 { !x } else { x };
+        let y = if self.bits & C3 != 0 { 0 } else { y };
+        let y = if self.bits & C4 != 0 { !y } else { y };
+        let out = if self.bits & C5 != 0 {
+            x.wrapping_add(y)
+        } else {
+            x & y
+        };
+        if self.bits & C6 != 0 {
+            !out
+        } else {
+            out
+        }
+    }
+
+    pub fn should_jump(&self, out: u16) -> bool {
+        let out = out as i16;
+        (self.bits & JLT != 0 && out < 0)
+            || (self.bits & JEQ != 0 && out == 0)
+            || (self.bits & JGT != 0 && out > 0)
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:016b}", HackInstSize::from(*self))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hack_assembler::symbol_table::{COMP_INSTR, JMP_INSTR};
+    use test_case::test_case;
+
+    use super::*;
+
+    fn comp_bits(mnemonic: &str) -> HackInstSize {
+        COMP_INSTR
+            .iter()
+            .find(|(m, _)| *m == mnemonic)
+            .map(|(_, bits)| *bits)
+            .unwrap()
+    }
+
+    fn jmp_bits(mnemonic: &str) -> HackInstSize {
+        JMP_INSTR
+            .iter()
+            .find(|(m, _)| *m == mnemonic)
+            .map(|(_, bits)| *bits)
+            .unwrap()
+    }
+
+    #[test]
+    fn it_decodes_a_instructions() {
+        assert_eq!(
+            Instruction::from(0b0111_1111_1111_1111),
+            Instruction::A(32767)
+        );
+        assert_eq!(Instruction::from(0), Instruction::A(0));
+    }
+
+    #[test]
+    fn it_decodes_c_instructions() {
+        let instr = Instruction::from(0b1111_1100_0001_0000);
+        assert_matches::assert_matches!(instr, Instruction::C(c) => {
+            assert_eq!(c.comp(), comp_bits("M"));
+            assert!(c.reads_mem());
+            assert!(c.writes_d_reg());
+            assert!(!c.writes_a_reg());
+            assert!(!c.writes_mem());
+            assert_eq!(c.jump(), 0);
+        });
+    }
+
+    #[test_case("0", 0; "zero")]
+    #[test_case("1", 1; "one")]
+    #[test_case("-1", -1; "minus one")]
+    #[test_case("D", 7; "d")]
+    #[test_case("A", 3; "a")]
+    #[test_case("!D", !7; "not d")]
+    #[test_case("!A", !3; "not a")]
+    #[test_case("-D", -7; "neg d")]
+    #[test_case("-A", -3; "neg a")]
+    #[test_case("D+1", 8; "d plus one")]
+    #[test_case("A+1", 4; "a plus one")]
+    #[test_case("D-1", 6; "d minus one")]
+    #[test_case("A-1", 2; "a minus one")]
+    #[test_case("D+A", 10; "d plus a")]
+    #[test_case("D-A", 4; "d minus a")]
+    #[test_case("A-D", -4; "a minus d")]
+    #[test_case("D&A", 3; "d and a")]
+    #[test_case("D|A", 7; "d or a")]
+    #[test_case("M", 3; "m")]
+    #[test_case("D-M", 4; "d minus m")]
+    fn it_computes_every_comp_mnemonic(mnemonic: &str, expected: i16) {
+        let instr = CInstruction::new(comp_bits(mnemonic), 0, 0);
+        assert_eq!(instr.compute(7, 3) as i16, expected);
+    }
+
+    #[test_case("JGT", &[false, false, true]; "jgt")]
+    #[test_case("JEQ", &[false, true, false]; "jeq")]
+    #[test_case("JGE", &[false, true, true]; "jge")]
+    #[test_case("JLT", &[true, false, false]; "jlt")]
+    #[test_case("JNE", &[true, false, true]; "jne")]
+    #[test_case("JLE", &[true, true, false]; "jle")]
+    #[test_case("JMP", &[true, true, true]; "jmp")]
+    fn it_evaluates_jump_conditions(mnemonic: &str, expected: &[bool]) {
+        let instr = CInstruction::new(0, 0, jmp_bits(mnemonic));
+        let actual: Vec<_> = [-1_i16, 0, 1]
+            .iter()
+            .map(|out| instr.should_jump(*out as u16))
+            .collect();
+        assert_eq!(actual, expected);
+    }
+}
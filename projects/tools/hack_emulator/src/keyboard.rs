@@ -0,0 +1,216 @@
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead, BufReader, Read},
+    str::FromStr,
+};
+
+const NAMED_KEYS: [(&str, u16); 15] = [
+    ("NONE", 0),
+    ("SPACE", 32),
+    ("NEWLINE", 128),
+    ("ENTER", 128),
+    ("BACKSPACE", 129),
+    ("LEFT", 130),
+    ("UP", 131),
+    ("RIGHT", 132),
+    ("DOWN", 133),
+    ("HOME", 134),
+    ("END", 135),
+    ("PAGEUP", 136),
+    ("PAGEDOWN", 137),
+    ("INSERT", 138),
+    ("DELETE", 139),
+];
+const ESC_KEY: u16 = 140;
+const F1_KEY: u16 = 141;
+const FUNCTION_KEYS: u16 = 12;
+
+#[derive(thiserror::Error, Debug)]
+pub enum KeyScriptError {
+    #[error("i/o error: {0}")]
+    Io(#[from] io::Error),
+    #[error("line {0}: expected '<cycle> <key>', found '{1}'")]
+    InvalidLine(usize, String),
+    #[error("line {0}: unknown key '{1}'")]
+    UnknownKey(usize, String),
+}
+
+///A key code in the Hack character set
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Key(pub u16);
+
+impl FromStr for Key {
+    type Err = ();
+
+    ///Parses a key code (`65`), a single character (`a`), a quoted character (`'5'`) or one of
+    ///the named keys such as `LEFT`, `ESC`, `F1` and `NONE` for no key pressed
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next(), chars.next(), chars.next()) {
+            (Some(c), None, _, _) if !c.is_ascii_digit() => char_key(c),
+            (Some('\''), Some(c), Some('\''), None) => char_key(c),
+            _ => {
+                if let Ok(code) = s.parse::<u16>() {
+                    return Ok(Key(code));
+                }
+                let name = s.to_uppercase();
+                if let Some((_, code)) = NAMED_KEYS.iter().find(|(n, _)| *n == name) {
+                    return Ok(Key(*code));
+                }
+                match name.as_str() {
+                    "ESC" | "ESCAPE" => Ok(Key(ESC_KEY)),
+                    fkey => fkey
+                        .strip_prefix('F')
+                        .and_then(|n| n.parse::<u16>().ok())
+                        .filter(|n| (1..=FUNCTION_KEYS).contains(n))
+                        .map(|n| Key(F1_KEY + n - 1))
+                        .ok_or(()),
+                }
+            }
+        }
+    }
+}
+
+fn char_key(c: char) -> Result<Key, ()> {
+    if c.is_ascii() && !c.is_ascii_control() {
+        Ok(Key(c as u16))
+    } else {
+        Err(())
+    }
+}
+
+///Timed key presses for the memory mapped keyboard. Each event holds its key down from the given
+///cycle until the next event, use `NONE` to release it
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct KeyScript {
+    events: VecDeque<(u64, Key)>,
+}
+
+impl KeyScript {
+    pub fn new<I: IntoIterator<Item = (u64, Key)>>(events: I) -> Self {
+        let mut events: Vec<_> = events.into_iter().collect();
+        events.sort_by_key(|(cycle, _)| *cycle);
+        Self {
+            events: events.into(),
+        }
+    }
+
+    ///Reads a script of `<cycle> <key>` lines, `//` starts a comment
+    pub fn parse<R: Read>(source: &mut BufReader<R>) -> Result<Self, KeyScriptError> {
+        let mut events = Vec::new();
+        for (line_no, line) in source.lines().enumerate() {
+            let line = line?;
+            let content = line[..line.find("//").unwrap_or(line.len())].trim();
+            if content.is_empty() {
+                continue;
+            }
+            let (cycle, key) = content
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| KeyScriptError::InvalidLine(line_no + 1, line.clone()))?;
+            let cycle = cycle
+                .parse()
+                .map_err(|_| KeyScriptError::InvalidLine(line_no + 1, line.clone()))?;
+            let key = key.trim();
+            let key = key
+                .parse()
+                .map_err(|_| KeyScriptError::UnknownKey(line_no + 1, key.to_owned()))?;
+            events.push((cycle, key));
+        }
+        Ok(KeyScript::new(events))
+    }
+
+    ///Removes and returns the last key due by `cycle`, if any event became due
+    pub(crate) fn take_due(&mut self, cycle: u64) -> Option<Key> {
+        let mut key = None;
+        while let Some((_, due)) = self.events.front().filter(|(at, _)| *at <= cycle) {
+            key = Some(*due);
+            self.events.pop_front();
+        }
+        key
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs::File,
+        io::{BufWriter, Cursor},
+    };
+
+    use assert_matches::assert_matches;
+    use test_case::test_case;
+
+    use super::*;
+    use crate::{emulator::Emulator, rom::Rom, screen::Screen};
+
+    #[test_case("65", 65; "key code")]
+    #[test_case("a", 97; "lower case character")]
+    #[test_case("A", 65; "upper case character")]
+    #[test_case("'5'", 53; "quoted digit")]
+    #[test_case("' '", 32; "quoted space")]
+    #[test_case("left", 130; "left arrow")]
+    #[test_case("UP", 131; "up arrow")]
+    #[test_case("RIGHT", 132; "right arrow")]
+    #[test_case("DOWN", 133; "down arrow")]
+    #[test_case("ENTER", 128; "enter")]
+    #[test_case("ESC", 140; "escape")]
+    #[test_case("F1", 141; "first function key")]
+    #[test_case("F12", 152; "last function key")]
+    #[test_case("NONE", 0; "no key")]
+    fn it_parses_keys(key: &str, code: u16) {
+        assert_eq!(key.parse(), Ok(Key(code)));
+    }
+
+    #[test_case("F13"; "function key out of range")]
+    #[test_case("SHIFT"; "unknown name")]
+    #[test_case("'ab'"; "quoted string")]
+    fn it_rejects_unknown_keys(key: &str) {
+        assert!(key.parse::<Key>().is_err());
+    }
+
+    #[test]
+    fn it_parses_scripts_in_cycle_order() {
+        let source = "// fill then clear\n100 NONE\n0 a  // press\n\n";
+        let script = KeyScript::parse(&mut BufReader::new(Cursor::new(source))).unwrap();
+        assert_eq!(script, KeyScript::new([(0, Key(97)), (100, Key(0))]));
+    }
+
+    #[test]
+    fn it_reports_the_line_of_bad_events() {
+        let source = "0 a\n10\n";
+        assert_matches!(
+            KeyScript::parse(&mut BufReader::new(Cursor::new(source))),
+            Err(KeyScriptError::InvalidLine(2, _))
+        );
+        let source = "0 SHIFT\n";
+        assert_matches!(
+            KeyScript::parse(&mut BufReader::new(Cursor::new(source))),
+            Err(KeyScriptError::UnknownKey(1, _))
+        );
+    }
+
+    #[test]
+    fn it_drives_fill_deterministically() {
+        let mut reader = BufReader::new(File::open("../../04/fill/Fill.asm").unwrap());
+        let mut writer = BufWriter::new(Vec::new());
+        hack_assembler::parse(&mut reader, &mut writer).unwrap();
+        let hack = writer.into_inner().unwrap();
+        let mut emulator =
+            Emulator::new(Rom::from_hack(&mut BufReader::new(hack.as_slice())).unwrap());
+        emulator.set_key_script(KeyScript::new([(0, Key(97)), (300_000, Key(0))]));
+
+        emulator.run(300_000).unwrap();
+        let screen = Screen::capture(emulator.ram());
+        assert_eq!(emulator.ram().keyboard(), 97);
+        assert!((0..256).all(|y| (0..512).all(|x| screen.pixel(x, y))));
+
+        emulator.run(300_000).unwrap();
+        let screen = Screen::capture(emulator.ram());
+        assert_eq!(emulator.ram().keyboard(), 0);
+        assert!((0..256).all(|y| (0..512).all(|x| !screen.pixel(x, y))));
+    }
+}
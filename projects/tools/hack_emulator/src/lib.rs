@@ -0,0 +1,28 @@
+mod cpu;
+mod debugger;
+mod disassembler;
+mod dump;
+mod emulator;
+mod instruction;
+mod keyboard;
+mod memory;
+mod profiler;
+mod program;
+mod rom;
+mod screen;
+mod symbols;
+
+pub use cpu::Cpu;
+pub use debugger::Debugger;
+pub use disassembler::disassemble;
+pub use dump::{RamDump, RamRange, RamRangeError};
+pub use emulator::{Emulator, EmulatorError, RunOutcome};
+pub use instruction::{CInstruction, Instruction};
+pub use keyboard::{Key, KeyScript, KeyScriptError};
+pub use memory::{MemoryError, Ram, RAM_SIZE, SCREEN_SIZE};
+pub use profiler::{LoopStats, Profile, ProfileReport, RegionStats, Regions};
+pub use program::{load_program, LoadError};
+pub use rom::{Rom, RomError, ROM_SIZE};
+pub use screen::{ImageFormat, Screen, ScreenError, SCREEN_HEIGHT, SCREEN_WIDTH};
+pub use source_map::{SourceMap, SourceMapError, SourceRange};
+pub use symbols::Symbols;
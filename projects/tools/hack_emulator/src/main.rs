@@ -0,0 +1,133 @@
+use clap::Parser;
+use hack_emulator::{
+    load_program, Debugger, Emulator, KeyScript, RamDump, RamRange, Regions, RunOutcome, Screen,
+    SourceMap,
+};
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::{self, BufReader},
+    path::PathBuf,
+    str::FromStr,
+};
+
+///An emulator for the Hack computer from the nand-to-tetris course
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about=None)]
+struct Args {
+    #[clap(name = "program (.hack or .asm)")]
+    program: PathBuf,
+    ///Maximum number of cycles to run for, unless the program halts first
+    #[clap(short, long, default_value_t = 1_000_000)]
+    cycles: u64,
+    ///Sets a RAM cell before the program starts, e.g. 0=256
+    #[clap(short, long)]
+    set: Vec<RamValue>,
+    ///RAM cells to dump when the program stops, e.g. 256 or 256..260
+    #[clap(short, long)]
+    dump: Vec<RamRange>,
+    ///Stops the program when the PC reaches this ROM address or label
+    #[clap(short, long = "break")]
+    breakpoints: Vec<String>,
+    ///Key script of `<cycle> <key>` lines to drive the keyboard with, e.g. `1000 LEFT`
+    #[clap(short, long)]
+    keys: Option<PathBuf>,
+    ///Saves the screen to a .pbm or .png image when the program stops
+    #[clap(long)]
+    screenshot: Option<PathBuf>,
+    ///Starts an interactive debugger instead of running the program, type help for commands
+    #[clap(long)]
+    debug: bool,
+    ///Writes an execution profile to this file when the program stops, `-` for stdout
+    #[clap(long)]
+    profile: Option<PathBuf>,
+    ///JSON map of ROM ranges to VM functions, used to profile per function rather than per label
+    #[clap(long)]
+    source_map: Option<PathBuf>,
+    ///Number of regions, loops and instructions listed in the profile
+    #[clap(long, default_value_t = 10)]
+    top: usize,
+}
+
+#[derive(Debug)]
+struct RamValue(u16, i16);
+
+impl FromStr for RamValue {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid RAM value '{}', expected address=value", s);
+        let (addr, value) = s.split_once('=').ok_or_else(invalid)?;
+        Ok(RamValue(
+            addr.trim().parse().map_err(|_| invalid())?,
+            value.trim().parse().map_err(|_| invalid())?,
+        ))
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    let (rom, symbols) = load_program(&args.program)?;
+    let mut emulator = Emulator::new(rom);
+    for RamValue(addr, value) in args.set {
+        emulator.ram_mut().write(addr, value as u16)?;
+    }
+    if let Some(path) = args.keys {
+        let mut reader = BufReader::new(File::open(path)?);
+        emulator.set_key_script(KeyScript::parse(&mut reader)?);
+    }
+    for breakpoint in args.breakpoints {
+        let addr = breakpoint
+            .parse()
+            .ok()
+            .or_else(|| symbols.label_addr(&breakpoint))
+            .ok_or_else(|| format!("unknown breakpoint label '{}'", breakpoint))?;
+        emulator.add_breakpoint(addr);
+    }
+    if args.debug {
+        let mut debugger = Debugger::new(emulator, symbols, args.cycles);
+        debugger.repl(io::stdin().lock(), &mut io::stdout())?;
+        return Ok(());
+    }
+    if args.profile.is_some() {
+        emulator.enable_profiling();
+    }
+    let outcome = emulator.run(args.cycles)?;
+    eprintln!(
+        "{} after {} cycles",
+        match outcome {
+            RunOutcome::Halted => "halted".to_owned(),
+            RunOutcome::CycleLimit => "stopped".to_owned(),
+            RunOutcome::Breakpoint(addr) => format!("hit breakpoint at {}", addr),
+            RunOutcome::Watchpoint(addr, old, new) => format!(
+                "RAM[{}] changed from {} to {}",
+                addr, old as i16, new as i16
+            ),
+        },
+        emulator.cycles()
+    );
+    if let Some(path) = args.screenshot {
+        Screen::capture(emulator.ram()).save(&path)?;
+    }
+    if !args.dump.is_empty() {
+        let addrs = args.dump.into_iter().flatten();
+        print!("{}", RamDump::new(emulator.ram(), addrs)?);
+    }
+    if let (Some(path), Some(profile)) = (args.profile, emulator.profile()) {
+        let source_map = args
+            .source_map
+            .map(|path| SourceMap::load(&path))
+            .transpose()?;
+        let regions = match &source_map {
+            Some(map) => Regions::Functions(map),
+            None => Regions::Labels(&symbols),
+        };
+        let report = profile.report(emulator.rom(), &regions, args.top);
+        if path.as_os_str() == "-" {
+            print!("{}", report);
+        } else {
+            fs::write(path, report.to_string())?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,101 @@
+use hack_assembler::symbol_table::{HackMemSize, KBD_MEM, SCREEN_MEM};
+
+pub const RAM_SIZE: usize = KBD_MEM as usize + 1;
+pub const SCREEN_SIZE: usize = (KBD_MEM - SCREEN_MEM) as usize;
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum MemoryError {
+    #[error("address {0} is outside of the Hack memory map")]
+    OutOfBounds(HackMemSize),
+}
+
+///The Hack data memory: 16K of RAM followed by the memory mapped screen and keyboard
+pub struct Ram {
+    words: Vec<u16>,
+}
+
+impl Default for Ram {
+    fn default() -> Self {
+        Ram::new()
+    }
+}
+
+impl Ram {
+    pub fn new() -> Self {
+        Self {
+            words: vec![0; RAM_SIZE],
+        }
+    }
+
+    pub fn read(&self, addr: HackMemSize) -> Result<u16, MemoryError> {
+        self.words
+            .get(addr as usize)
+            .copied()
+            .ok_or(MemoryError::OutOfBounds(addr))
+    }
+
+    ///Writes a word to memory. The keyboard register is read only, so writes to it are ignored as
+    ///they would be by the hardware
+    pub fn write(&mut self, addr: HackMemSize, value: u16) -> Result<(), MemoryError> {
+        match addr {
+            KBD_MEM => Ok(()),
+            addr => self
+                .words
+                .get_mut(addr as usize)
+                .map(|word| *word = value)
+                .ok_or(MemoryError::OutOfBounds(addr)),
+        }
+    }
+
+    pub fn keyboard(&self) -> u16 {
+        self.words[KBD_MEM as usize]
+    }
+
+    pub fn set_keyboard(&mut self, key: u16) {
+        self.words[KBD_MEM as usize] = key;
+    }
+
+    pub fn screen(&self) -> &[u16] {
+        &self.words[SCREEN_MEM as usize..KBD_MEM as usize]
+    }
+
+    pub fn clear(&mut self) {
+        self.words.iter_mut().for_each(|word| *word = 0);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    #[test]
+    fn it_reads_back_written_values() {
+        let mut ram = Ram::new();
+        ram.write(256, 42).unwrap();
+        assert_eq!(ram.read(256), Ok(42));
+    }
+
+    #[test]
+    fn it_maps_the_screen_into_ram() {
+        let mut ram = Ram::new();
+        ram.write(SCREEN_MEM + 1, 0xffff).unwrap();
+        assert_eq!(ram.screen().len(), SCREEN_SIZE);
+        assert_eq!(ram.screen()[1], 0xffff);
+    }
+
+    #[test]
+    fn it_ignores_writes_to_the_keyboard() {
+        let mut ram = Ram::new();
+        ram.set_keyboard(65);
+        ram.write(KBD_MEM, 1).unwrap();
+        assert_eq!(ram.read(KBD_MEM), Ok(65));
+    }
+
+    #[test]
+    fn it_rejects_addresses_beyond_the_keyboard() {
+        let mut ram = Ram::new();
+        assert_matches!(ram.read(KBD_MEM + 1), Err(MemoryError::OutOfBounds(_)));
+        assert_matches!(ram.write(KBD_MEM + 1, 0), Err(MemoryError::OutOfBounds(_)));
+    }
+}
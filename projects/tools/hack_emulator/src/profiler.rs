@@ -0,0 +1,408 @@
+use std::{collections::BTreeMap, fmt};
+
+use hack_assembler::symbol_table::HackRomSize;
+use source_map::SourceMap;
+
+use crate::{disassembler::disassemble, instruction::Instruction, rom::Rom, symbols::Symbols};
+
+const UNLABELLED: &str = "(no label)";
+
+///Execution counts per ROM address, gathered by the emulator while profiling is enabled
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Profile {
+    counts: Vec<u64>,
+    entries: Vec<u64>,
+    back_edges: BTreeMap<(HackRomSize, HackRomSize), u64>,
+}
+
+impl Profile {
+    pub fn new(rom_len: usize) -> Self {
+        Self {
+            counts: vec![0; rom_len],
+            entries: vec![0; rom_len],
+            back_edges: BTreeMap::new(),
+        }
+    }
+
+    ///Records the instruction at `pc` running, followed by `next_pc`. Anything but `pc + 1` is
+    ///a jump, and a jump back is the end of a loop iteration
+    pub(crate) fn record(&mut self, pc: HackRomSize, next_pc: HackRomSize) {
+        if let Some(count) = self.counts.get_mut(pc as usize) {
+            *count += 1;
+        }
+        if next_pc != pc.wrapping_add(1) {
+            if let Some(entries) = self.entries.get_mut(next_pc as usize) {
+                *entries += 1;
+            }
+            if next_pc <= pc {
+                *self.back_edges.entry((pc, next_pc)).or_default() += 1;
+            }
+        }
+    }
+
+    ///How many times the instruction at `addr` ran
+    pub fn count(&self, addr: HackRomSize) -> u64 {
+        self.counts.get(addr as usize).copied().unwrap_or_default()
+    }
+
+    ///How many jumps arrived at `addr`, which are the calls when it starts a function
+    pub fn entries(&self, addr: HackRomSize) -> u64 {
+        self.entries.get(addr as usize).copied().unwrap_or_default()
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    ///Aggregates the counts per region, listing the `top` hottest regions, loops and instructions
+    pub fn report(&self, rom: &Rom, regions: &Regions, top: usize) -> ProfileReport {
+        let mut stats: BTreeMap<&str, RegionStats> = BTreeMap::new();
+        for (addr, count) in self.counts.iter().enumerate() {
+            let addr = addr as HackRomSize;
+            let (start, name) = regions.region_at(addr).unwrap_or((0, UNLABELLED));
+            let region = stats.entry(name).or_insert_with(|| RegionStats {
+                name: name.to_owned(),
+                cycles: 0,
+                entries: self.entries(start),
+            });
+            region.cycles += count;
+        }
+        let mut stats: Vec<_> = stats.into_values().collect();
+        let entries = entry_distribution(&stats);
+        stats.sort_by(|a, b| b.cycles.cmp(&a.cycles).then(a.name.cmp(&b.name)));
+        stats.truncate(top);
+
+        let mut loops: Vec<_> = self
+            .back_edges
+            .iter()
+            .filter(|(&(end, start), _)| {
+                is_static_jump(rom, end, start) && regions.same_function(start, end)
+            })
+            .map(|(&(end, start), &iterations)| LoopStats {
+                start,
+                end,
+                iterations,
+                cycles: (start..=end).map(|addr| self.count(addr)).sum(),
+                region: regions
+                    .region_at(start)
+                    .map_or(UNLABELLED, |(_, name)| name)
+                    .to_owned(),
+            })
+            .collect();
+        loops.sort_by(|a, b| b.cycles.cmp(&a.cycles).then(a.start.cmp(&b.start)));
+        loops.truncate(top);
+
+        let mut instructions: Vec<_> = (0..self.counts.len() as HackRomSize)
+            .filter(|addr| self.count(*addr) > 0)
+            .map(|addr| (addr, self.count(addr)))
+            .collect();
+        instructions.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        instructions.truncate(top);
+        let instructions = instructions
+            .into_iter()
+            .map(|(addr, count)| {
+                let text = rom.fetch(addr).map(disassemble).unwrap_or_default();
+                (addr, count, text)
+            })
+            .collect();
+
+        ProfileReport {
+            kind: regions.kind(),
+            total_cycles: self.total_cycles(),
+            regions: stats,
+            loops,
+            instructions,
+            entries,
+        }
+    }
+}
+
+///Whether the jump at `end` goes to an address loaded by the instruction before it, rather than
+///to a return address read from memory
+fn is_static_jump(rom: &Rom, end: HackRomSize, start: HackRomSize) -> bool {
+    end > 0 && rom.fetch(end - 1) == Some(Instruction::A(start))
+}
+
+///How the profile is attributed to code, by the assembly labels or, given a source map, by the
+///VM functions that were translated
+pub enum Regions<'a> {
+    Labels(&'a Symbols),
+    Functions(&'a SourceMap),
+}
+
+impl<'a> Regions<'a> {
+    fn kind(&self) -> &'static str {
+        match self {
+            Regions::Labels(_) => "label",
+            Regions::Functions(_) => "function",
+        }
+    }
+
+    ///Whether both addresses belong to the same function, taking a label's function to be the part
+    ///before any `$`, as in the `function$label` names of translated VM code
+    fn same_function(&self, a: HackRomSize, b: HackRomSize) -> bool {
+        let function_at = |addr| {
+            self.region_at(addr)
+                .map(|(_, name)| name.split('$').next().unwrap_or(name))
+        };
+        function_at(a) == function_at(b)
+    }
+
+    ///The region `addr` belongs to, and the address calls to it arrive at
+    fn region_at(&self, addr: HackRomSize) -> Option<(HackRomSize, &'a str)> {
+        match self {
+            Regions::Labels(symbols) => symbols.enclosing_label(addr),
+            Regions::Functions(map) => map.range_at(addr).map(|range| {
+                let start = map.function_addr(&range.function).unwrap_or(range.start);
+                (start, range.function.as_str())
+            }),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct RegionStats {
+    pub name: String,
+    pub cycles: u64,
+    ///How many jumps arrived at the region's start. For a function these are its calls, but for a
+    ///label they include the loops jumping back to it
+    pub entries: u64,
+}
+
+///A jump from `end` back to `start`, taken `iterations` times
+#[derive(Debug, PartialEq, Clone)]
+pub struct LoopStats {
+    pub start: HackRomSize,
+    pub end: HackRomSize,
+    pub iterations: u64,
+    pub cycles: u64,
+    pub region: String,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ProfileReport {
+    kind: &'static str,
+    pub total_cycles: u64,
+    pub regions: Vec<RegionStats>,
+    pub loops: Vec<LoopStats>,
+    pub instructions: Vec<(HackRomSize, u64, String)>,
+    ///How many regions were entered `low..=high` times
+    pub entries: Vec<(u64, u64, usize)>,
+}
+
+impl ProfileReport {
+    ///What the entries of a region are called: calls for functions, entries for labels
+    fn entries_name(&self) -> &'static str {
+        match self.kind {
+            "function" => "calls",
+            _ => "entries",
+        }
+    }
+}
+
+///Buckets the regions by their entry counts in powers of ten: 0, 1, 2..9, 10..99 and so on
+fn entry_distribution(stats: &[RegionStats]) -> Vec<(u64, u64, usize)> {
+    let mut buckets: Vec<(u64, u64, usize)> = vec![(0, 0, 0), (1, 1, 0)];
+    let max_entries = stats.iter().map(|region| region.entries).max().unwrap_or(0);
+    let mut low = 2;
+    while low <= max_entries {
+        let high = if low == 2 { 9 } else { low * 10 - 1 };
+        buckets.push((low, high, 0));
+        low = high + 1;
+    }
+    for region in stats {
+        if let Some(bucket) = buckets
+            .iter_mut()
+            .find(|(low, high, _)| (*low..=*high).contains(&region.entries))
+        {
+            bucket.2 += 1;
+        }
+    }
+    buckets
+}
+
+impl fmt::Display for ProfileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Total cycles: {}", self.total_cycles)?;
+        let percent = |cycles: u64| match self.total_cycles {
+            0 => 0.0,
+            total => cycles as f64 * 100.0 / total as f64,
+        };
+
+        writeln!(f, "\nHot {}s:", self.kind)?;
+        writeln!(
+            f,
+            "{:>12} {:>7} {:>10}  {}",
+            "cycles",
+            "%",
+            self.entries_name(),
+            self.kind
+        )?;
+        for region in &self.regions {
+            writeln!(
+                f,
+                "{:>12} {:>6.2}% {:>10}  {}",
+                region.cycles,
+                percent(region.cycles),
+                region.entries,
+                region.name
+            )?;
+        }
+
+        writeln!(f, "\nHot loops:")?;
+        writeln!(
+            f,
+            "{:>12} {:>7} {:>10}  {:<13}  {}",
+            "cycles", "%", "iterations", "addresses", self.kind
+        )?;
+        for hot_loop in &self.loops {
+            writeln!(
+                f,
+                "{:>12} {:>6.2}% {:>10}  {:<13}  {}",
+                hot_loop.cycles,
+                percent(hot_loop.cycles),
+                hot_loop.iterations,
+                format!("{}..={}", hot_loop.start, hot_loop.end),
+                hot_loop.region
+            )?;
+        }
+
+        writeln!(f, "\nHot instructions:")?;
+        writeln!(
+            f,
+            "{:>12} {:>7} {:>10}  instruction",
+            "cycles", "%", "address"
+        )?;
+        for (addr, count, text) in &self.instructions {
+            writeln!(
+                f,
+                "{:>12} {:>6.2}% {:>10}  {}",
+                count,
+                percent(*count),
+                addr,
+                text
+            )?;
+        }
+
+        match self.kind {
+            "function" => writeln!(f, "\nCall counts:")?,
+            _ => writeln!(f, "\nEntry counts:")?,
+        }
+        writeln!(
+            f,
+            "{:>12} {:>7}",
+            self.entries_name(),
+            format!("{}s", self.kind)
+        )?;
+        for (low, high, regions) in &self.entries {
+            let entries = if low == high {
+                low.to_string()
+            } else {
+                format!("{}..={}", low, high)
+            };
+            writeln!(f, "{:>12} {:>7}", entries, regions)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use source_map::SourceRange;
+
+    use super::*;
+    use crate::{emulator::Emulator, program::load_program};
+
+    fn profile_rect() -> (Emulator, Symbols) {
+        let (rom, symbols) = load_program(Path::new("./test_files/Rect.asm")).unwrap();
+        let mut emulator = Emulator::new(rom);
+        emulator.enable_profiling();
+        emulator.ram_mut().write(0, 4).unwrap();
+        emulator.run(1000).unwrap();
+        (emulator, symbols)
+    }
+
+    #[test]
+    fn it_counts_executions_per_address() {
+        let (emulator, _) = profile_rect();
+        let profile = emulator.profile().unwrap();
+        assert_eq!(profile.total_cycles(), emulator.cycles());
+        assert_eq!(profile.count(0), 1);
+        assert_eq!(profile.count(10), 4);
+        assert_eq!(profile.entries(10), 3);
+        assert_eq!(profile.count(23), 0);
+    }
+
+    #[test]
+    fn it_reports_per_label() {
+        let (emulator, symbols) = profile_rect();
+        let report =
+            emulator
+                .profile()
+                .unwrap()
+                .report(emulator.rom(), &Regions::Labels(&symbols), 2);
+        assert_eq!(report.total_cycles, 62);
+        assert_eq!(
+            report.regions,
+            [
+                RegionStats {
+                    name: "LOOP".to_owned(),
+                    cycles: 52,
+                    entries: 3
+                },
+                RegionStats {
+                    name: UNLABELLED.to_owned(),
+                    cycles: 10,
+                    entries: 0
+                },
+            ]
+        );
+        assert_eq!(
+            report.loops,
+            [LoopStats {
+                start: 10,
+                end: 22,
+                iterations: 3,
+                cycles: 52,
+                region: "LOOP".to_owned()
+            }]
+        );
+        assert_eq!(report.instructions[0], (10, 4, "@17".to_owned()));
+        assert_eq!(report.entries, [(0, 0, 2), (1, 1, 0), (2, 9, 1)]);
+        let text = report.to_string();
+        assert!(text.contains("cycles       %    entries  label\n"));
+        assert!(text.contains("\nEntry counts:\n"));
+    }
+
+    #[test]
+    fn it_reports_per_function_with_a_source_map() {
+        let (emulator, _) = profile_rect();
+        let map = SourceMap::new(vec![
+            SourceRange {
+                start: 0,
+                end: 10,
+                function: "Sys.init".to_owned(),
+                file: None,
+                line: None,
+            },
+            SourceRange {
+                start: 10,
+                end: 25,
+                function: "Rect.fill".to_owned(),
+                file: None,
+                line: None,
+            },
+        ]);
+        let report =
+            emulator
+                .profile()
+                .unwrap()
+                .report(emulator.rom(), &Regions::Functions(&map), 10);
+        let text = report.to_string();
+        assert!(text.contains("Hot functions:"));
+        assert!(text.contains("          52  83.87%          3  Rect.fill\n"));
+        assert!(text.contains("          52  83.87%          3  10..=22        Rect.fill\n"));
+    }
+}
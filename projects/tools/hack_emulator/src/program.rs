@@ -0,0 +1,63 @@
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    path::Path,
+};
+
+use crate::{
+    rom::{Rom, RomError},
+    symbols::Symbols,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum LoadError {
+    #[error("i/o error: {0}")]
+    Io(#[from] io::Error),
+    #[error("rom error: {0}")]
+    Rom(#[from] RomError),
+    #[error("assembler error: {0}")]
+    Assembler(#[from] hack_assembler::ParseError),
+    #[error("unsupported program {0}, expected a .hack or .asm file")]
+    UnsupportedFormat(String),
+}
+
+///Loads a `.hack` binary, or assembles a `.asm` source so that its labels and variables can be
+///used by name
+pub fn load_program(path: &Path) -> Result<(Rom, Symbols), LoadError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("hack") => Ok((Rom::from_hack(&mut reader)?, Symbols::default())),
+        Some("asm") => {
+            let assembly = hack_assembler::assemble(&mut reader)?;
+            Ok((
+                Rom::new(assembly.instructions)?,
+                Symbols::new(assembly.symbols),
+            ))
+        }
+        _ => Err(LoadError::UnsupportedFormat(path.display().to_string())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_matches::assert_matches;
+
+    use super::*;
+
+    #[test]
+    fn it_loads_binaries_and_sources_alike() {
+        let (hack, _) = load_program(Path::new("./test_files/Max.hack")).unwrap();
+        let (asm, symbols) = load_program(Path::new("./test_files/Max.asm")).unwrap();
+        assert_eq!(hack.len(), asm.len());
+        assert!((0..hack.len() as u16).all(|addr| hack.fetch(addr) == asm.fetch(addr)));
+        assert_eq!(symbols.label_addr("INFINITE_LOOP"), Some(14));
+    }
+
+    #[test]
+    fn it_rejects_other_files() {
+        assert_matches!(
+            load_program(Path::new("./test_files/ComputerMax.cmp")),
+            Err(LoadError::UnsupportedFormat(_))
+        );
+    }
+}
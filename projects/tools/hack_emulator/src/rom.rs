@@ -0,0 +1,114 @@
+use std::io::{self, BufRead, BufReader, Read};
+
+use hack_assembler::symbol_table::{HackInstSize, HackRomSize};
+
+use crate::instruction::Instruction;
+
+pub const ROM_SIZE: usize = 0x8000;
+
+#[derive(thiserror::Error, Debug)]
+pub enum RomError {
+    #[error("i/o error: {0}")]
+    Io(#[from] io::Error),
+    #[error("line {0}: '{1}' is not a 16 bit binary instruction")]
+    InvalidInstruction(usize, String),
+    #[error("program of {0} instructions does not fit in ROM")]
+    ProgramTooLarge(usize),
+}
+
+///The Hack instruction memory. Addresses past the end of the loaded program read as zero, as
+///they would from an otherwise empty ROM chip
+#[derive(Debug, Clone)]
+pub struct Rom {
+    program: Vec<HackInstSize>,
+}
+
+impl Rom {
+    pub fn new(program: Vec<HackInstSize>) -> Result<Self, RomError> {
+        if program.len() > ROM_SIZE {
+            Err(RomError::ProgramTooLarge(program.len()))
+        } else {
+            Ok(Self { program })
+        }
+    }
+
+    ///Loads a program in the textual `.hack` format, one instruction per line
+    pub fn from_hack<R: Read>(source: &mut BufReader<R>) -> Result<Self, RomError> {
+        let mut program = Vec::new();
+        for (line_no, line) in source.lines().enumerate() {
+            let line = line?;
+            let trimmed_line = line.trim();
+            if trimmed_line.is_empty() {
+                continue;
+            }
+            if trimmed_line.len() != 16 || !trimmed_line.chars().all(|c| c == '0' || c == '1') {
+                return Err(RomError::InvalidInstruction(line_no + 1, line));
+            }
+            let instruction = HackInstSize::from_str_radix(trimmed_line, 2)
+                .map_err(|_| RomError::InvalidInstruction(line_no + 1, line.clone()))?;
+            program.push(instruction);
+        }
+        Rom::new(program)
+    }
+
+    pub fn fetch(&self, addr: HackRomSize) -> Option<Instruction> {
+        match addr as usize {
+            addr if addr < self.program.len() => Some(self.program[addr].into()),
+            addr if addr < ROM_SIZE => Some(Instruction::A(0)),
+            _ => None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.program.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.program.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs::File, io::Cursor};
+
+    use assert_matches::assert_matches;
+
+    use super::*;
+
+    fn load(source: &str) -> Result<Rom, RomError> {
+        Rom::from_hack(&mut BufReader::new(Cursor::new(source.to_owned())))
+    }
+
+    #[test]
+    fn it_loads_hack_files() {
+        let mut reader = BufReader::new(File::open("./test_files/Max.hack").unwrap());
+        let rom = Rom::from_hack(&mut reader).unwrap();
+        assert_eq!(rom.len(), 16);
+        assert_eq!(rom.fetch(2), Some(Instruction::A(1)));
+    }
+
+    #[test]
+    fn it_reads_zero_past_the_end_of_the_program() {
+        let rom = load("1110101010000111\n").unwrap();
+        assert_eq!(rom.fetch(1), Some(Instruction::A(0)));
+        assert_eq!(rom.fetch(ROM_SIZE as HackRomSize), None);
+    }
+
+    #[test]
+    fn it_reports_the_line_of_invalid_instructions() {
+        assert_matches!(
+            load("0000000000000000\n\n00000000000000002\n"),
+            Err(RomError::InvalidInstruction(3, _))
+        );
+        assert_matches!(load("@12\n"), Err(RomError::InvalidInstruction(1, _)));
+    }
+
+    #[test]
+    fn it_rejects_programs_larger_than_rom() {
+        assert_matches!(
+            Rom::new(vec![0; ROM_SIZE + 1]),
+            Err(RomError::ProgramTooLarge(_))
+        );
+    }
+}
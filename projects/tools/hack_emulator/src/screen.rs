@@ -0,0 +1,180 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::memory::{Ram, SCREEN_SIZE};
+
+pub const SCREEN_WIDTH: usize = 512;
+pub const SCREEN_HEIGHT: usize = 256;
+const WORD_BITS: usize = 16;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ScreenError {
+    #[error("i/o error: {0}")]
+    Io(#[from] io::Error),
+    #[error("png encoding error: {0}")]
+    Png(#[from] png::EncodingError),
+    #[error("unsupported image format for {0}, expected .pbm or .png")]
+    UnsupportedFormat(String),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ImageFormat {
+    Pbm,
+    Png,
+}
+
+impl ImageFormat {
+    pub fn from_path(path: &Path) -> Result<Self, ScreenError> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("pbm") => Ok(ImageFormat::Pbm),
+            Some("png") => Ok(ImageFormat::Png),
+            _ => Err(ScreenError::UnsupportedFormat(path.display().to_string())),
+        }
+    }
+}
+
+///A copy of the memory mapped screen. Each row of 512 pixels is 32 consecutive words, and the
+///least significant bit of a word is its leftmost pixel
+#[derive(Debug, PartialEq, Clone)]
+pub struct Screen {
+    words: Vec<u16>,
+}
+
+impl Screen {
+    pub fn capture(ram: &Ram) -> Self {
+        Self {
+            words: ram.screen().to_vec(),
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        let word = self.words[y * SCREEN_WIDTH / WORD_BITS + x / WORD_BITS];
+        word & (1 << (x % WORD_BITS)) != 0
+    }
+
+    ///Packs each row into bytes with the leftmost pixel in the most significant bit, the layout
+    ///shared by raw PBM and 1 bit PNG images
+    fn packed_rows(&self, black: bool) -> Vec<u8> {
+        let mut bytes = vec![0_u8; SCREEN_SIZE * 2];
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                if self.pixel(x, y) == black {
+                    bytes[(y * SCREEN_WIDTH + x) / 8] |= 0x80 >> (x % 8);
+                }
+            }
+        }
+        bytes
+    }
+
+    pub fn write_pbm<W: Write>(&self, out: &mut W) -> Result<(), ScreenError> {
+        write!(out, "P4\n{} {}\n", SCREEN_WIDTH, SCREEN_HEIGHT)?;
+        out.write_all(&self.packed_rows(true))?;
+        Ok(())
+    }
+
+    pub fn write_png<W: Write>(&self, out: &mut W) -> Result<(), ScreenError> {
+        let mut encoder = png::Encoder::new(out, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::One);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.packed_rows(false))?;
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ScreenError> {
+        let format = ImageFormat::from_path(path)?;
+        let mut out = BufWriter::new(File::create(path)?);
+        match format {
+            ImageFormat::Pbm => self.write_pbm(&mut out)?,
+            ImageFormat::Png => self.write_png(&mut out)?,
+        }
+        Ok(out.flush()?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs::File, io::BufReader};
+
+    use hack_assembler::symbol_table::SCREEN_MEM;
+
+    use super::*;
+    use crate::{emulator::Emulator, rom::Rom, RunOutcome};
+
+    fn load(path: &str) -> Emulator {
+        let mut reader = BufReader::new(File::open(path).unwrap());
+        Emulator::new(Rom::from_hack(&mut reader).unwrap())
+    }
+
+    fn assemble(path: &str) -> Emulator {
+        let mut reader = BufReader::new(File::open(path).unwrap());
+        let mut writer = BufWriter::new(Vec::new());
+        hack_assembler::parse(&mut reader, &mut writer).unwrap();
+        let hack = writer.into_inner().unwrap();
+        Emulator::new(Rom::from_hack(&mut BufReader::new(hack.as_slice())).unwrap())
+    }
+
+    fn golden(path: &str) -> Vec<u8> {
+        std::fs::read(path).unwrap()
+    }
+
+    #[test]
+    fn it_maps_words_to_pixels() {
+        let mut ram = Ram::new();
+        ram.write(SCREEN_MEM, 0b10).unwrap();
+        ram.write(SCREEN_MEM + 33, 0x8000).unwrap();
+        let screen = Screen::capture(&ram);
+        assert!(!screen.pixel(0, 0));
+        assert!(screen.pixel(1, 0));
+        assert!(screen.pixel(31, 1));
+    }
+
+    #[test]
+    fn it_renders_rect_to_the_golden_pbm() {
+        let mut emulator = load("./test_files/Rect.hack");
+        emulator.ram_mut().write(0, 4).unwrap();
+        assert_eq!(emulator.run(1000).unwrap(), RunOutcome::Halted);
+        let mut pbm = Vec::new();
+        Screen::capture(emulator.ram()).write_pbm(&mut pbm).unwrap();
+        assert_eq!(pbm, golden("./test_files/Rect.pbm"));
+    }
+
+    #[test]
+    fn it_renders_pong_to_the_golden_pbm() {
+        let mut emulator = assemble("../../06/pong/Pong.asm");
+        emulator.run(20_000_000).unwrap();
+        let mut pbm = Vec::new();
+        Screen::capture(emulator.ram()).write_pbm(&mut pbm).unwrap();
+        assert_eq!(pbm, golden("./test_files/Pong.pbm"));
+    }
+
+    #[test]
+    fn it_writes_black_pixels_as_zero_in_png() {
+        let mut ram = Ram::new();
+        ram.write(SCREEN_MEM, 0b1).unwrap();
+        let mut png = Vec::new();
+        Screen::capture(&ram).write_png(&mut png).unwrap();
+        let decoder = png::Decoder::new(png.as_slice());
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut buf).unwrap();
+        assert_eq!(buf[0], 0x7f);
+        assert_eq!(buf[1], 0xff);
+    }
+
+    #[test]
+    fn it_picks_the_format_from_the_extension() {
+        assert_eq!(
+            ImageFormat::from_path(Path::new("out.pbm")).unwrap(),
+            ImageFormat::Pbm
+        );
+        assert_eq!(
+            ImageFormat::from_path(Path::new("out.png")).unwrap(),
+            ImageFormat::Png
+        );
+        assert!(ImageFormat::from_path(Path::new("out.bmp")).is_err());
+    }
+}
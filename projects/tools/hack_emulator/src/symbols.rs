@@ -0,0 +1,75 @@
+use std::collections::BTreeMap;
+
+use hack_assembler::symbol_table::{HackMemSize, HackRomSize, SymbolTable};
+
+///The labels and variables of a program, looked up either by name or by address. Programs loaded
+///from `.hack` files only know the predefined symbols such as `SP` and `SCREEN`
+#[derive(Debug, Default)]
+pub struct Symbols {
+    table: SymbolTable,
+    labels_at: BTreeMap<HackRomSize, Vec<String>>,
+}
+
+impl Symbols {
+    pub fn new(table: SymbolTable) -> Self {
+        let mut labels_at: BTreeMap<_, Vec<_>> = BTreeMap::new();
+        for (label, addr) in table.labels() {
+            labels_at.entry(addr).or_default().push(label.to_owned());
+        }
+        labels_at.values_mut().for_each(|labels| labels.sort());
+        Self { table, labels_at }
+    }
+
+    pub fn label_addr(&self, label: &str) -> Option<HackRomSize> {
+        self.table.get_line_no(label)
+    }
+
+    pub fn ram_addr(&self, name: &str) -> Option<HackMemSize> {
+        self.table.get_addr(name)
+    }
+
+    pub fn labels_at(&self, addr: HackRomSize) -> &[String] {
+        self.labels_at
+            .get(&addr)
+            .map_or(&[], |labels| labels.as_slice())
+    }
+
+    ///The closest label at or before `addr`, i.e. the block of code the address belongs to
+    pub fn enclosing_label(&self, addr: HackRomSize) -> Option<(HackRomSize, &str)> {
+        self.labels_at
+            .range(..=addr)
+            .next_back()
+            .and_then(|(addr, labels)| labels.first().map(|label| (*addr, label.as_str())))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn make_symbols() -> Symbols {
+        let mut table = SymbolTable::new();
+        table.add_label("LOOP".to_owned(), 4).unwrap();
+        table.add_label("END".to_owned(), 10).unwrap();
+        table.add_label("ALSO_END".to_owned(), 10).unwrap();
+        table.add_alias("counter".to_owned()).unwrap();
+        Symbols::new(table)
+    }
+
+    #[test]
+    fn it_looks_up_symbols_by_name() {
+        let symbols = make_symbols();
+        assert_eq!(symbols.label_addr("LOOP"), Some(4));
+        assert_eq!(symbols.ram_addr("counter"), Some(16));
+        assert_eq!(symbols.ram_addr("SP"), Some(0));
+    }
+
+    #[test]
+    fn it_looks_up_labels_by_address() {
+        let symbols = make_symbols();
+        assert_eq!(symbols.labels_at(10), ["ALSO_END", "END"]);
+        assert!(symbols.labels_at(5).is_empty());
+        assert_eq!(symbols.enclosing_label(7), Some((4, "LOOP")));
+        assert_eq!(symbols.enclosing_label(3), None);
+    }
+}
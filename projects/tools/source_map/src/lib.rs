@@ -0,0 +1,125 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Write},
+    path::Path,
+};
+
+use hack_assembler::symbol_table::HackRomSize;
+use serde::{Deserialize, Serialize};
+
+#[derive(thiserror::Error, Debug)]
+pub enum SourceMapError {
+    #[error("i/o error: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid source map: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+///The ROM addresses `start..end` that were generated for a VM function, or for a single command
+///of it when the map was made per line
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct SourceRange {
+    pub start: HackRomSize,
+    pub end: HackRomSize,
+    pub function: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+}
+
+///Maps ROM addresses back to the VM code they were translated from, kept in a JSON sidecar
+///holding a list of ranges
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct SourceMap {
+    ranges: Vec<SourceRange>,
+}
+
+impl SourceMap {
+    pub fn new(mut ranges: Vec<SourceRange>) -> Self {
+        ranges.sort_by_key(|range| range.start);
+        Self { ranges }
+    }
+
+    pub fn parse<R: Read>(source: &mut BufReader<R>) -> Result<Self, SourceMapError> {
+        Ok(SourceMap::new(serde_json::from_reader(source)?))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, SourceMapError> {
+        SourceMap::parse(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn write<W: Write>(&self, out: W) -> Result<(), SourceMapError> {
+        Ok(serde_json::to_writer_pretty(out, &self.ranges)?)
+    }
+
+    pub fn ranges(&self) -> &[SourceRange] {
+        &self.ranges
+    }
+
+    pub fn range_at(&self, addr: HackRomSize) -> Option<&SourceRange> {
+        let index = self.ranges.partition_point(|range| range.start <= addr);
+        index
+            .checked_sub(1)
+            .map(|index| &self.ranges[index])
+            .filter(|range| addr < range.end)
+    }
+
+    ///The first address of `function`, where calls to it arrive
+    pub fn function_addr(&self, function: &str) -> Option<HackRomSize> {
+        self.ranges
+            .iter()
+            .find(|range| range.function == function)
+            .map(|range| range.start)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_matches::assert_matches;
+
+    use super::*;
+
+    const MAP: &str = r#"[
+        {"start": 10, "end": 20, "function": "Main.loop"},
+        {"start": 0, "end": 10, "function": "Sys.init"},
+        {"start": 25, "end": 30, "function": "Main.loop"}
+    ]"#;
+
+    #[test]
+    fn it_finds_the_range_of_an_address() {
+        let map = SourceMap::parse(&mut BufReader::new(MAP.as_bytes())).unwrap();
+        assert_eq!(map.range_at(0).unwrap().function, "Sys.init");
+        assert_eq!(map.range_at(19).unwrap().function, "Main.loop");
+        assert_eq!(map.range_at(22), None);
+        assert_eq!(map.range_at(30), None);
+        assert_eq!(map.function_addr("Main.loop"), Some(10));
+    }
+
+    #[test]
+    fn it_reads_back_the_lines_it_writes() {
+        let map = SourceMap::new(vec![SourceRange {
+            start: 3,
+            end: 7,
+            function: "Main.main".to_owned(),
+            file: Some("Main.vm".to_owned()),
+            line: Some(12),
+        }]);
+        let mut json = Vec::new();
+        map.write(&mut json).unwrap();
+        assert_eq!(
+            SourceMap::parse(&mut BufReader::new(&json[..])).unwrap(),
+            map
+        );
+        let plain = SourceMap::parse(&mut BufReader::new(MAP.as_bytes())).unwrap();
+        assert_eq!(plain.range_at(0).unwrap().line, None);
+    }
+
+    #[test]
+    fn it_rejects_malformed_maps() {
+        assert_matches!(
+            SourceMap::parse(&mut BufReader::new(r#"[{"start": 0}]"#.as_bytes())),
+            Err(SourceMapError::Json(_))
+        );
+    }
+}
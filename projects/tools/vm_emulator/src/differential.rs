@@ -0,0 +1,396 @@
+use std::{collections::BTreeSet, fmt, io::BufReader};
+
+use hack_assembler::{assemble, ParseError as AssemblyError};
+use hack_emulator::{
+    Emulator, EmulatorError, MemoryError, Ram, Rom, RomError, RunOutcome as HackOutcome,
+};
+use vm_translator::{
+    parser::{Command, ParseError, Parser},
+    translator::{translate_in_memory, Bootstrap, CodegenOptions, TranslatorError},
+};
+
+use crate::{
+    os::OsMode,
+    program::{Program, ProgramError, LCL, SP},
+    vm::{Vm, VmError},
+};
+
+const STACK_BASE: u16 = 256;
+const STACK_END: u16 = 2048;
+const SCRATCH_REGISTERS: [u16; 3] = [13, 14, 15];
+const STATIC_BASE: u16 = 16;
+///Hack instructions allowed per VM command before the translated program counts as not halting
+const CYCLES_PER_STEP: u64 = 1000;
+
+#[derive(thiserror::Error, Debug)]
+pub enum DiffError {
+    #[error("{0}")]
+    Parse(#[from] ParseError),
+    #[error("{0}")]
+    Program(#[from] ProgramError),
+    #[error("{0}")]
+    Translator(#[from] TranslatorError),
+    #[error("assembly error: {0}")]
+    Assembly(#[from] AssemblyError),
+    #[error("{0}")]
+    Rom(#[from] RomError),
+    #[error("{0}")]
+    Memory(#[from] MemoryError),
+    #[error("interpreted: {0}")]
+    Vm(#[from] VmError),
+    #[error("translated: {0}")]
+    Emulator(#[from] EmulatorError),
+    #[error("the interpreted program didn't halt within {0} steps, and only programs that halt can be compared when translating with shared routines, stack caching or optimisation")]
+    VmDidNotHalt(u64),
+    #[error("the translated program didn't halt within {0} cycles")]
+    HackDidNotHalt(u64),
+    #[error("the translated program didn't run {0} commands within {1} cycles")]
+    HackFellBehind(u64, u64),
+}
+
+///A cell left holding different values by the two ways of running a program
+#[derive(Debug, PartialEq, Clone)]
+pub struct Mismatch {
+    ///`RAM[addr]`, or the name of a static as `File.i`
+    pub cell: String,
+    pub interpreted: i16,
+    pub translated: i16,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} interpreted, {} translated",
+            self.cell, self.interpreted, self.translated
+        )
+    }
+}
+
+///Parses a module's source, as if read from `name.vm`
+pub fn parse_module(name: &str, source: &str) -> Result<(String, Vec<Command>), ParseError> {
+    let file = format!("{}.vm", name);
+    let commands =
+        Parser::new(&file, BufReader::new(source.as_bytes())).collect::<Result<_, _>>()?;
+    Ok((name.to_owned(), commands))
+}
+
+///Writes modules back out as VM source, each headed by its file name
+pub fn source(modules: &[(String, Vec<Command>)]) -> String {
+    modules
+        .iter()
+        .map(|(name, commands)| {
+            let lines: String = commands
+                .iter()
+                .map(|c| format!("{}\n", c.original()))
+                .collect();
+            format!("//{}.vm\n{}", name, lines)
+        })
+        .collect()
+}
+
+///Runs modules both by interpreting them and by translating them with `options`, assembling
+///and running the result on the Hack CPU, each until it halts. Both start with SP at 256 and go
+///through the bootstrap when there is a `Sys.init`. Compares the RAM they leave behind: the
+///stack up to SP and every other cell, with statics matched by name as the two place them
+///differently. Left out are the scratch registers R13 to R15, the cells above SP and the return
+///addresses saved in frames, which the two legitimately disagree on.
+///
+///Programs that halt within `max_steps`, by returning from the entry function, running off
+///their end or looping on a `goto` to itself, are compared once both runs have stopped. Others
+///are compared after `max_steps` commands, but only for the plain translation, where each VM
+///command has its own run of instructions that starts it. Shared routines, stack caching and the
+///optimiser leave no such point in the translation, so with them the program has to halt
+pub fn diff(
+    modules: &[(String, Vec<Command>)],
+    options: &CodegenOptions,
+    max_steps: u64,
+) -> Result<Vec<Mismatch>, DiffError> {
+    let bootstrap = Bootstrap::default();
+    let (asm, translation) = translate_in_memory(modules.to_vec(), options, &bootstrap)?;
+    let commands: BTreeSet<_> = translation
+        .source_map
+        .ranges()
+        .iter()
+        .filter_map(|range| Some((range.file.as_deref()?, range.line?)))
+        .collect();
+
+    let mut vm = Vm::new(Program::from_sources(modules, OsMode::Vm)?);
+    vm.ram_mut().write(SP, STACK_BASE)?;
+    vm.bootstrap(&bootstrap)?;
+    //Commands such as labels translate to no instructions, so the translation never starts them
+    let mut translated_commands = 0;
+    while vm.steps() < max_steps && !vm.is_halted() {
+        let location = vm.program().location(vm.pc());
+        if location.is_some_and(|l| commands.contains(&(&*l.file, l.line))) {
+            translated_commands += 1;
+        }
+        vm.step()?;
+    }
+    let halted = vm.is_halted();
+    if !halted && !lines_up(options) {
+        return Err(DiffError::VmDidNotHalt(max_steps));
+    }
+
+    let assembly = assemble(&mut BufReader::new(&asm[..]))?;
+    let mut emulator = Emulator::new(Rom::new(assembly.instructions)?);
+    emulator.ram_mut().write(SP, STACK_BASE)?;
+    let max_cycles = (vm.steps() + 1) * CYCLES_PER_STEP;
+    if halted {
+        if emulator.run(max_cycles)? != HackOutcome::Halted {
+            return Err(DiffError::HackDidNotHalt(max_cycles));
+        }
+    } else {
+        let starts: BTreeSet<_> = translation
+            .source_map
+            .ranges()
+            .iter()
+            .filter(|range| range.file.is_some())
+            .map(|range| range.start)
+            .collect();
+        let mut started = 0;
+        loop {
+            if starts.contains(&emulator.cpu().pc()) {
+                if started == translated_commands {
+                    break;
+                }
+                started += 1;
+            }
+            if emulator.cycles() >= max_cycles {
+                return Err(DiffError::HackFellBehind(translated_commands, max_cycles));
+            }
+            emulator.step()?;
+        }
+    }
+
+    let (interpreted, translated) = (vm.ram(), emulator.ram());
+    let sp = interpreted.read(SP)?;
+    let return_slots = return_slots(interpreted)?;
+    let statics = vm.program().statics().count() as u16;
+    let skipped = |addr: u16| {
+        SCRATCH_REGISTERS.contains(&addr)
+            || (STATIC_BASE..STATIC_BASE + statics).contains(&addr)
+            || (sp.max(STACK_BASE)..STACK_END).contains(&addr)
+            || return_slots.contains(&addr)
+    };
+    let mut mismatches = Vec::new();
+    let mut compare = |cell: String, interpreted: u16, translated: u16| {
+        if interpreted != translated {
+            mismatches.push(Mismatch {
+                cell,
+                interpreted: interpreted as i16,
+                translated: translated as i16,
+            });
+        }
+    };
+    for addr in (0..hack_emulator::RAM_SIZE as u16).filter(|addr| !skipped(*addr)) {
+        compare(
+            format!("RAM[{}]", addr),
+            interpreted.read(addr)?,
+            translated.read(addr)?,
+        );
+    }
+    for (name, addr) in vm.program().statics() {
+        let translated_addr = assembly.symbols.get_addr(name).unwrap_or(addr);
+        compare(
+            name.to_owned(),
+            interpreted.read(addr)?,
+            translated.read(translated_addr)?,
+        );
+    }
+    Ok(mismatches)
+}
+
+///Whether each VM command is translated to a run of instructions of its own, so that the two runs
+///can be stopped at the same command
+fn lines_up(options: &CodegenOptions) -> bool {
+    !options.shared_routines && !options.stack_caching && !options.optimise
+}
+
+///The cells holding return addresses in the frames still on the stack, found by following the
+///saved LCL of each frame down from the current one
+fn return_slots(ram: &Ram) -> Result<Vec<u16>, DiffError> {
+    let mut slots = Vec::new();
+    let mut lcl = ram.read(LCL)?;
+    while (STACK_BASE + 5..STACK_END).contains(&lcl) {
+        slots.push(lcl - 5);
+        let caller = ram.read(lcl - 4)?;
+        if caller >= lcl {
+            break;
+        }
+        lcl = caller;
+    }
+    Ok(slots)
+}
+
+///Whether running both ways still ends in mismatches, rather than agreeing or failing to run
+fn still_differs(
+    modules: &[(String, Vec<Command>)],
+    options: &CodegenOptions,
+    max_steps: u64,
+) -> bool {
+    matches!(diff(modules, options, max_steps), Ok(mismatches) if !mismatches.is_empty())
+}
+
+///Deletes commands from modules that run to mismatches for as long as they still do, first in
+///large runs and then one at a time, leaving a program none of whose commands can be deleted
+///alone without losing every mismatch. Modules left empty are dropped
+pub fn shrink(
+    modules: &[(String, Vec<Command>)],
+    options: &CodegenOptions,
+    max_steps: u64,
+) -> Vec<(String, Vec<Command>)> {
+    let mut modules = modules.to_vec();
+    for module in 0..modules.len() {
+        let mut chunk = modules[module].1.len().max(1);
+        loop {
+            let mut start = 0;
+            while start < modules[module].1.len() {
+                let mut candidate = modules.clone();
+                let end = (start + chunk).min(candidate[module].1.len());
+                candidate[module].1.drain(start..end);
+                if still_differs(&candidate, options, max_steps) {
+                    modules = candidate;
+                } else {
+                    start += chunk;
+                }
+            }
+            if chunk == 1 {
+                break;
+            }
+            chunk /= 2;
+        }
+    }
+    modules.retain(|(_, commands)| !commands.is_empty());
+    modules
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use assert_matches::assert_matches;
+    use test_case::test_case;
+    use vm_translator::translator::parse_inputs;
+
+    use super::*;
+    use crate::random::random_program;
+
+    const MAX_STEPS: u64 = 100_000;
+
+    fn options(shared_routines: bool, stack_caching: bool, optimise: bool) -> CodegenOptions {
+        CodegenOptions {
+            shared_routines,
+            stack_caching,
+            optimise,
+            safe_comparisons: true,
+            temp_registers: None,
+            runtime_checks: false,
+        }
+    }
+
+    fn parse(modules: &[(&str, &str)]) -> Vec<(String, Vec<Command>)> {
+        modules
+            .iter()
+            .map(|(name, source)| parse_module(name, source).unwrap())
+            .collect()
+    }
+
+    #[test_case("08/FunctionCalls/FibonacciElement")]
+    #[test_case("08/FunctionCalls/NestedCall")]
+    #[test_case("08/FunctionCalls/StaticsTest")]
+    fn it_agrees_on_the_course_programs(dir: &str) {
+        let modules = parse_inputs(&[Path::new("../..").join(dir)]).unwrap();
+        for options in [options(false, false, false), options(true, true, true)] {
+            assert_eq!(diff(&modules, &options, MAX_STEPS).unwrap(), vec![]);
+        }
+    }
+
+    #[test_case(false, false, false; "inline")]
+    #[test_case(true, false, false; "shared routines")]
+    #[test_case(false, true, false; "stack caching")]
+    #[test_case(false, false, true; "optimised")]
+    #[test_case(true, true, true; "everything")]
+    fn it_agrees_on_random_programs(shared_routines: bool, stack_caching: bool, optimise: bool) {
+        let options = options(shared_routines, stack_caching, optimise);
+        for seed in 0..40 {
+            let modules = random_program(seed);
+            let mismatches = diff(&modules, &options, MAX_STEPS)
+                .unwrap_or_else(|err| panic!("seed {}: {}\n{}", seed, err, source(&modules)));
+            if !mismatches.is_empty() {
+                let shrunk = shrink(&modules, &options, MAX_STEPS);
+                panic!(
+                    "seed {} differs:\n{}\nshrunk to:\n{}",
+                    seed,
+                    mismatches[0],
+                    source(&shrunk)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn it_compares_programs_that_dont_halt_after_each_step() {
+        let modules = parse(&[(
+            "Sys",
+            "function Sys.init 0\nlabel LOOP\npush static 0\npush constant 1\nadd\npop static 0\n\
+             push static 0\ncall Sys.double 1\npop temp 0\ngoto LOOP\n\
+             function Sys.double 1\npush argument 0\npush argument 0\nadd\npop local 0\n\
+             push local 0\nreturn\n",
+        )]);
+        for steps in 0..60 {
+            assert_eq!(
+                diff(&modules, &options(false, false, false), steps).unwrap(),
+                vec![],
+                "after {} steps",
+                steps
+            );
+        }
+        assert_matches!(
+            diff(&modules, &options(true, false, false), 60),
+            Err(DiffError::VmDidNotHalt(60))
+        );
+    }
+
+    #[test]
+    fn it_agrees_on_random_programs_stopped_early() {
+        for seed in 0..40 {
+            let modules = random_program(seed);
+            for steps in [1, 10, 100] {
+                let mismatches = diff(&modules, &options(false, false, false), steps)
+                    .unwrap_or_else(|err| panic!("seed {}: {}\n{}", seed, err, source(&modules)));
+                assert_eq!(mismatches, vec![], "seed {} after {} steps", seed, steps);
+            }
+        }
+    }
+
+    #[test]
+    fn it_finds_and_shrinks_overflowing_comparisons() {
+        let modules = parse(&[(
+            "Main",
+            "push constant 7\npop static 0\npush constant 20000\npush constant 3\nadd\n\
+             push constant 5\nneg\npush constant 20000\nsub\nlt\npush constant 1\npop temp 2\n\
+             push static 0\nlabel END\ngoto END\n",
+        )]);
+        let unsafe_comparisons = CodegenOptions::default();
+        let mismatches = diff(&modules, &unsafe_comparisons, MAX_STEPS).unwrap();
+        assert_eq!(
+            mismatches,
+            vec![Mismatch {
+                cell: "RAM[256]".to_owned(),
+                interpreted: 0,
+                translated: -1,
+            }]
+        );
+        assert_eq!(
+            diff(&modules, &options(false, false, false), MAX_STEPS).unwrap(),
+            vec![]
+        );
+        let shrunk = shrink(&modules, &unsafe_comparisons, MAX_STEPS);
+        assert_eq!(
+            source(&shrunk),
+            "//Main.vm\npush constant 20000\npush constant 5\npush constant 20000\nsub\nlt\nlabel END\n\
+             goto END\n"
+        );
+    }
+}
@@ -0,0 +1,11 @@
+mod differential;
+mod os;
+mod program;
+mod random;
+mod vm;
+
+pub use differential::{diff, parse_module, shrink, source, DiffError, Mismatch};
+pub use os::{Native, Os, OsError, OsFunction, OsMode};
+pub use program::{Op, Place, Program, ProgramError};
+pub use random::random_program;
+pub use vm::{RunOutcome, Vm, VmError};
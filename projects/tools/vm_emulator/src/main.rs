@@ -0,0 +1,160 @@
+use clap::Parser;
+use hack_emulator::{RamDump, RamRange, Screen};
+use std::{error::Error, path::PathBuf, process, str::FromStr};
+use vm_emulator::{diff, shrink, OsMode, Program, RunOutcome, Vm};
+use vm_translator::translator::{parse_inputs, Bootstrap, BootstrapMode, CodegenOptions};
+
+///An emulator running Jack VM programs from the nand-to-tetris course command by command
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about=None)]
+struct Args {
+    ///VM files or directories, loaded in the order the translator would
+    #[clap(name = "input files or directories", required = true)]
+    inputs: Vec<PathBuf>,
+    ///Maximum number of VM commands to run, unless the program halts first, which it must
+    ///within them to be compared with --diff when translating with any codegen flag
+    #[clap(short = 'n', long, default_value_t = 1_000_000)]
+    steps: u64,
+    ///Sets a RAM cell before the program starts, e.g. 0=256, applied before the bootstrap
+    #[clap(short, long)]
+    set: Vec<RamValue>,
+    ///RAM cells to dump when the program stops, e.g. 256 or 256..260
+    #[clap(short, long)]
+    dump: Vec<RamRange>,
+    ///Bootstraps even when the entry function isn't defined, which is then an error
+    #[clap(long, conflicts_with = "no-bootstrap")]
+    bootstrap: bool,
+    ///Starts at the first command without bootstrapping, as the single file tests of
+    ///projects/07 expect
+    #[clap(long)]
+    no_bootstrap: bool,
+    ///Initial stack pointer set by the bootstrap
+    #[clap(long, default_value_t = 256)]
+    sp: u16,
+    ///Function called by the bootstrap
+    #[clap(long, default_value = "Sys.init")]
+    entry: String,
+    ///Calls only the functions the VM files define, for running with the OS compiled from
+    ///projects/12 rather than the one built into the emulator
+    #[clap(long)]
+    vm_os: bool,
+    ///Text typed on the keyboard for the built in OS to read, with \n for the newline key
+    #[clap(short, long, default_value = "")]
+    input: String,
+    ///Saves the screen to a .pbm or .png image when the program stops
+    #[clap(long)]
+    screenshot: Option<PathBuf>,
+    ///Instead of reporting on a run, compares it with running the translation on the Hack CPU
+    ///and prints the cells they disagree on with the smallest program that still shows it.
+    ///Programs that don't halt are compared after --steps commands, which only the plain
+    ///translation lines up with, so with any codegen flag they must halt
+    #[clap(long, conflicts_with_all = &[
+        "set", "dump", "bootstrap", "no-bootstrap", "sp", "entry", "vm-os", "input", "screenshot"
+    ])]
+    diff: bool,
+    ///Translates with shared call, return and comparison routines for --diff
+    #[clap(long, requires = "diff")]
+    shared_routines: bool,
+    ///Translates keeping the top of the stack in the D register for --diff
+    #[clap(long, requires = "diff")]
+    stack_caching: bool,
+    ///Translates with constant folding and simplified command sequences for --diff
+    #[clap(short = 'O', long, requires = "diff")]
+    optimise: bool,
+    ///Translates `gt` and `lt` to get operands far apart right for --diff
+    #[clap(long, requires = "diff")]
+    safe_comparisons: bool,
+}
+
+#[derive(Debug)]
+struct RamValue(u16, i16);
+
+impl FromStr for RamValue {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid RAM value '{}', expected address=value", s);
+        let (addr, value) = s.split_once('=').ok_or_else(invalid)?;
+        Ok(RamValue(
+            addr.trim().parse().map_err(|_| invalid())?,
+            value.trim().parse().map_err(|_| invalid())?,
+        ))
+    }
+}
+
+fn main() {
+    //Printed with Display rather than returned, as that would print the Debug form
+    if let Err(err) = run(Args::parse()) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    if args.diff {
+        let modules = parse_inputs(&args.inputs)?;
+        let options = CodegenOptions {
+            shared_routines: args.shared_routines,
+            stack_caching: args.stack_caching,
+            optimise: args.optimise,
+            safe_comparisons: args.safe_comparisons,
+            ..CodegenOptions::default()
+        };
+        let mismatches = diff(&modules, &options, args.steps)?;
+        if mismatches.is_empty() {
+            eprintln!("no differences");
+            return Ok(());
+        }
+        for mismatch in mismatches {
+            eprintln!("{}", mismatch);
+        }
+        print!(
+            "{}",
+            vm_emulator::source(&shrink(&modules, &options, args.steps))
+        );
+        return Err("the translation differs".into());
+    }
+    let os = if args.vm_os {
+        OsMode::Vm
+    } else {
+        OsMode::Native
+    };
+    let program = Program::load(&args.inputs, os)?;
+    for warning in program.warnings() {
+        eprintln!("warning: {}", warning);
+    }
+    let mut vm = Vm::new(program);
+    vm.os_mut().type_input(&args.input.replace("\\n", "\n"));
+    for RamValue(addr, value) in args.set {
+        vm.ram_mut().write(addr, value as u16)?;
+    }
+    vm.bootstrap(&Bootstrap {
+        mode: match (args.bootstrap, args.no_bootstrap) {
+            (true, _) => BootstrapMode::Always,
+            (_, true) => BootstrapMode::Never,
+            _ => BootstrapMode::Auto,
+        },
+        sp: args.sp,
+        entry: args.entry,
+        ..Bootstrap::default()
+    })?;
+    let outcome = vm.run(args.steps);
+    print!("{}", vm.os().output());
+    let outcome = outcome?;
+    eprintln!(
+        "{} after {} steps",
+        match outcome {
+            RunOutcome::Halted => "halted",
+            RunOutcome::StepLimit => "stopped",
+        },
+        vm.steps()
+    );
+    if let Some(path) = args.screenshot {
+        Screen::capture(vm.ram()).save(&path)?;
+    }
+    if !args.dump.is_empty() {
+        let addrs = args.dump.into_iter().flatten();
+        print!("{}", RamDump::new(vm.ram(), addrs)?);
+    }
+    Ok(())
+}
@@ -0,0 +1,754 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use hack_assembler::symbol_table::{KBD_MEM, SCREEN_MEM};
+use hack_emulator::{MemoryError, Ram, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+const HEAP_BASE: u16 = 2048;
+const HEAP_END: u16 = SCREEN_MEM;
+const WORD_BITS: i16 = 16;
+const SCREEN_ROW_WORDS: i16 = SCREEN_WIDTH as i16 / WORD_BITS;
+const MAX_RADIUS: i16 = 181;
+const TEXT_ROWS: i16 = 23;
+const TEXT_COLUMNS: i16 = 64;
+const NEWLINE: i16 = 128;
+const BACKSPACE: i16 = 129;
+const DOUBLE_QUOTE: i16 = 34;
+///A string object holds its maximum length and length before its characters
+const STRING_HEADER: i16 = 2;
+
+///Where the functions of the Jack OS come from
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OsMode {
+    ///Built into the emulator, for every OS function the program doesn't define itself
+    Native,
+    ///Only the VM files loaded, as when linking the OS compiled from projects/12
+    Vm,
+}
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum OsError {
+    #[error("memory error: {0}")]
+    Memory(#[from] MemoryError),
+    #[error("Sys.error({0}): {}", describe(*.0))]
+    Sys(i16),
+    #[error("the keyboard was read after all the input was typed")]
+    NoInput,
+}
+
+///What the error codes of the course's OS mean
+fn describe(code: i16) -> &'static str {
+    match code {
+        1 => "Sys.wait duration must be positive",
+        2 => "Array size must be positive",
+        3 => "division by zero",
+        4 => "cannot compute the square root of a negative number",
+        5 => "allocated memory size must be positive",
+        6 => "heap overflow",
+        7 => "illegal pixel coordinates",
+        8 => "illegal line coordinates",
+        9 => "illegal rectangle coordinates",
+        12 => "illegal center coordinates",
+        13 => "illegal radius",
+        14 => "maximum length must be non-negative",
+        15 => "string index out of bounds",
+        16 => "string index out of bounds",
+        17 => "string is full",
+        18 => "string is empty",
+        19 => "insufficient string capacity",
+        20 => "illegal cursor location",
+        _ => "raised by the program",
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OsFunction {
+    MathInit,
+    MathAbs,
+    MathMultiply,
+    MathDivide,
+    MathMin,
+    MathMax,
+    MathSqrt,
+    StringNew,
+    StringDispose,
+    StringLength,
+    StringCharAt,
+    StringSetCharAt,
+    StringAppendChar,
+    StringEraseLastChar,
+    StringIntValue,
+    StringSetInt,
+    StringBackSpace,
+    StringDoubleQuote,
+    StringNewLine,
+    ArrayNew,
+    ArrayDispose,
+    OutputInit,
+    OutputMoveCursor,
+    OutputPrintChar,
+    OutputPrintString,
+    OutputPrintInt,
+    OutputPrintln,
+    OutputBackSpace,
+    ScreenInit,
+    ScreenClearScreen,
+    ScreenSetColor,
+    ScreenDrawPixel,
+    ScreenDrawLine,
+    ScreenDrawRectangle,
+    ScreenDrawCircle,
+    KeyboardInit,
+    KeyboardKeyPressed,
+    KeyboardReadChar,
+    KeyboardReadLine,
+    KeyboardReadInt,
+    MemoryInit,
+    MemoryPeek,
+    MemoryPoke,
+    MemoryAlloc,
+    MemoryDeAlloc,
+    SysHalt,
+    SysError,
+    SysWait,
+}
+
+///The functions of the Jack OS API with their number of arguments, methods counting `this`
+const FUNCTIONS: [(&str, u16, OsFunction); 48] = [
+    ("Math.init", 0, OsFunction::MathInit),
+    ("Math.abs", 1, OsFunction::MathAbs),
+    ("Math.multiply", 2, OsFunction::MathMultiply),
+    ("Math.divide", 2, OsFunction::MathDivide),
+    ("Math.min", 2, OsFunction::MathMin),
+    ("Math.max", 2, OsFunction::MathMax),
+    ("Math.sqrt", 1, OsFunction::MathSqrt),
+    ("String.new", 1, OsFunction::StringNew),
+    ("String.dispose", 1, OsFunction::StringDispose),
+    ("String.length", 1, OsFunction::StringLength),
+    ("String.charAt", 2, OsFunction::StringCharAt),
+    ("String.setCharAt", 3, OsFunction::StringSetCharAt),
+    ("String.appendChar", 2, OsFunction::StringAppendChar),
+    ("String.eraseLastChar", 1, OsFunction::StringEraseLastChar),
+    ("String.intValue", 1, OsFunction::StringIntValue),
+    ("String.setInt", 2, OsFunction::StringSetInt),
+    ("String.backSpace", 0, OsFunction::StringBackSpace),
+    ("String.doubleQuote", 0, OsFunction::StringDoubleQuote),
+    ("String.newLine", 0, OsFunction::StringNewLine),
+    ("Array.new", 1, OsFunction::ArrayNew),
+    ("Array.dispose", 1, OsFunction::ArrayDispose),
+    ("Output.init", 0, OsFunction::OutputInit),
+    ("Output.moveCursor", 2, OsFunction::OutputMoveCursor),
+    ("Output.printChar", 1, OsFunction::OutputPrintChar),
+    ("Output.printString", 1, OsFunction::OutputPrintString),
+    ("Output.printInt", 1, OsFunction::OutputPrintInt),
+    ("Output.println", 0, OsFunction::OutputPrintln),
+    ("Output.backSpace", 0, OsFunction::OutputBackSpace),
+    ("Screen.init", 0, OsFunction::ScreenInit),
+    ("Screen.clearScreen", 0, OsFunction::ScreenClearScreen),
+    ("Screen.setColor", 1, OsFunction::ScreenSetColor),
+    ("Screen.drawPixel", 2, OsFunction::ScreenDrawPixel),
+    ("Screen.drawLine", 4, OsFunction::ScreenDrawLine),
+    ("Screen.drawRectangle", 4, OsFunction::ScreenDrawRectangle),
+    ("Screen.drawCircle", 3, OsFunction::ScreenDrawCircle),
+    ("Keyboard.init", 0, OsFunction::KeyboardInit),
+    ("Keyboard.keyPressed", 0, OsFunction::KeyboardKeyPressed),
+    ("Keyboard.readChar", 0, OsFunction::KeyboardReadChar),
+    ("Keyboard.readLine", 1, OsFunction::KeyboardReadLine),
+    ("Keyboard.readInt", 1, OsFunction::KeyboardReadInt),
+    ("Memory.init", 0, OsFunction::MemoryInit),
+    ("Memory.peek", 1, OsFunction::MemoryPeek),
+    ("Memory.poke", 2, OsFunction::MemoryPoke),
+    ("Memory.alloc", 1, OsFunction::MemoryAlloc),
+    ("Memory.deAlloc", 1, OsFunction::MemoryDeAlloc),
+    ("Sys.halt", 0, OsFunction::SysHalt),
+    ("Sys.error", 1, OsFunction::SysError),
+    ("Sys.wait", 1, OsFunction::SysWait),
+];
+
+impl OsFunction {
+    pub fn from_name(name: &str) -> Option<Self> {
+        FUNCTIONS
+            .iter()
+            .find(|(n, _, _)| *n == name)
+            .map(|(_, _, function)| *function)
+    }
+
+    fn signature(self) -> (&'static str, u16) {
+        FUNCTIONS
+            .iter()
+            .find(|(_, _, function)| *function == self)
+            .map(|(name, args, _)| (*name, *args))
+            .expect("every function is listed")
+    }
+
+    pub fn name(self) -> &'static str {
+        self.signature().0
+    }
+
+    pub fn args(self) -> u16 {
+        self.signature().1
+    }
+}
+
+///What running a native function leaves the program to do
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Native {
+    ///Carry on after the call with the value returned, 0 for void functions
+    Return(i16),
+    Halt,
+}
+
+///First fit allocation over the heap, kept outside of RAM so that programs can't corrupt it
+#[derive(Debug)]
+struct Heap {
+    free: BTreeMap<u16, u16>,
+    used: HashMap<u16, u16>,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self {
+            free: BTreeMap::from([(HEAP_BASE, HEAP_END - HEAP_BASE)]),
+            used: HashMap::new(),
+        }
+    }
+}
+
+impl Heap {
+    fn alloc(&mut self, size: u16) -> Option<u16> {
+        let (start, len) = self
+            .free
+            .iter()
+            .find(|(_, len)| **len >= size)
+            .map(|(start, len)| (*start, *len))?;
+        self.free.remove(&start);
+        if len > size {
+            self.free.insert(start + size, len - size);
+        }
+        self.used.insert(start, size);
+        Some(start)
+    }
+
+    ///Frees a block, merging it with free neighbours. As in the course's OS, freeing anything
+    ///that wasn't allocated does nothing
+    fn free(&mut self, start: u16) {
+        let (mut start, mut size) = match self.used.remove(&start) {
+            Some(size) => (start, size),
+            None => return,
+        };
+        if let Some((prev, prev_size)) = self.free.range(..start).next_back() {
+            if prev + prev_size == start {
+                let prev = *prev;
+                size += self.free.remove(&prev).unwrap_or_default();
+                start = prev;
+            }
+        }
+        if let Some(next_size) = self.free.remove(&(start + size)) {
+            size += next_size;
+        }
+        self.free.insert(start, size);
+    }
+}
+
+///The state of the native Jack OS. Strings and arrays live on the heap as in the course's OS,
+///but the output is kept as text rather than drawn on the screen in the OS font
+#[derive(Debug)]
+pub struct Os {
+    heap: Heap,
+    black: bool,
+    output: String,
+    input: VecDeque<i16>,
+}
+
+impl Default for Os {
+    fn default() -> Self {
+        Self {
+            heap: Heap::default(),
+            black: true,
+            output: String::new(),
+            input: VecDeque::new(),
+        }
+    }
+}
+
+impl Os {
+    ///What the program printed, with a new line wherever it moved the cursor
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    ///Queues text for the keyboard to read, with new lines as the newline key
+    pub fn type_input(&mut self, text: &str) {
+        self.input.extend(text.chars().map(|c| match c {
+            '\n' => NEWLINE,
+            c => c as i16,
+        }));
+    }
+
+    pub fn call(
+        &mut self,
+        function: OsFunction,
+        args: &[i16],
+        ram: &mut Ram,
+    ) -> Result<Native, OsError> {
+        let arg = |idx: usize| args[idx];
+        let value = match function {
+            OsFunction::MathInit
+            | OsFunction::OutputInit
+            | OsFunction::ScreenInit
+            | OsFunction::KeyboardInit
+            | OsFunction::MemoryInit => 0,
+            OsFunction::MathAbs => arg(0).wrapping_abs(),
+            OsFunction::MathMultiply => arg(0).wrapping_mul(arg(1)),
+            OsFunction::MathDivide if arg(1) == 0 => return Err(OsError::Sys(3)),
+            OsFunction::MathDivide => arg(0).wrapping_div(arg(1)),
+            OsFunction::MathMin => arg(0).min(arg(1)),
+            OsFunction::MathMax => arg(0).max(arg(1)),
+            OsFunction::MathSqrt if arg(0) < 0 => return Err(OsError::Sys(4)),
+            OsFunction::MathSqrt => (f64::from(arg(0))).sqrt() as i16,
+            OsFunction::StringNew if arg(0) < 0 => return Err(OsError::Sys(14)),
+            OsFunction::StringNew => self.new_string(arg(0), ram)?,
+            OsFunction::StringDispose | OsFunction::ArrayDispose | OsFunction::MemoryDeAlloc => {
+                self.heap.free(arg(0) as u16);
+                0
+            }
+            OsFunction::StringLength => read(ram, arg(0), 1)?,
+            OsFunction::StringCharAt => {
+                let idx = string_index(ram, arg(0), arg(1), 15)?;
+                read(ram, arg(0), idx)?
+            }
+            OsFunction::StringSetCharAt => {
+                let idx = string_index(ram, arg(0), arg(1), 16)?;
+                write(ram, arg(0), idx, arg(2))?;
+                0
+            }
+            OsFunction::StringAppendChar => {
+                let (max, len) = (read(ram, arg(0), 0)?, read(ram, arg(0), 1)?);
+                if len >= max {
+                    return Err(OsError::Sys(17));
+                }
+                write(ram, arg(0), STRING_HEADER + len, arg(1))?;
+                write(ram, arg(0), 1, len + 1)?;
+                arg(0)
+            }
+            OsFunction::StringEraseLastChar => {
+                let len = read(ram, arg(0), 1)?;
+                if len == 0 {
+                    return Err(OsError::Sys(18));
+                }
+                write(ram, arg(0), 1, len - 1)?;
+                0
+            }
+            OsFunction::StringIntValue => int_value(&string(ram, arg(0))?),
+            OsFunction::StringSetInt => {
+                let digits: Vec<i16> = arg(1).to_string().bytes().map(i16::from).collect();
+                if digits.len() as i16 > read(ram, arg(0), 0)? {
+                    return Err(OsError::Sys(19));
+                }
+                for (idx, c) in digits.iter().enumerate() {
+                    write(ram, arg(0), STRING_HEADER + idx as i16, *c)?;
+                }
+                write(ram, arg(0), 1, digits.len() as i16)?;
+                0
+            }
+            OsFunction::StringBackSpace => BACKSPACE,
+            OsFunction::StringDoubleQuote => DOUBLE_QUOTE,
+            OsFunction::StringNewLine => NEWLINE,
+            OsFunction::ArrayNew if arg(0) <= 0 => return Err(OsError::Sys(2)),
+            OsFunction::ArrayNew | OsFunction::MemoryAlloc => self.alloc(arg(0))?,
+            OsFunction::OutputMoveCursor => {
+                if !(0..TEXT_ROWS).contains(&arg(0)) || !(0..TEXT_COLUMNS).contains(&arg(1)) {
+                    return Err(OsError::Sys(20));
+                }
+                if !self.output.is_empty() && !self.output.ends_with('\n') {
+                    self.output.push('\n');
+                }
+                0
+            }
+            OsFunction::OutputPrintChar => {
+                self.print_char(arg(0));
+                0
+            }
+            OsFunction::OutputPrintString => {
+                for c in string(ram, arg(0))? {
+                    self.print_char(c);
+                }
+                0
+            }
+            OsFunction::OutputPrintInt => {
+                self.output += &arg(0).to_string();
+                0
+            }
+            OsFunction::OutputPrintln => {
+                self.print_char(NEWLINE);
+                0
+            }
+            OsFunction::OutputBackSpace => {
+                self.print_char(BACKSPACE);
+                0
+            }
+            OsFunction::ScreenClearScreen => {
+                for addr in SCREEN_MEM..KBD_MEM {
+                    ram.write(addr, 0)?;
+                }
+                0
+            }
+            OsFunction::ScreenSetColor => {
+                self.black = arg(0) != 0;
+                0
+            }
+            OsFunction::ScreenDrawPixel => {
+                if !on_screen(arg(0), arg(1)) {
+                    return Err(OsError::Sys(7));
+                }
+                self.draw_pixel(ram, arg(0), arg(1))?;
+                0
+            }
+            OsFunction::ScreenDrawLine => {
+                if !on_screen(arg(0), arg(1)) || !on_screen(arg(2), arg(3)) {
+                    return Err(OsError::Sys(8));
+                }
+                self.draw_line(ram, (arg(0), arg(1)), (arg(2), arg(3)))?;
+                0
+            }
+            OsFunction::ScreenDrawRectangle => {
+                let (x1, y1, x2, y2) = (arg(0), arg(1), arg(2), arg(3));
+                if !on_screen(x1, y1) || !on_screen(x2, y2) || x1 > x2 || y1 > y2 {
+                    return Err(OsError::Sys(9));
+                }
+                for y in y1..=y2 {
+                    self.draw_row(ram, x1, x2, y)?;
+                }
+                0
+            }
+            OsFunction::ScreenDrawCircle => {
+                let (x, y, r) = (arg(0), arg(1), arg(2));
+                if !on_screen(x, y) {
+                    return Err(OsError::Sys(12));
+                }
+                if !(0..=MAX_RADIUS).contains(&r) {
+                    return Err(OsError::Sys(13));
+                }
+                for dy in -r..=r {
+                    let half = f64::from(r * r - dy * dy).sqrt() as i16;
+                    self.draw_row(ram, x - half, x + half, y + dy)?;
+                }
+                0
+            }
+            OsFunction::KeyboardKeyPressed => ram.read(KBD_MEM)? as i16,
+            OsFunction::KeyboardReadChar => {
+                let c = self.input.pop_front().ok_or(OsError::NoInput)?;
+                self.print_char(c);
+                c
+            }
+            OsFunction::KeyboardReadLine => self.read_line(ram, arg(0))?,
+            OsFunction::KeyboardReadInt => {
+                let line = self.read_line(ram, arg(0))?;
+                let value = int_value(&string(ram, line)?);
+                self.heap.free(line as u16);
+                value
+            }
+            OsFunction::MemoryPeek => ram.read(arg(0) as u16)? as i16,
+            OsFunction::MemoryPoke => {
+                ram.write(arg(0) as u16, arg(1) as u16)?;
+                0
+            }
+            OsFunction::SysHalt => return Ok(Native::Halt),
+            OsFunction::SysError => return Err(OsError::Sys(arg(0))),
+            OsFunction::SysWait if arg(0) <= 0 => return Err(OsError::Sys(1)),
+            OsFunction::SysWait => 0,
+        };
+        Ok(Native::Return(value))
+    }
+
+    fn alloc(&mut self, size: i16) -> Result<i16, OsError> {
+        if size <= 0 {
+            return Err(OsError::Sys(5));
+        }
+        self.heap
+            .alloc(size as u16)
+            .map(|addr| addr as i16)
+            .ok_or(OsError::Sys(6))
+    }
+
+    fn new_string(&mut self, max: i16, ram: &mut Ram) -> Result<i16, OsError> {
+        let this = self.alloc(max.saturating_add(STRING_HEADER))?;
+        write(ram, this, 0, max)?;
+        write(ram, this, 1, 0)?;
+        Ok(this)
+    }
+
+    ///Prints a prompt and reads characters up to a new line into a new string, handling
+    ///backspaces and echoing everything typed as the course's OS does
+    fn read_line(&mut self, ram: &mut Ram, message: i16) -> Result<i16, OsError> {
+        for c in string(ram, message)? {
+            self.print_char(c);
+        }
+        let mut line = Vec::new();
+        loop {
+            let c = self.input.pop_front().ok_or(OsError::NoInput)?;
+            self.print_char(c);
+            match c {
+                NEWLINE => break,
+                BACKSPACE => {
+                    line.pop();
+                }
+                c => line.push(c),
+            }
+        }
+        let this = self.new_string(line.len() as i16, ram)?;
+        for (idx, c) in line.iter().enumerate() {
+            write(ram, this, STRING_HEADER + idx as i16, *c)?;
+        }
+        write(ram, this, 1, line.len() as i16)?;
+        Ok(this)
+    }
+
+    fn print_char(&mut self, c: i16) {
+        match c {
+            NEWLINE => self.output.push('\n'),
+            BACKSPACE => {
+                if !self.output.ends_with('\n') {
+                    self.output.pop();
+                }
+            }
+            c => self.output.push(
+                char::from_u32(c as u32)
+                    .filter(char::is_ascii)
+                    .unwrap_or('?'),
+            ),
+        }
+    }
+
+    fn draw_pixel(&self, ram: &mut Ram, x: i16, y: i16) -> Result<(), OsError> {
+        let addr = SCREEN_MEM + (y * SCREEN_ROW_WORDS + x / WORD_BITS) as u16;
+        let bit = 1 << (x % WORD_BITS);
+        let word = ram.read(addr)?;
+        ram.write(addr, if self.black { word | bit } else { word & !bit })?;
+        Ok(())
+    }
+
+    ///Draws the pixels from x1 to x2 of row y that are on the screen
+    fn draw_row(&self, ram: &mut Ram, x1: i16, x2: i16, y: i16) -> Result<(), OsError> {
+        if !(0..SCREEN_HEIGHT as i16).contains(&y) {
+            return Ok(());
+        }
+        for x in x1.max(0)..=x2.min(SCREEN_WIDTH as i16 - 1) {
+            self.draw_pixel(ram, x, y)?;
+        }
+        Ok(())
+    }
+
+    fn draw_line(&self, ram: &mut Ram, from: (i16, i16), to: (i16, i16)) -> Result<(), OsError> {
+        let (dx, dy) = ((to.0 - from.0).abs(), -(to.1 - from.1).abs());
+        let (step_x, step_y) = ((to.0 - from.0).signum(), (to.1 - from.1).signum());
+        let (mut x, mut y) = from;
+        let mut err = dx + dy;
+        loop {
+            self.draw_pixel(ram, x, y)?;
+            if (x, y) == to {
+                return Ok(());
+            }
+            if 2 * err >= dy {
+                err += dy;
+                x += step_x;
+            }
+            if 2 * err <= dx {
+                err += dx;
+                y += step_y;
+            }
+        }
+    }
+}
+
+fn on_screen(x: i16, y: i16) -> bool {
+    (0..SCREEN_WIDTH as i16).contains(&x) && (0..SCREEN_HEIGHT as i16).contains(&y)
+}
+
+fn read(ram: &Ram, object: i16, field: i16) -> Result<i16, OsError> {
+    Ok(ram.read(object.wrapping_add(field) as u16)? as i16)
+}
+
+fn write(ram: &mut Ram, object: i16, field: i16, value: i16) -> Result<(), OsError> {
+    Ok(ram.write(object.wrapping_add(field) as u16, value as u16)?)
+}
+
+///The field holding the character at `idx`, if it is within the string's length
+fn string_index(ram: &Ram, this: i16, idx: i16, code: i16) -> Result<i16, OsError> {
+    if !(0..read(ram, this, 1)?).contains(&idx) {
+        return Err(OsError::Sys(code));
+    }
+    Ok(STRING_HEADER + idx)
+}
+
+fn string(ram: &Ram, this: i16) -> Result<Vec<i16>, OsError> {
+    (0..read(ram, this, 1)?)
+        .map(|idx| read(ram, this, STRING_HEADER + idx))
+        .collect()
+}
+
+///The integer a string starts with, stopping at the first character that isn't a digit
+fn int_value(chars: &[i16]) -> i16 {
+    let (negative, digits) = match chars.split_first() {
+        Some((c, rest)) if *c == b'-' as i16 => (true, rest),
+        _ => (false, chars),
+    };
+    let value = digits
+        .iter()
+        .take_while(|c| (b'0' as i16..=b'9' as i16).contains(c))
+        .fold(0_i16, |value, c| {
+            value.wrapping_mul(10).wrapping_add(c - b'0' as i16)
+        });
+    if negative {
+        value.wrapping_neg()
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_matches::assert_matches;
+    use test_case::test_case;
+
+    use super::*;
+
+    fn call(os: &mut Os, ram: &mut Ram, name: &str, args: &[i16]) -> Result<i16, OsError> {
+        let function = OsFunction::from_name(name).unwrap();
+        assert_eq!(function.args() as usize, args.len(), "{}", name);
+        match os.call(function, args, ram)? {
+            Native::Return(value) => Ok(value),
+            Native::Halt => panic!("{} halted", name),
+        }
+    }
+
+    fn new_string(os: &mut Os, ram: &mut Ram, text: &str) -> i16 {
+        let s = call(os, ram, "String.new", &[text.len() as i16]).unwrap();
+        for c in text.bytes() {
+            call(os, ram, "String.appendChar", &[s, c as i16]).unwrap();
+        }
+        s
+    }
+
+    #[test_case("Math.multiply", &[-180, 100], -18000; "multiply")]
+    #[test_case("Math.multiply", &[300, 300], 24464; "multiply overflowing")]
+    #[test_case("Math.divide", &[-18000, 6], -3000; "divide")]
+    #[test_case("Math.divide", &[32766, -32767], 0; "divide towards zero")]
+    #[test_case("Math.sqrt", &[32767], 181; "sqrt")]
+    #[test_case("Math.min", &[345, 123], 123; "min")]
+    #[test_case("Math.max", &[123, -345], 123; "max")]
+    #[test_case("Math.abs", &[-32767], 32767; "abs")]
+    fn it_does_math(name: &str, args: &[i16], expected: i16) {
+        let mut ram = Ram::new();
+        assert_eq!(call(&mut Os::default(), &mut ram, name, args), Ok(expected));
+    }
+
+    #[test_case("Math.divide", &[1, 0], 3)]
+    #[test_case("Math.sqrt", &[-1], 4)]
+    #[test_case("Array.new", &[0], 2)]
+    #[test_case("Memory.alloc", &[20000], 6)]
+    #[test_case("Screen.drawPixel", &[512, 0], 7)]
+    #[test_case("Screen.drawCircle", &[100, 100, 200], 13)]
+    #[test_case("Output.moveCursor", &[23, 0], 20)]
+    #[test_case("Sys.error", &[42], 42)]
+    fn it_reports_errors_with_the_codes_of_the_course_os(name: &str, args: &[i16], code: i16) {
+        let mut ram = Ram::new();
+        assert_eq!(
+            call(&mut Os::default(), &mut ram, name, args),
+            Err(OsError::Sys(code))
+        );
+    }
+
+    #[test]
+    fn it_reuses_and_merges_freed_heap_blocks() {
+        let (mut os, mut ram) = (Os::default(), Ram::new());
+        let a = call(&mut os, &mut ram, "Memory.alloc", &[10]).unwrap();
+        let b = call(&mut os, &mut ram, "Array.new", &[5]).unwrap();
+        let c = call(&mut os, &mut ram, "Memory.alloc", &[3]).unwrap();
+        assert_eq!((a, b, c), (2048, 2058, 2063));
+        call(&mut os, &mut ram, "Memory.deAlloc", &[a]).unwrap();
+        call(&mut os, &mut ram, "Array.dispose", &[b]).unwrap();
+        assert_eq!(call(&mut os, &mut ram, "Memory.alloc", &[15]), Ok(2048));
+        assert_eq!(call(&mut os, &mut ram, "Memory.alloc", &[1]), Ok(2066));
+    }
+
+    #[test]
+    fn it_builds_and_prints_strings() {
+        let (mut os, mut ram) = (Os::default(), Ram::new());
+        let s = new_string(&mut os, &mut ram, "-123x");
+        assert_eq!(call(&mut os, &mut ram, "String.length", &[s]), Ok(5));
+        assert_eq!(
+            call(&mut os, &mut ram, "String.charAt", &[s, 4]),
+            Ok('x' as i16)
+        );
+        assert_eq!(call(&mut os, &mut ram, "String.intValue", &[s]), Ok(-123));
+        assert_eq!(
+            call(&mut os, &mut ram, "String.appendChar", &[s, 'y' as i16]),
+            Err(OsError::Sys(17))
+        );
+        call(&mut os, &mut ram, "String.eraseLastChar", &[s]).unwrap();
+        call(&mut os, &mut ram, "Output.printString", &[s]).unwrap();
+        call(&mut os, &mut ram, "Output.println", &[]).unwrap();
+        call(&mut os, &mut ram, "String.setInt", &[s, 4567]).unwrap();
+        call(&mut os, &mut ram, "Output.printString", &[s]).unwrap();
+        call(&mut os, &mut ram, "Output.backSpace", &[]).unwrap();
+        call(&mut os, &mut ram, "Output.printInt", &[-8]).unwrap();
+        assert_eq!(os.output(), "-123\n456-8");
+        assert_eq!(
+            call(&mut os, &mut ram, "String.setInt", &[s, -12345]),
+            Err(OsError::Sys(19))
+        );
+    }
+
+    #[test]
+    fn it_reads_typed_lines() {
+        let (mut os, mut ram) = (Os::default(), Ram::new());
+        let prompt = new_string(&mut os, &mut ram, "n? ");
+        os.type_input("12\u{81}3\nab");
+        assert_eq!(
+            call(&mut os, &mut ram, "Keyboard.readInt", &[prompt]),
+            Ok(13)
+        );
+        assert_eq!(
+            call(&mut os, &mut ram, "Keyboard.readChar", &[]),
+            Ok('a' as i16)
+        );
+        let line = call(&mut os, &mut ram, "Keyboard.readLine", &[prompt]);
+        assert_eq!(line, Err(OsError::NoInput));
+        assert_eq!(os.output(), "n? 13\nan? b");
+    }
+
+    #[test]
+    fn it_draws_on_the_screen() {
+        let (mut os, mut ram) = (Os::default(), Ram::new());
+        call(&mut os, &mut ram, "Screen.drawRectangle", &[14, 1, 17, 2]).unwrap();
+        call(&mut os, &mut ram, "Screen.drawLine", &[0, 10, 3, 13]).unwrap();
+        call(&mut os, &mut ram, "Screen.setColor", &[0]).unwrap();
+        call(&mut os, &mut ram, "Screen.drawPixel", &[15, 2]).unwrap();
+        let word = |ram: &Ram, row: u16, col: u16| ram.read(SCREEN_MEM + row * 32 + col).unwrap();
+        assert_eq!((word(&ram, 1, 0), word(&ram, 1, 1)), (0xC000, 0x0003));
+        assert_eq!((word(&ram, 2, 0), word(&ram, 2, 1)), (0x4000, 0x0003));
+        assert_eq!(
+            (
+                word(&ram, 10, 0),
+                word(&ram, 11, 0),
+                word(&ram, 12, 0),
+                word(&ram, 13, 0)
+            ),
+            (1, 2, 4, 8)
+        );
+        call(&mut os, &mut ram, "Screen.clearScreen", &[]).unwrap();
+        assert_eq!(word(&ram, 1, 0), 0);
+    }
+
+    #[test]
+    fn it_halts_and_clips_circles() {
+        let (mut os, mut ram) = (Os::default(), Ram::new());
+        call(&mut os, &mut ram, "Screen.drawCircle", &[0, 0, 2]).unwrap();
+        assert_eq!(ram.read(SCREEN_MEM).unwrap(), 0b111);
+        assert_eq!(ram.read(SCREEN_MEM + 32).unwrap(), 0b11);
+        assert_matches!(
+            os.call(OsFunction::SysHalt, &[], &mut ram),
+            Ok(Native::Halt)
+        );
+    }
+}
@@ -0,0 +1,246 @@
+use std::{collections::HashMap, path::Path};
+
+use vm_translator::{
+    parser::{Arithmetic, Command, Flow, Goto, Location, Marker, ParsedCmd, Segment},
+    translator::{parse_inputs, TranslatorError},
+    validator::{findings, Problem, ValidationError},
+};
+
+use crate::os::{OsFunction, OsMode};
+
+pub(crate) const SP: u16 = 0;
+pub(crate) const LCL: u16 = 1;
+pub(crate) const ARG: u16 = 2;
+pub(crate) const THIS: u16 = 3;
+pub(crate) const THAT: u16 = 4;
+const TEMP_BASE: u16 = 5;
+const STATIC_BASE: u16 = 16;
+const STATIC_END: u16 = 256;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ProgramError {
+    #[error("{0}")]
+    Translator(#[from] TranslatorError),
+    #[error("{0}: {1} doesn't fit in the {} static cells", STATIC_END - STATIC_BASE)]
+    TooManyStatics(Location, String),
+    #[error("{0}: {1} is called with {2} arguments, but the OS function takes {3}")]
+    NativeArgCount(Location, String, u16, u16),
+}
+
+///Where a push reads from or a pop writes to
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Place {
+    ///A cell of its own, as static, temp and pointer cells are
+    Fixed(u16),
+    ///An index past the address held by a pointer, as local, argument, this and that are
+    Based(u16, u16),
+}
+
+///A VM command with its segment, labels and functions resolved, labels themselves leaving no
+///command behind
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Op {
+    Arithmetic(Arithmetic),
+    PushConstant(i16),
+    Push(Place),
+    Pop(Place),
+    Goto(usize),
+    IfGoto(usize),
+    IfZeroGoto(usize),
+    ///Calls the function starting at an index with a number of arguments
+    Call(usize, u16),
+    ///Calls an OS function built into the emulator
+    Native(OsFunction),
+    ///Starts a function with a number of locals
+    Function(u16),
+    Return,
+}
+
+///The commands of all modules in one sequence, as the translator lays them out
+#[derive(Debug)]
+pub struct Program {
+    ops: Vec<Op>,
+    locations: Vec<Location>,
+    functions: HashMap<String, usize>,
+    statics: Vec<String>,
+    os: OsMode,
+    warnings: Vec<ValidationError>,
+}
+
+impl Program {
+    ///Loads `.vm` files and directories of them in the order the translator would
+    pub fn load<P: AsRef<Path>>(inputs: &[P], os: OsMode) -> Result<Self, ProgramError> {
+        Self::from_sources(&parse_inputs(inputs)?, os)
+    }
+
+    ///Builds a program of modules, each named after its file, after validating them as the
+    ///translator does. With the native OS, calls to OS functions the modules don't define
+    ///go to the emulator's own
+    pub fn from_sources<S: AsRef<str>>(
+        sources: &[(S, Vec<Command>)],
+        os: OsMode,
+    ) -> Result<Self, ProgramError> {
+        let native = |name: &str| os == OsMode::Native && OsFunction::from_name(name).is_some();
+        let (warnings, errors): (Vec<_>, Vec<_>) = findings(sources)
+            .into_iter()
+            .filter(|finding| {
+                !matches!(&finding.problem, Problem::UndefinedFunction(name) if native(name))
+            })
+            .partition(|finding| finding.problem.is_warning());
+        if !errors.is_empty() {
+            return Err(TranslatorError::Validation(errors).into());
+        }
+        let mut program = Program {
+            ops: Vec::new(),
+            locations: Vec::new(),
+            functions: HashMap::new(),
+            statics: Vec::new(),
+            os,
+            warnings,
+        };
+        let mut labels = HashMap::new();
+        let mut jumps = Vec::new();
+        for (module, commands) in sources {
+            let module = module.as_ref();
+            let mut scope = module;
+            for command in commands {
+                let op = match command.parsed() {
+                    ParsedCmd::Noop => continue,
+                    ParsedCmd::Marker(Marker::Label(label)) => {
+                        labels.insert(format!("{}${}", scope, label), program.ops.len());
+                        continue;
+                    }
+                    ParsedCmd::Marker(Marker::Function(name, locals)) => {
+                        scope = name;
+                        program.functions.insert(name.clone(), program.ops.len());
+                        Op::Function(*locals)
+                    }
+                    ParsedCmd::Arithmetic(arithmetic) => Op::Arithmetic(*arithmetic),
+                    ParsedCmd::PushConstant(value) => Op::PushConstant(*value),
+                    ParsedCmd::Push(segment, idx) => {
+                        Op::Push(program.place(module, *segment, *idx, command.location())?)
+                    }
+                    ParsedCmd::Pop(segment, idx) => {
+                        Op::Pop(program.place(module, *segment, *idx, command.location())?)
+                    }
+                    ParsedCmd::Flow(Flow::Goto(goto, label)) => {
+                        jumps.push((program.ops.len(), format!("{}${}", scope, label)));
+                        match goto {
+                            Goto::Direct => Op::Goto(0),
+                            Goto::Conditional => Op::IfGoto(0),
+                            Goto::IfZero => Op::IfZeroGoto(0),
+                        }
+                    }
+                    ParsedCmd::Flow(Flow::Call(name, args)) => {
+                        jumps.push((program.ops.len(), name.clone()));
+                        Op::Call(0, *args)
+                    }
+                    ParsedCmd::Flow(Flow::Return) => Op::Return,
+                };
+                program.ops.push(op);
+                program.locations.push(command.location().clone());
+            }
+        }
+        //Every label and function jumped to is known to be defined, having been validated, or
+        //else to be an OS function
+        for (idx, target) in jumps {
+            match program.ops[idx] {
+                Op::Call(_, args) => {
+                    program.ops[idx] = match program.functions.get(&target) {
+                        Some(start) => Op::Call(*start, args),
+                        None => program.native(&target, args, idx)?,
+                    }
+                }
+                Op::Goto(ref mut to) | Op::IfGoto(ref mut to) | Op::IfZeroGoto(ref mut to) => {
+                    *to = labels[&target]
+                }
+                _ => {}
+            }
+        }
+        Ok(program)
+    }
+
+    fn native(&self, name: &str, args: u16, idx: usize) -> Result<Op, ProgramError> {
+        let function = OsFunction::from_name(name).expect("only OS functions may be undefined");
+        if function.args() != args {
+            let location = self.locations[idx].clone();
+            return Err(ProgramError::NativeArgCount(
+                location,
+                name.to_owned(),
+                args,
+                function.args(),
+            ));
+        }
+        Ok(Op::Native(function))
+    }
+
+    ///Statics get the cells from RAM[16] on in the order they first appear, as the assembler
+    ///allocates their variables
+    fn place(
+        &mut self,
+        module: &str,
+        segment: Segment,
+        idx: u16,
+        location: &Location,
+    ) -> Result<Place, ProgramError> {
+        Ok(match segment {
+            Segment::Local => Place::Based(LCL, idx),
+            Segment::Argument => Place::Based(ARG, idx),
+            Segment::This => Place::Based(THIS, idx),
+            Segment::That => Place::Based(THAT, idx),
+            Segment::Pointer => Place::Fixed(THIS + idx),
+            Segment::Temp => Place::Fixed(TEMP_BASE + idx),
+            Segment::Static => {
+                let name = format!("{}.{}", module, idx);
+                let offset = match self.statics.iter().position(|s| *s == name) {
+                    Some(offset) => offset,
+                    None if STATIC_BASE as usize + self.statics.len() >= STATIC_END as usize => {
+                        return Err(ProgramError::TooManyStatics(location.clone(), name))
+                    }
+                    None => {
+                        self.statics.push(name);
+                        self.statics.len() - 1
+                    }
+                };
+                Place::Fixed(STATIC_BASE + offset as u16)
+            }
+            Segment::Constant => unreachable!("pops to constant are rejected by the validator"),
+        })
+    }
+
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    ///Where the command at `idx` came from
+    pub fn location(&self, idx: usize) -> Option<&Location> {
+        self.locations.get(idx)
+    }
+
+    ///Where a function starts
+    pub fn function(&self, name: &str) -> Option<usize> {
+        self.functions.get(name).copied()
+    }
+
+    ///Where the bootstrap starts. Without a `Sys.init` of its own, the native OS starts at
+    ///`Main.main` as its `Sys.init` would, after which the program halts
+    pub fn entry(&self, name: &str) -> Option<usize> {
+        match self.function(name) {
+            None if self.os == OsMode::Native && name == "Sys.init" => self.function("Main.main"),
+            start => start,
+        }
+    }
+
+    ///What validation found that doesn't stop the program running, such as unreachable code
+    pub fn warnings(&self) -> &[ValidationError] {
+        &self.warnings
+    }
+
+    ///The statics, named as `File.i`, with their addresses
+    pub fn statics(&self) -> impl Iterator<Item = (&str, u16)> {
+        self.statics
+            .iter()
+            .enumerate()
+            .map(|(offset, name)| (name.as_str(), STATIC_BASE + offset as u16))
+    }
+}
@@ -0,0 +1,243 @@
+use vm_translator::parser::Command;
+
+use crate::differential::parse_module;
+
+const THIS_BASE: u16 = 3000;
+const THAT_BASE: u16 = 4000;
+///The cells of this, that and static that programs use
+const SEGMENT_SIZE: u16 = 8;
+///Temp 6 and 7 count down the loops of `Main.helper` and `Main.main`, so that calls made in a
+///loop leave its counter alone, and statements only use the temp cells below them
+const HELPER_LOOP_COUNTER: u16 = 6;
+const MAIN_LOOP_COUNTER: u16 = 7;
+const MAX_ARGS: u16 = 4;
+const MAX_LOCALS: u16 = 6;
+const MAX_EXPRESSION_DEPTH: u32 = 3;
+const MAX_STATEMENTS: u32 = 12;
+
+///A xorshift generator, so that a seed always gives the same program
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn chance(&mut self, percent: u64) -> bool {
+        self.below(100) < percent
+    }
+}
+
+struct Function {
+    args: u16,
+    locals: u16,
+    labels: usize,
+    loop_counter: u16,
+    may_call: Option<(String, u16)>,
+}
+
+///Builds the source of a function in the order it is generated
+struct Generator<'a> {
+    rng: &'a mut Rng,
+    function: Function,
+    lines: Vec<String>,
+    in_loop: bool,
+}
+
+impl Generator<'_> {
+    fn emit(&mut self, line: String) {
+        self.lines.push(line);
+    }
+
+    fn constant(&mut self) -> i16 {
+        match self.rng.below(6) {
+            0 => i16::MAX,
+            1 => i16::MIN,
+            2 => -1,
+            3 => self.rng.below(4) as i16,
+            _ => self.rng.next() as i16,
+        }
+    }
+
+    ///A cell a value can be popped to, leaving the pointers alone
+    fn writable(&mut self) -> String {
+        loop {
+            let (segment, size) = match self.rng.below(6) {
+                0 => ("local", self.function.locals),
+                1 => ("argument", self.function.args),
+                2 => ("this", SEGMENT_SIZE),
+                3 => ("that", SEGMENT_SIZE),
+                4 => ("static", SEGMENT_SIZE),
+                _ => ("temp", HELPER_LOOP_COUNTER),
+            };
+            if size > 0 {
+                return format!("{} {}", segment, self.rng.below(size as u64));
+            }
+        }
+    }
+
+    fn readable(&mut self) -> String {
+        if self.rng.chance(10) {
+            format!("pointer {}", self.rng.below(2))
+        } else {
+            self.writable()
+        }
+    }
+
+    ///Pushes exactly one value
+    fn expression(&mut self, depth: u32) {
+        let leaf = depth >= MAX_EXPRESSION_DEPTH || self.rng.chance(40);
+        if leaf {
+            if self.rng.chance(50) {
+                let value = self.constant();
+                self.emit(format!("push constant {}", value));
+            } else {
+                let cell = self.readable();
+                self.emit(format!("push {}", cell));
+            }
+        } else if self.rng.chance(25) {
+            self.expression(depth + 1);
+            let op = ["neg", "not"][self.rng.below(2) as usize];
+            self.emit(op.to_owned());
+        } else if let Some((callee, args)) = self
+            .function
+            .may_call
+            .clone()
+            .filter(|_| self.rng.chance(20))
+        {
+            for _ in 0..args {
+                self.expression(depth + 1);
+            }
+            self.emit(format!("call {} {}", callee, args));
+        } else {
+            self.expression(depth + 1);
+            self.expression(depth + 1);
+            let ops = ["add", "sub", "and", "or", "eq", "gt", "lt"];
+            let op = ops[self.rng.below(ops.len() as u64) as usize];
+            self.emit(op.to_owned());
+        }
+    }
+
+    fn label(&mut self) -> String {
+        self.function.labels += 1;
+        format!("L{}", self.function.labels)
+    }
+
+    ///Leaves the stack as it found it
+    fn statements(&mut self, count: u32) {
+        for _ in 0..count {
+            match self.rng.below(10) {
+                0 | 1 => {
+                    let skip = self.label();
+                    self.expression(1);
+                    self.emit(format!("if-goto {}", skip));
+                    let count = self.rng.below(3) as u32 + 1;
+                    self.statements(count);
+                    self.emit(format!("label {}", skip));
+                }
+                2 if !self.in_loop => {
+                    let top = self.label();
+                    let counter = self.function.loop_counter;
+                    let iterations = self.rng.below(4) + 1;
+                    self.emit(format!("push constant {}", iterations));
+                    self.emit(format!("pop temp {}", counter));
+                    self.emit(format!("label {}", top));
+                    self.in_loop = true;
+                    let count = self.rng.below(3) as u32 + 1;
+                    self.statements(count);
+                    self.in_loop = false;
+                    for line in [
+                        format!("push temp {}", counter),
+                        "push constant 1".to_owned(),
+                        "sub".to_owned(),
+                        format!("pop temp {}", counter),
+                        format!("push temp {}", counter),
+                        format!("if-goto {}", top),
+                    ] {
+                        self.emit(line);
+                    }
+                }
+                _ => {
+                    self.expression(1);
+                    let cell = self.writable();
+                    self.emit(format!("pop {}", cell));
+                }
+            }
+        }
+    }
+}
+
+fn function(rng: &mut Rng, name: &str, function: Function) -> String {
+    let header = format!("function {} {}", name, function.locals);
+    let mut generator = Generator {
+        rng,
+        function,
+        lines: vec![header],
+        in_loop: false,
+    };
+    let count = generator.rng.below(MAX_STATEMENTS as u64) as u32 + 1;
+    generator.statements(count);
+    generator.expression(1);
+    generator.emit("return".to_owned());
+    generator.lines.join("\n") + "\n"
+}
+
+///Generates a program that always halts, keeps its stack balanced and only touches memory it
+///owns: `Sys.init` points this and that at their own areas and calls `Main.main`, which may call
+///`Main.helper`, each running random statements with forward branches and bounded loops over
+///random expressions
+pub fn random_program(seed: u64) -> Vec<(String, Vec<Command>)> {
+    let mut rng = Rng::new(seed);
+    let helper_args = rng.below(MAX_ARGS as u64 + 1) as u16;
+    let locals = rng.below(MAX_LOCALS as u64 + 1) as u16;
+    let helper = function(
+        &mut rng,
+        "Main.helper",
+        Function {
+            args: helper_args,
+            locals,
+            labels: 0,
+            loop_counter: HELPER_LOOP_COUNTER,
+            may_call: None,
+        },
+    );
+    let main_args = rng.below(MAX_ARGS as u64 + 1) as u16;
+    let locals = rng.below(MAX_LOCALS as u64 + 1) as u16;
+    let main = function(
+        &mut rng,
+        "Main.main",
+        Function {
+            args: main_args,
+            locals,
+            labels: 0,
+            loop_counter: MAIN_LOOP_COUNTER,
+            may_call: Some(("Main.helper".to_owned(), helper_args)),
+        },
+    );
+    let mut sys = format!(
+        "function Sys.init 0\npush constant {}\npop pointer 0\npush constant {}\npop pointer 1\n",
+        THIS_BASE, THAT_BASE
+    );
+    for _ in 0..main_args {
+        sys += &format!("push constant {}\n", rng.next() as i16);
+    }
+    sys += &format!(
+        "call Main.main {}\npop temp 0\nlabel END\ngoto END\n",
+        main_args
+    );
+    [("Sys", sys), ("Main", main + &helper)]
+        .iter()
+        .map(|(name, source)| parse_module(name, source).expect("generated programs parse"))
+        .collect()
+}
@@ -0,0 +1,549 @@
+use hack_emulator::{MemoryError, Ram};
+use vm_translator::{
+    parser::{Arithmetic, Location},
+    translator::{Bootstrap, BootstrapMode},
+};
+
+use crate::{
+    os::{Native, Os, OsError},
+    program::{Op, Place, Program, ARG, LCL, SP, THAT, THIS},
+};
+
+///The cells a call saves below its callee's locals: the return address, LCL, ARG, THIS and THAT
+const FRAME_SIZE: i16 = 5;
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum VmError {
+    #[error("memory error: {0}")]
+    Memory(#[from] MemoryError),
+    #[error("return to {0}, which is outside of the program")]
+    InvalidReturn(i16),
+    #[error("stack underflow into the frame of the current function")]
+    StackUnderflow,
+    #[error("{0}")]
+    Os(#[from] OsError),
+    #[error("no {0} function is defined to bootstrap")]
+    NoEntryFunction(String),
+    #[error("{0}: {1}")]
+    Command(Location, Box<VmError>),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RunOutcome {
+    Halted,
+    StepLimit,
+}
+
+///Runs VM programs a command at a time, keeping the stack, segments and frames in RAM exactly
+///where the translated program would
+pub struct Vm {
+    program: Program,
+    ram: Ram,
+    pc: usize,
+    steps: u64,
+    ///Per running function, the stack pointer once its locals were pushed, below which nothing
+    ///may be popped
+    floors: Vec<u16>,
+    os: Os,
+}
+
+impl Vm {
+    pub fn new(program: Program) -> Self {
+        Self {
+            program,
+            ram: Ram::new(),
+            pc: 0,
+            steps: 0,
+            floors: Vec::new(),
+            os: Os::default(),
+        }
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    pub fn ram(&self) -> &Ram {
+        &self.ram
+    }
+
+    pub fn ram_mut(&mut self) -> &mut Ram {
+        &mut self.ram
+    }
+
+    ///The native OS, whether or not the program calls it
+    pub fn os(&self) -> &Os {
+        &self.os
+    }
+
+    pub fn os_mut(&mut self) -> &mut Os {
+        &mut self.os
+    }
+
+    ///The index of the next command to run
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    ///Sets up the pointers and calls the entry function as the translator's bootstrap code does,
+    ///when it would emit any. Returning from the entry function halts. This isn't counted as a
+    ///step, so step counts match the VM emulator of the course, which starts in `Sys.init`
+    pub fn bootstrap(&mut self, bootstrap: &Bootstrap) -> Result<(), VmError> {
+        let entry = match (self.program.entry(&bootstrap.entry), bootstrap.mode) {
+            (_, BootstrapMode::Never) | (None, BootstrapMode::Auto) => return Ok(()),
+            (None, BootstrapMode::Always) => {
+                return Err(VmError::NoEntryFunction(bootstrap.entry.clone()))
+            }
+            (Some(entry), _) => entry,
+        };
+        let pointers = [
+            (SP, Some(bootstrap.sp)),
+            (LCL, bootstrap.lcl),
+            (ARG, bootstrap.arg),
+            (THIS, bootstrap.this),
+            (THAT, bootstrap.that),
+        ];
+        for (pointer, addr) in pointers {
+            if let Some(addr) = addr {
+                self.write(pointer, addr as i16)?;
+            }
+        }
+        self.call(self.program.ops().len(), 0)?;
+        self.pc = entry;
+        Ok(())
+    }
+
+    ///Whether the program ran off its end or is stuck in a `goto` to itself, which is how VM
+    ///programs halt
+    pub fn is_halted(&self) -> bool {
+        match self.program.ops().get(self.pc) {
+            None => true,
+            Some(Op::Goto(target)) => *target == self.pc,
+            Some(_) => false,
+        }
+    }
+
+    pub fn step(&mut self) -> Result<(), VmError> {
+        if self.is_halted() {
+            return Ok(());
+        }
+        let op = self.program.ops()[self.pc];
+        self.pc = self.execute(op).map_err(|err| {
+            let location = self.program.location(self.pc).cloned().unwrap_or_default();
+            VmError::Command(location, Box::new(err))
+        })?;
+        self.steps += 1;
+        Ok(())
+    }
+
+    pub fn run(&mut self, max_steps: u64) -> Result<RunOutcome, VmError> {
+        for _ in 0..max_steps {
+            if self.is_halted() {
+                return Ok(RunOutcome::Halted);
+            }
+            self.step()?;
+        }
+        Ok(if self.is_halted() {
+            RunOutcome::Halted
+        } else {
+            RunOutcome::StepLimit
+        })
+    }
+
+    ///Runs `op`, returning the index of the next command
+    fn execute(&mut self, op: Op) -> Result<usize, VmError> {
+        let next = self.pc + 1;
+        match op {
+            Op::Arithmetic(arithmetic) => {
+                let result = match arithmetic {
+                    Arithmetic::Add => self.binary(i16::wrapping_add)?,
+                    Arithmetic::Sub => self.binary(i16::wrapping_sub)?,
+                    Arithmetic::And => self.binary(|x, y| x & y)?,
+                    Arithmetic::Or => self.binary(|x, y| x | y)?,
+                    Arithmetic::Eq => self.binary(|x, y| truth(x == y))?,
+                    Arithmetic::Gt => self.binary(|x, y| truth(x > y))?,
+                    Arithmetic::Lt => self.binary(|x, y| truth(x < y))?,
+                    Arithmetic::Neg => self.pop()?.wrapping_neg(),
+                    Arithmetic::Not => !self.pop()?,
+                    Arithmetic::Inc => self.pop()?.wrapping_add(1),
+                    Arithmetic::Dec => self.pop()?.wrapping_sub(1),
+                };
+                self.push(result)?;
+            }
+            Op::PushConstant(value) => self.push(value)?,
+            Op::Push(place) => {
+                let value = self.read(self.address(place)?)?;
+                self.push(value)?;
+            }
+            Op::Pop(place) => {
+                let value = self.pop()?;
+                self.write(self.address(place)?, value)?;
+            }
+            Op::Goto(target) => return Ok(target),
+            Op::IfGoto(target) if self.pop()? != 0 => return Ok(target),
+            Op::IfZeroGoto(target) if self.pop()? == 0 => return Ok(target),
+            Op::IfGoto(_) | Op::IfZeroGoto(_) => {}
+            Op::Call(start, args) => {
+                self.call(next, args)?;
+                return Ok(start);
+            }
+            Op::Native(function) => {
+                let mut args = vec![0; function.args() as usize];
+                for arg in args.iter_mut().rev() {
+                    *arg = self.pop()?;
+                }
+                match self.os.call(function, &args, &mut self.ram)? {
+                    Native::Return(value) => self.push(value)?,
+                    Native::Halt => return Ok(self.program.ops().len()),
+                }
+            }
+            Op::Function(locals) => {
+                for _ in 0..locals {
+                    self.push(0)?;
+                }
+                let floor = self.read(SP)?;
+                self.floors.push(floor as u16);
+            }
+            Op::Return => return self.return_from_function(),
+        }
+        Ok(next)
+    }
+
+    ///Saves the caller's frame and points ARG at the `args` arguments pushed before it
+    fn call(&mut self, ret: usize, args: u16) -> Result<(), VmError> {
+        self.push(ret as i16)?;
+        for pointer in [LCL, ARG, THIS, THAT] {
+            let value = self.read(pointer)?;
+            self.push(value)?;
+        }
+        let sp = self.read(SP)?;
+        self.write(ARG, sp.wrapping_sub(FRAME_SIZE + args as i16))?;
+        self.write(LCL, sp)
+    }
+
+    ///Leaves the return value where the first argument was and restores the caller's frame,
+    ///returning the index to carry on from
+    fn return_from_function(&mut self) -> Result<usize, VmError> {
+        let frame = self.read(LCL)?;
+        let ret = self.read(frame.wrapping_sub(FRAME_SIZE) as u16)?;
+        let value = self.pop()?;
+        self.floors.pop();
+        let arg = self.read(ARG)?;
+        self.write(arg as u16, value)?;
+        self.write(SP, arg.wrapping_add(1))?;
+        for (offset, pointer) in [(1, THAT), (2, THIS), (3, ARG), (4, LCL)] {
+            let value = self.read(frame.wrapping_sub(offset) as u16)?;
+            self.write(pointer, value)?;
+        }
+        match usize::try_from(ret) {
+            Ok(ret) if ret <= self.program.ops().len() => Ok(ret),
+            _ => Err(VmError::InvalidReturn(ret)),
+        }
+    }
+
+    fn address(&self, place: Place) -> Result<u16, VmError> {
+        Ok(match place {
+            Place::Fixed(addr) => addr,
+            Place::Based(pointer, idx) => (self.read(pointer)? as u16).wrapping_add(idx),
+        })
+    }
+
+    ///Pops y then x, pushing nothing
+    fn binary(&mut self, op: fn(i16, i16) -> i16) -> Result<i16, VmError> {
+        let y = self.pop()?;
+        let x = self.pop()?;
+        Ok(op(x, y))
+    }
+
+    fn push(&mut self, value: i16) -> Result<(), VmError> {
+        let sp = self.read(SP)?;
+        self.write(sp as u16, value)?;
+        self.write(SP, sp.wrapping_add(1))
+    }
+
+    fn pop(&mut self) -> Result<i16, VmError> {
+        let sp = self.read(SP)?.wrapping_sub(1);
+        if matches!(self.floors.last(), Some(floor) if (sp as u16) < *floor) {
+            return Err(VmError::StackUnderflow);
+        }
+        self.write(SP, sp)?;
+        self.read(sp as u16)
+    }
+
+    fn read(&self, addr: u16) -> Result<i16, VmError> {
+        Ok(self.ram.read(addr)? as i16)
+    }
+
+    fn write(&mut self, addr: u16, value: i16) -> Result<(), VmError> {
+        Ok(self.ram.write(addr, value as u16)?)
+    }
+}
+
+fn truth(condition: bool) -> i16 {
+    if condition {
+        -1
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, io::BufReader, path::Path};
+
+    use assert_matches::assert_matches;
+    use hack_emulator::RamDump;
+    use test_case::test_case;
+    use vm_translator::{parser::Parser, translator::TranslatorError};
+
+    use super::*;
+    use crate::{
+        os::{OsError, OsMode},
+        program::ProgramError,
+    };
+
+    fn program(source: &str, os: OsMode) -> Result<Program, ProgramError> {
+        let commands = Parser::new("Main.vm", BufReader::new(source.as_bytes()))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        Program::from_sources(&[("Main", commands)], os)
+    }
+
+    fn load(source: &str) -> Vm {
+        Vm::new(program(source, OsMode::Vm).unwrap())
+    }
+
+    ///The addresses and values of the single output line of a `.cmp` file
+    fn expected_ram(cmp: &Path) -> Vec<(u16, i16)> {
+        let cmp = fs::read_to_string(cmp).unwrap();
+        let mut lines = cmp.lines();
+        let cells = |line: &str| -> Vec<String> {
+            line.split('|')
+                .map(|cell| cell.trim().to_owned())
+                .filter(|cell| !cell.is_empty())
+                .collect()
+        };
+        let addrs = cells(lines.next().unwrap());
+        let values = cells(lines.next().unwrap());
+        addrs
+            .iter()
+            .zip(values)
+            .map(|(addr, value)| {
+                let addr = addr.trim_start_matches("RAM[").trim_end_matches(']');
+                (addr.parse().unwrap(), value.parse().unwrap())
+            })
+            .collect()
+    }
+
+    const SIMPLE_FUNCTION: [(u16, i16); 12] = [
+        (0, 317),
+        (1, 317),
+        (2, 310),
+        (3, 3000),
+        (4, 4000),
+        (310, 1234),
+        (311, 37),
+        (312, 9),
+        (313, 305),
+        (314, 300),
+        (315, 3010),
+        (316, 4010),
+    ];
+
+    #[test_case("07/StackArithmetic/SimpleAdd", &[(0, 256)], 3)]
+    #[test_case("07/StackArithmetic/StackTest", &[(0, 256)], 38)]
+    #[test_case("07/MemoryAccess/BasicTest", &[(0, 256), (1, 300), (2, 400), (3, 3000), (4, 3010)], 25)]
+    #[test_case("07/MemoryAccess/PointerTest", &[(0, 256)], 15)]
+    #[test_case("07/MemoryAccess/StaticTest", &[(0, 256)], 11)]
+    #[test_case("08/ProgramFlow/BasicLoop", &[(0, 256), (1, 300), (2, 400), (400, 3)], 33)]
+    #[test_case("08/ProgramFlow/FibonacciSeries", &[(0, 256), (1, 300), (2, 400), (400, 6), (401, 3000)], 73)]
+    #[test_case("08/FunctionCalls/SimpleFunction", &SIMPLE_FUNCTION, 10)]
+    #[test_case("08/FunctionCalls/NestedCall", &[(3, 3000), (4, 4000)], 50)]
+    #[test_case("08/FunctionCalls/FibonacciElement", &[], 110)]
+    #[test_case("08/FunctionCalls/StaticsTest", &[], 36)]
+    fn it_passes_the_course_tests(dir: &str, ram: &[(u16, i16)], steps: u64) {
+        let dir = Path::new("../..").join(dir);
+        let mut vm = Vm::new(Program::load(&[&dir], OsMode::Vm).unwrap());
+        for (addr, value) in ram {
+            vm.ram_mut().write(*addr, *value as u16).unwrap();
+        }
+        //NestedCall checks that locals are zeroed over what was on the stack
+        for addr in 261..300 {
+            vm.ram_mut().write(addr, u16::MAX).unwrap();
+        }
+        vm.bootstrap(&Bootstrap::default()).unwrap();
+        vm.run(steps).unwrap();
+        let name = dir.file_name().unwrap().to_str().unwrap();
+        for (addr, value) in expected_ram(&dir.join(name).with_extension("cmp")) {
+            assert_eq!(vm.ram().read(addr).unwrap() as i16, value, "RAM[{}]", addr);
+        }
+    }
+
+    #[test]
+    fn it_dumps_ram_like_the_cmp_files() {
+        let dir = Path::new("../../07/MemoryAccess/BasicTest");
+        let mut vm = Vm::new(Program::load(&[dir], OsMode::Vm).unwrap());
+        for (addr, value) in [(0, 256), (1, 300), (2, 400), (3, 3000), (4, 3010)] {
+            vm.ram_mut().write(addr, value).unwrap();
+        }
+        assert_eq!(vm.run(1000).unwrap(), RunOutcome::Halted);
+        assert_eq!(vm.steps(), 25);
+        let dump = RamDump::new(vm.ram(), [256, 300, 401, 402, 3006, 3012, 3015, 11]).unwrap();
+        assert_eq!(
+            dump.to_string(),
+            fs::read_to_string(dir.join("BasicTest.cmp")).unwrap()
+        );
+    }
+
+    #[test_case(i16::MAX, i16::MIN, -1, 0; "max and min")]
+    #[test_case(-20000, 20000, 0, -1; "far apart")]
+    #[test_case(5, 5, 0, 0; "equal")]
+    fn it_compares_without_overflowing(x: i16, y: i16, gt: i16, lt: i16) {
+        let mut vm = load(&format!(
+            "push constant {x}\npush constant {y}\ngt\npush constant {x}\npush constant {y}\nlt\n"
+        ));
+        vm.ram_mut().write(SP, 256).unwrap();
+        vm.run(6).unwrap();
+        assert_eq!(vm.ram().read(256).unwrap() as i16, gt);
+        assert_eq!(vm.ram().read(257).unwrap() as i16, lt);
+    }
+
+    #[test]
+    fn it_halts_on_returning_from_the_entry_function() {
+        let mut vm = load("function Sys.init 0\npush constant 7\nreturn\n");
+        vm.bootstrap(&Bootstrap::default()).unwrap();
+        assert_eq!(vm.ram().read(SP).unwrap(), 261);
+        assert_eq!(vm.run(10).unwrap(), RunOutcome::Halted);
+        assert_eq!(vm.steps(), 3);
+        assert_eq!(vm.ram().read(256).unwrap(), 7);
+        assert_eq!(vm.ram().read(SP).unwrap(), 257);
+    }
+
+    #[test]
+    fn it_stops_at_the_step_limit() {
+        let mut vm = load("label LOOP\npush constant 1\nif-goto LOOP\n");
+        vm.ram_mut().write(SP, 256).unwrap();
+        assert_eq!(vm.run(100).unwrap(), RunOutcome::StepLimit);
+        assert_eq!(vm.steps(), 100);
+        assert_eq!(vm.pc(), 0);
+    }
+
+    #[test]
+    fn it_only_bootstraps_an_entry_function_that_is_defined() {
+        let mut vm = load("push constant 1\n");
+        vm.bootstrap(&Bootstrap::default()).unwrap();
+        assert_eq!(vm.ram().read(SP).unwrap(), 0);
+        let always = Bootstrap {
+            mode: BootstrapMode::Always,
+            ..Bootstrap::default()
+        };
+        assert_eq!(
+            vm.bootstrap(&always),
+            Err(VmError::NoEntryFunction("Sys.init".to_owned()))
+        );
+    }
+
+    #[test]
+    fn it_stops_functions_popping_into_their_frame() {
+        let mut vm = load("function Sys.init 1\npush constant 1\nadd\nlabel END\ngoto END\n");
+        vm.bootstrap(&Bootstrap::default()).unwrap();
+        assert_matches!(vm.run(3), Err(VmError::Command(location, err)) => {
+            assert_eq!(location.to_string(), "Main.vm:3");
+            assert_eq!(*err, VmError::StackUnderflow);
+        });
+    }
+
+    #[test]
+    fn it_locates_runtime_errors() {
+        let mut vm = load("push constant 1\nreturn\n");
+        vm.ram_mut().write(SP, 256).unwrap();
+        vm.ram_mut().write(LCL, 300).unwrap();
+        vm.ram_mut().write(295, 999).unwrap();
+        assert_matches!(vm.run(2), Err(VmError::Command(location, err)) => {
+            assert_eq!(location.to_string(), "Main.vm:2");
+            assert_eq!(*err, VmError::InvalidReturn(999));
+        });
+    }
+
+    const HELLO: &str = "function Main.main 0\npush constant 2\ncall String.new 1\n\
+        push constant 72\ncall String.appendChar 2\npush constant 105\ncall String.appendChar 2\n\
+        call Output.printString 1\npop temp 0\npush constant 6\npush constant 7\n\
+        call Math.multiply 2\npop static 0\ncall Sys.halt 0\npop temp 0\npush constant 0\nreturn\n";
+
+    #[test]
+    fn it_runs_programs_on_the_native_os_from_main() {
+        let mut vm = Vm::new(program(HELLO, OsMode::Native).unwrap());
+        vm.bootstrap(&Bootstrap::default()).unwrap();
+        assert_eq!(vm.run(100).unwrap(), RunOutcome::Halted);
+        assert_eq!(vm.steps(), 14);
+        assert_eq!(vm.os().output(), "Hi");
+        assert_eq!(vm.ram().read(16).unwrap(), 42);
+        assert_eq!(vm.ram().read(2048).unwrap(), 2);
+    }
+
+    #[test]
+    fn it_prefers_the_programs_own_os_functions() {
+        let source = format!(
+            "{}function Math.multiply 0\npush constant 99\nreturn\n",
+            HELLO
+        );
+        let mut vm = Vm::new(program(&source, OsMode::Native).unwrap());
+        vm.bootstrap(&Bootstrap::default()).unwrap();
+        vm.run(100).unwrap();
+        assert_eq!(vm.ram().read(16).unwrap(), 99);
+    }
+
+    #[test]
+    fn it_only_links_the_native_os_when_asked_to() {
+        assert_matches!(
+            program(HELLO, OsMode::Vm),
+            Err(ProgramError::Translator(TranslatorError::Validation(errors))) => {
+                assert_eq!(errors.len(), 6);
+            }
+        );
+        assert_matches!(
+            program("push constant 1\npush constant 2\ncall Math.abs 2\n", OsMode::Native),
+            Err(ProgramError::NativeArgCount(location, name, 2, 1)) => {
+                assert_eq!(location.to_string(), "Main.vm:3");
+                assert_eq!(name, "Math.abs");
+            }
+        );
+    }
+
+    #[test]
+    fn it_runs_code_the_jack_compiler_writes_with_warnings() {
+        let source = "function Main.main 0\npush constant 5\nneg\ncall Main.sign 1\npop static 0\n\
+            call Sys.halt 0\npop temp 0\npush constant 0\nreturn\n\
+            function Main.sign 0\npush argument 0\ncall Math.abs 1\npush constant 0\ngt\n\
+            if-goto IF_TRUE0\ngoto IF_FALSE0\nlabel IF_TRUE0\npush constant 1\nreturn\n\
+            goto IF_END0\nlabel IF_FALSE0\npush constant 0\nreturn\nlabel IF_END0\n";
+        let program = program(source, OsMode::Native).unwrap();
+        let warnings: Vec<_> = program.warnings().iter().map(ToString::to_string).collect();
+        assert_eq!(
+            warnings,
+            [
+                "Main.vm:20: unreachable code",
+                "Main.vm:24: function Main.sign falls through without returning",
+            ]
+        );
+        let mut vm = Vm::new(program);
+        vm.bootstrap(&Bootstrap::default()).unwrap();
+        assert_eq!(vm.run(100).unwrap(), RunOutcome::Halted);
+        assert_eq!(vm.ram().read(16).unwrap(), 1);
+    }
+
+    #[test]
+    fn it_locates_os_errors() {
+        let source = "push constant 1\npush constant 0\ncall Math.divide 2\n";
+        let mut vm = Vm::new(program(source, OsMode::Native).unwrap());
+        vm.ram_mut().write(SP, 256).unwrap();
+        assert_matches!(vm.run(3), Err(VmError::Command(location, err)) => {
+            assert_eq!(location.to_string(), "Main.vm:3");
+            assert_eq!(*err, VmError::Os(OsError::Sys(3)));
+        });
+    }
+}
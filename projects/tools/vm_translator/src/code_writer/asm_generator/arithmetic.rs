@@ -2,10 +2,13 @@ use crate::{code_writer::label_manager::LabelManager, parser::Arithmetic};
 
 use super::{
     flatten,
-    flow::{jmp, JmpCmd},
+    instruction::{compute, jump, Comp, Dest, Instruction, Jump},
     label,
-    register::{set_a_reg_to_pointer, set_alias, CmpVal},
-    stack::{dec_stack_pointer, inc_stack_pointer, pop_and_prep_stack, push_d_reg_to_stack},
+    register::{set_a_reg_to_pointer, set_alias},
+    stack::{
+        address_stack_top, dec_stack_pointer, inc_stack_pointer, pop_and_prep_stack,
+        push_d_reg_to_stack, take_stack_top, SEGMENT_STACK,
+    },
 };
 
 #[derive(Debug)]
@@ -15,62 +18,179 @@ enum Cmp {
     Gt,
 }
 
-const ADD_SYMBOL: char = '+';
-const NEG_SYMBOL: char = '-';
-const NOT_SYMBOL: char = '!';
-const AND_SYMBOL: char = '&';
-const OR_SYMBOL: char = '|';
+///The binary operations on x in M and y in D
+const ADD: Comp = Comp::DPlusM;
+const SUB: Comp = Comp::MMinusD;
+const AND: Comp = Comp::DAndM;
+const OR: Comp = Comp::DOrM;
 
-pub(crate) fn arithmetic(arr: Arithmetic, label_manager: &mut LabelManager) -> Vec<String> {
+pub(crate) fn arithmetic(
+    arr: Arithmetic,
+    label_manager: &mut LabelManager,
+    overflow_safe: bool,
+) -> Vec<Instruction> {
     match arr {
-        Arithmetic::Add => bin_math_to_asm(ADD_SYMBOL),
-        Arithmetic::Sub => bin_math_to_asm(NEG_SYMBOL),
-        Arithmetic::And => bin_math_to_asm(AND_SYMBOL),
-        Arithmetic::Or => bin_math_to_asm(OR_SYMBOL),
-        Arithmetic::Neg => uni_math_to_asm(NEG_SYMBOL),
-        Arithmetic::Not => uni_math_to_asm(NOT_SYMBOL),
-        Arithmetic::Eq => cmp_math_to_asm(Cmp::Eq, label_manager),
-        Arithmetic::Gt => cmp_math_to_asm(Cmp::Gt, label_manager),
-        Arithmetic::Lt => cmp_math_to_asm(Cmp::Lt, label_manager),
+        Arithmetic::Add => bin_math_to_asm(ADD),
+        Arithmetic::Sub => bin_math_to_asm(SUB),
+        Arithmetic::And => bin_math_to_asm(AND),
+        Arithmetic::Or => bin_math_to_asm(OR),
+        Arithmetic::Neg => uni_math_to_asm(Comp::NegM),
+        Arithmetic::Not => uni_math_to_asm(Comp::NotM),
+        Arithmetic::Eq => cmp_math_to_asm(Cmp::Eq, label_manager, overflow_safe),
+        Arithmetic::Gt => cmp_math_to_asm(Cmp::Gt, label_manager, overflow_safe),
+        Arithmetic::Lt => cmp_math_to_asm(Cmp::Lt, label_manager, overflow_safe),
+        Arithmetic::Inc => step_in_place(Comp::MPlusOne),
+        Arithmetic::Dec => step_in_place(Comp::MMinusOne),
     }
 }
 
-fn bin_math_to_asm(symbol: char) -> Vec<String> {
+fn step_in_place(step: Comp) -> Vec<Instruction> {
+    flatten(vec![
+        set_alias(SEGMENT_STACK),
+        vec![compute(Dest::A, Comp::MMinusOne), compute(Dest::M, step)],
+    ])
+}
+
+///The stack caching form, where the top of the stack is in D and the result is left there
+pub(crate) fn arithmetic_on_d_reg(
+    arr: Arithmetic,
+    label_manager: &mut LabelManager,
+    overflow_safe: bool,
+) -> Vec<Instruction> {
+    match arr {
+        Arithmetic::Add => bin_math_on_d_reg(ADD),
+        Arithmetic::Sub => bin_math_on_d_reg(SUB),
+        Arithmetic::And => bin_math_on_d_reg(AND),
+        Arithmetic::Or => bin_math_on_d_reg(OR),
+        Arithmetic::Neg => vec![compute(Dest::D, Comp::NegD)],
+        Arithmetic::Not => vec![compute(Dest::D, Comp::NotD)],
+        Arithmetic::Eq => cmp_on_d_reg(Cmp::Eq, label_manager, overflow_safe),
+        Arithmetic::Gt => cmp_on_d_reg(Cmp::Gt, label_manager, overflow_safe),
+        Arithmetic::Lt => cmp_on_d_reg(Cmp::Lt, label_manager, overflow_safe),
+        Arithmetic::Inc => vec![compute(Dest::D, Comp::DPlusOne)],
+        Arithmetic::Dec => vec![compute(Dest::D, Comp::DMinusOne)],
+    }
+}
+
+fn bin_math_on_d_reg(op: Comp) -> Vec<Instruction> {
+    flatten(vec![address_stack_top(), vec![compute(Dest::D, op)]])
+}
+
+fn cmp_on_d_reg(
+    cmp: Cmp,
+    label_manager: &mut LabelManager,
+    overflow_safe: bool,
+) -> Vec<Instruction> {
+    let difference = match cmp {
+        Cmp::Gt | Cmp::Lt if overflow_safe => flatten(vec![
+            push_d_reg_to_stack(),
+            generated_signed_difference(label_manager),
+        ]),
+        _ => bin_math_on_d_reg(SUB),
+    };
+    let true_lbl = label_manager.generate_static();
+    let false_lbl = label_manager.generate_static();
+    flatten(vec![
+        difference,
+        set_alias(&true_lbl),
+        vec![jump(Comp::D, cmp_jump(cmp))],
+        vec![compute(Dest::D, Comp::Zero)],
+        set_alias(&false_lbl),
+        vec![jump(Comp::Zero, Jump::Jmp)],
+        label(&true_lbl),
+        vec![compute(Dest::D, Comp::MinusOne)],
+        label(&false_lbl),
+    ])
+}
+
+///Pops y then x, leaving in D a value with the sign of x - y. That subtraction overflows only
+///when x and y differ in sign, and then the sign of y alone decides, so D is set to -1 or 1
+pub(super) fn signed_difference(
+    y_neg_lbl: &str,
+    same_sign_lbl: &str,
+    done_lbl: &str,
+) -> Vec<Instruction> {
+    flatten(vec![
+        take_stack_top(),
+        set_alias(y_neg_lbl),
+        vec![jump(Comp::D, Jump::Jlt)],
+        take_stack_top(),
+        set_alias(same_sign_lbl),
+        vec![jump(Comp::D, Jump::Jge)],
+        vec![compute(Dest::D, Comp::MinusOne)],
+        set_alias(done_lbl),
+        vec![jump(Comp::Zero, Jump::Jmp)],
+        label(y_neg_lbl),
+        take_stack_top(),
+        set_alias(same_sign_lbl),
+        vec![jump(Comp::D, Jump::Jlt)],
+        vec![compute(Dest::D, Comp::One)],
+        set_alias(done_lbl),
+        vec![jump(Comp::Zero, Jump::Jmp)],
+        label(same_sign_lbl),
+        set_alias(SEGMENT_STACK),
+        vec![
+            compute(Dest::A, Comp::MPlusOne),
+            compute(Dest::D, Comp::DMinusM),
+        ],
+        label(done_lbl),
+    ])
+}
+
+fn generated_signed_difference(label_manager: &mut LabelManager) -> Vec<Instruction> {
+    let y_neg_lbl = label_manager.generate_static();
+    let same_sign_lbl = label_manager.generate_static();
+    let done_lbl = label_manager.generate_static();
+    signed_difference(&y_neg_lbl, &same_sign_lbl, &done_lbl)
+}
+
+fn cmp_jump(cmp: Cmp) -> Jump {
+    match cmp {
+        Cmp::Eq => Jump::Jeq,
+        Cmp::Lt => Jump::Jlt,
+        Cmp::Gt => Jump::Jgt,
+    }
+}
+
+fn bin_math_to_asm(op: Comp) -> Vec<Instruction> {
     flatten(vec![
         pop_and_prep_stack(),
-        vec![format!("M=M{}D", symbol)],
+        vec![compute(Dest::M, op)],
         inc_stack_pointer(),
     ])
 }
 
-fn uni_math_to_asm(symbol: char) -> Vec<String> {
+fn uni_math_to_asm(op: Comp) -> Vec<Instruction> {
     flatten(vec![
         dec_stack_pointer(),
         set_a_reg_to_pointer(),
-        vec![format!("M={}M", symbol)],
+        vec![compute(Dest::M, op)],
         inc_stack_pointer(),
     ])
 }
 
-fn cmp_math_to_asm(cmp: Cmp, label_manager: &mut LabelManager) -> Vec<String> {
-    let jmp_cmd = match cmp {
-        Cmp::Eq => JmpCmd::Jeq,
-        Cmp::Lt => JmpCmd::Jlt,
-        Cmp::Gt => JmpCmd::Jgt,
+fn cmp_math_to_asm(
+    cmp: Cmp,
+    label_manager: &mut LabelManager,
+    overflow_safe: bool,
+) -> Vec<Instruction> {
+    let difference = match cmp {
+        Cmp::Gt | Cmp::Lt if overflow_safe => generated_signed_difference(label_manager),
+        _ => flatten(vec![pop_and_prep_stack(), vec![compute(Dest::D, SUB)]]),
     };
+    let jmp_cmd = cmp_jump(cmp);
     let true_lbl = label_manager.generate_static();
     let false_lbl = label_manager.generate_static();
 
     flatten(vec![
-        pop_and_prep_stack(),
-        vec!["D=M-D".to_owned()],
+        difference,
         set_alias(&true_lbl),
-        jmp(jmp_cmd, CmpVal::D),
-        vec!["D=0".to_owned()],
+        vec![jump(Comp::D, jmp_cmd)],
+        vec![compute(Dest::D, Comp::Zero)],
         set_alias(&false_lbl),
-        jmp(JmpCmd::Jmp, CmpVal::Zero),
+        vec![jump(Comp::Zero, Jump::Jmp)],
         label(&true_lbl),
-        vec!["D=-1".to_owned()],
+        vec![compute(Dest::D, Comp::MinusOne)],
         label(&false_lbl),
         push_d_reg_to_stack(),
     ])
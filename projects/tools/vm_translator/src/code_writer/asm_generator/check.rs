@@ -0,0 +1,158 @@
+use std::fmt::{self, Display};
+
+use super::{
+    flatten,
+    instruction::{compute, jump, Comp, Dest, Instruction, Jump},
+    label,
+    register::{
+        set_a_reg_to_constant, set_alias, set_d_reg_to_a_reg, set_d_reg_to_alias, set_mem_to_d_reg,
+    },
+};
+
+///The RAM cell, R15, a failed runtime check leaves its error code in before halting. Runtime checks
+///leave it out of the temp registers, so it stays 0 while the program runs correctly
+pub const TRAP_CELL: u16 = 15;
+const STACK_END: u16 = 2048;
+///The cells a call saves between the caller's arguments and the callee's locals
+pub(crate) const FRAME_SIZE: u16 = 5;
+const TRAP: &str = "$$trap";
+const TRAP_HALT: &str = "$$trap.halt";
+
+///What a failed runtime check records in the trap cell
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum RuntimeError {
+    StackOverflow = 1,
+    StackUnderflow = 2,
+    LocalOutOfBounds = 3,
+    ArgumentOutOfBounds = 4,
+}
+
+impl RuntimeError {
+    pub fn from_code(code: u16) -> Option<Self> {
+        [
+            RuntimeError::StackOverflow,
+            RuntimeError::StackUnderflow,
+            RuntimeError::LocalOutOfBounds,
+            RuntimeError::ArgumentOutOfBounds,
+        ]
+        .into_iter()
+        .find(|error| *error as u16 == code)
+    }
+
+    fn entry(self) -> String {
+        format!("{}.{}", TRAP, self as u16)
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RuntimeError::StackOverflow => "stack overflow into the heap",
+            RuntimeError::StackUnderflow => {
+                "stack underflow into the frame of the current function"
+            }
+            RuntimeError::LocalOutOfBounds => "local beyond those the function declares",
+            RuntimeError::ArgumentOutOfBounds => {
+                "argument beyond those the function was called with"
+            }
+        })
+    }
+}
+
+///Jumps to the trap for `error` when D compares to 0 as `jmp` says
+fn trap_if(jmp: Jump, error: RuntimeError) -> Vec<Instruction> {
+    flatten(vec![set_alias(&error.entry()), vec![jump(Comp::D, jmp)]])
+}
+
+pub(crate) fn trap(error: RuntimeError) -> Vec<Instruction> {
+    flatten(vec![
+        set_alias(&error.entry()),
+        vec![jump(Comp::Zero, Jump::Jmp)],
+    ])
+}
+
+///Traps when D, a distance between two addresses, is below `bound`, which it always is when the
+///bound is too large for an A instruction to load
+fn trap_if_d_below(bound: u16, error: RuntimeError) -> Vec<Instruction> {
+    match i16::try_from(bound) {
+        Ok(bound) => flatten(vec![
+            set_a_reg_to_constant(bound),
+            vec![compute(Dest::D, Comp::DMinusA)],
+            trap_if(Jump::Jlt, error),
+        ]),
+        Err(_) => trap(error),
+    }
+}
+
+///Traps unless the stack has room below the heap for `growth` more values
+pub(crate) fn check_stack_room(growth: u16) -> Vec<Instruction> {
+    let limit = STACK_END.saturating_sub(growth) as i16;
+    flatten(vec![
+        set_d_reg_to_alias("SP", Some(-limit)),
+        trap_if(Jump::Jgt, RuntimeError::StackOverflow),
+    ])
+}
+
+///Traps unless the stack holds `pops` values above the `locals` of the current function
+pub(crate) fn check_stack_depth(locals: u16, pops: u16) -> Vec<Instruction> {
+    flatten(vec![
+        set_d_reg_to_alias("SP", None),
+        set_alias("LCL"),
+        vec![compute(Dest::D, Comp::DMinusM)],
+        trap_if_d_below(locals.saturating_add(pops), RuntimeError::StackUnderflow),
+    ])
+}
+
+///Traps unless argument `idx` is below the saved frame, which starts right after the arguments
+///the caller pushed
+pub(crate) fn check_argument(idx: u16) -> Vec<Instruction> {
+    flatten(vec![
+        set_d_reg_to_alias("LCL", None),
+        set_alias("ARG"),
+        vec![compute(Dest::D, Comp::DMinusM)],
+        trap_if_d_below(
+            idx.saturating_add(FRAME_SIZE + 1),
+            RuntimeError::ArgumentOutOfBounds,
+        ),
+    ])
+}
+
+///An entry for each error checked for that records its code in the trap cell and halts
+pub(crate) fn trap_routine<'a, I: IntoIterator<Item = &'a RuntimeError>>(
+    errors: I,
+) -> Vec<Instruction> {
+    let mut asm = Vec::new();
+    for error in errors {
+        asm.extend(flatten(vec![
+            label(&error.entry()),
+            set_a_reg_to_constant(*error as i16),
+            set_d_reg_to_a_reg(),
+            set_alias(TRAP),
+            vec![jump(Comp::Zero, Jump::Jmp)],
+        ]));
+    }
+    asm.extend(flatten(vec![
+        label(TRAP),
+        set_a_reg_to_constant(TRAP_CELL as i16),
+        set_mem_to_d_reg(),
+        label(TRAP_HALT),
+        set_alias(TRAP_HALT),
+        vec![jump(Comp::Zero, Jump::Jmp)],
+    ]));
+    asm
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_always_traps_for_bounds_too_large_to_load() {
+        let underflow = trap(RuntimeError::StackUnderflow);
+        assert!(check_stack_depth(32762, 32762).ends_with(&underflow));
+        assert!(!check_stack_depth(32762, 5).ends_with(&underflow));
+        let out_of_bounds = trap(RuntimeError::ArgumentOutOfBounds);
+        assert!(check_argument(32767).ends_with(&out_of_bounds));
+        assert!(!check_argument(32761).ends_with(&out_of_bounds));
+    }
+}
@@ -1,11 +1,13 @@
-use std::{cell::RefCell, fmt::Display, rc::Rc};
+use std::rc::Rc;
 
 use super::{
-    flatten, label,
+    flatten,
+    instruction::{compute, jump, Comp, Dest, Instruction, Jump},
+    label,
     memory::{get_segment_alias, set_d_reg_to_segment_idx},
     register::{
         set_a_reg_to_alias, set_a_reg_to_constant, set_alias, set_d_reg_to_a_reg,
-        set_d_reg_to_alias, set_d_reg_to_mem, set_mem_to_d_reg, CmpVal,
+        set_d_reg_to_alias, set_d_reg_to_mem, set_mem_to_d_reg,
     },
     stack::{pop_stack_to_d_reg, push_d_reg_to_stack, SEGMENT_STACK},
     MemCmdWriter, MemoryError,
@@ -26,58 +28,44 @@ pub enum FlowError {
     Memory(#[from] MemoryError),
 }
 
-pub(crate) enum JmpCmd {
-    Jeq,
-    Jlt,
-    Jgt,
-    Jmp,
-}
-
-impl Display for JmpCmd {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                JmpCmd::Jeq => "JEQ",
-                JmpCmd::Jlt => "JLT",
-                JmpCmd::Jgt => "JGT",
-                JmpCmd::Jmp => "JMP",
-            }
-        )
-    }
-}
+type FlowCmd = Box<dyn Fn(Flow, &mut LabelManager) -> Result<Vec<Instruction>, FlowError>>;
 
-type FlowCmd = Box<dyn Fn(Flow, &mut LabelManager) -> Result<Vec<String>, FlowError>>;
-
-pub(crate) fn flow(gen_purp_reg: Rc<RefCell<RegMgr>>, mem_cmd_writer: Rc<MemCmdWriter>) -> FlowCmd {
+pub(crate) fn flow(gen_purp_reg: RegMgr, mem_cmd_writer: Rc<MemCmdWriter>) -> FlowCmd {
     Box::new(move |flow_cmd, label_manager| match flow_cmd {
-        Flow::Goto(goto_type, ref l) => match goto_type {
-            crate::parser::Goto::Direct => Ok(goto(l)),
-            crate::parser::Goto::Conditional => Ok(if_goto(l)),
-        },
-        Flow::Call(name, args) => Ok(call(&name, args, label_manager)),
-        Flow::Return => {
-            label_manager.end_function();
-            Ok(return_cmd(gen_purp_reg.clone(), mem_cmd_writer.clone())?)
+        Flow::Goto(goto_type, ref l) => {
+            let l = label_manager.qualify_label(l);
+            match goto_type {
+                crate::parser::Goto::Direct => Ok(goto(&l)),
+                crate::parser::Goto::Conditional => Ok(if_goto(&l)),
+                crate::parser::Goto::IfZero => Ok(flatten(vec![
+                    pop_stack_to_d_reg(),
+                    if_zero_goto_on_d_reg(&l),
+                ])),
+            }
         }
+        Flow::Call(name, args) => Ok(call(&name, args, label_manager)),
+        Flow::Return => Ok(return_cmd(&gen_purp_reg, &mem_cmd_writer)?),
     })
 }
 
-fn goto(label: &str) -> Vec<String> {
-    flatten(vec![set_alias(label), jmp(JmpCmd::Jmp, CmpVal::Zero)])
+fn goto(label: &str) -> Vec<Instruction> {
+    flatten(vec![set_alias(label), vec![jump(Comp::Zero, Jump::Jmp)]])
+}
+
+fn if_goto(label: &str) -> Vec<Instruction> {
+    flatten(vec![pop_stack_to_d_reg(), if_goto_on_d_reg(label)])
 }
 
-fn if_goto(label: &str) -> Vec<String> {
+///Jumps to the already qualified `label` when D, the popped condition, is true
+pub(crate) fn if_goto_on_d_reg(label: &str) -> Vec<Instruction> {
     flatten(vec![
-        pop_stack_to_d_reg(),
         set_alias(label),
-        jmp(JmpCmd::Jgt, CmpVal::D),
-        jmp(JmpCmd::Jlt, CmpVal::D),
+        vec![jump(Comp::D, Jump::Jgt)],
+        vec![jump(Comp::D, Jump::Jlt)],
     ])
 }
 
-fn call(name: &str, arg_count: u8, label_manager: &mut LabelManager) -> Vec<String> {
+fn call(name: &str, arg_count: u16, label_manager: &mut LabelManager) -> Vec<Instruction> {
     let ret_label = label_manager.generate_label(format!("{}$ret", name).as_str(), true);
     flatten(vec![
         generate_retun(&ret_label),
@@ -91,7 +79,7 @@ fn call(name: &str, arg_count: u8, label_manager: &mut LabelManager) -> Vec<Stri
     ])
 }
 
-fn generate_retun(label: &str) -> Vec<String> {
+fn generate_retun(label: &str) -> Vec<Instruction> {
     flatten(vec![
         set_alias(label),
         set_d_reg_to_a_reg(),
@@ -99,7 +87,7 @@ fn generate_retun(label: &str) -> Vec<String> {
     ])
 }
 
-fn save_local_frame() -> Vec<String> {
+pub(super) fn save_local_frame() -> Vec<Instruction> {
     flatten(vec![
         set_d_reg_to_segment_idx(Segment::Local, 0),
         push_d_reg_to_stack(),
@@ -112,7 +100,7 @@ fn save_local_frame() -> Vec<String> {
     ])
 }
 
-fn reset_args_for_call(arg_count: u8) -> Vec<String> {
+fn reset_args_for_call(arg_count: u16) -> Vec<Instruction> {
     flatten(vec![
         set_d_reg_to_alias(SEGMENT_STACK, Some(-(5 + arg_count as i16))),
         set_alias(get_segment_alias(&Segment::Argument)),
@@ -120,22 +108,24 @@ fn reset_args_for_call(arg_count: u8) -> Vec<String> {
     ])
 }
 
-fn return_cmd(
-    gen_purp_reg: Rc<RefCell<RegMgr>>,
-    mem_cmd_writer: Rc<MemCmdWriter>,
-) -> Result<Vec<String>, FlowError> {
-    let lcl = gen_purp_reg.borrow_mut().next()?;
-    let ret_add = gen_purp_reg.borrow_mut().next()?;
+///Takes two temp registers, for the frame and the return address, and no more
+pub(super) fn return_cmd(
+    gen_purp_reg: &RegMgr,
+    mem_cmd_writer: &MemCmdWriter,
+) -> Result<Vec<Instruction>, FlowError> {
+    let lcl = gen_purp_reg.next()?;
+    let ret_add = gen_purp_reg.next()?;
     Ok(flatten(vec![
         set_d_reg_to_segment_idx(Segment::Local, 0),
         set_alias(&lcl.to_string()),
         set_mem_to_d_reg(),
         set_a_reg_to_constant(5),
-        vec!["A=D-A".to_string()],
+        vec![compute(Dest::A, Comp::DMinusA)],
         set_d_reg_to_mem(),
         set_alias(&ret_add.to_string()),
         set_mem_to_d_reg(),
-        mem_cmd_writer.pop_stack_to(Segment::Argument, 0)?,
+        pop_stack_to_d_reg(),
+        mem_cmd_writer.store_d_reg_to(Segment::Argument, 0)?,
         set_d_reg_to_segment_idx(Segment::Argument, 1),
         set_alias(SEGMENT_STACK),
         set_mem_to_d_reg(),
@@ -144,19 +134,22 @@ fn return_cmd(
         set_segment_addr(&lcl, 3, Segment::Argument),
         set_segment_addr(&lcl, 4, Segment::Local),
         set_a_reg_to_alias(&ret_add.to_string()),
-        jmp(JmpCmd::Jmp, CmpVal::Zero),
+        vec![jump(Comp::Zero, Jump::Jmp)],
     ]))
 }
 
-fn set_segment_addr(reg: &Reg, steps_back: u8, segment: Segment) -> Vec<String> {
+fn set_segment_addr(reg: &Reg, steps_back: u8, segment: Segment) -> Vec<Instruction> {
     flatten(vec![
         if steps_back == 1 {
-            flatten(vec![set_alias(&reg.to_string()), vec!["A=M-1".to_string()]])
+            flatten(vec![
+                set_alias(&reg.to_string()),
+                vec![compute(Dest::A, Comp::MMinusOne)],
+            ])
         } else {
             flatten(vec![
                 set_d_reg_to_alias(&reg.to_string(), None),
                 set_a_reg_to_constant(steps_back as i16),
-                vec!["A=D-A".to_string()],
+                vec![compute(Dest::A, Comp::DMinusA)],
             ])
         },
         set_d_reg_to_mem(),
@@ -165,6 +158,7 @@ fn set_segment_addr(reg: &Reg, steps_back: u8, segment: Segment) -> Vec<String>
     ])
 }
 
-pub(super) fn jmp(jmp_cmd: JmpCmd, cmp_val: CmpVal) -> Vec<String> {
-    vec![format!("{};{}", cmp_val, jmp_cmd)]
+///Jumps to the already qualified `label` when D is zero
+pub(crate) fn if_zero_goto_on_d_reg(label: &str) -> Vec<Instruction> {
+    flatten(vec![set_alias(label), vec![jump(Comp::D, Jump::Jeq)]])
 }
@@ -0,0 +1,211 @@
+use std::fmt::{self, Display};
+
+///A Hack assembly instruction, which the code generators build and the code writer only renders
+///to text once it writes them out
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) enum Instruction {
+    A(Address),
+    C(Dest, Comp, Jump),
+    ///Takes no room in ROM, naming the address of the instruction after it
+    Label(String),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) enum Address {
+    Value(u16),
+    Symbol(String),
+}
+
+///The registers a C instruction stores its result in
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) struct Dest {
+    pub a: bool,
+    pub d: bool,
+    pub m: bool,
+}
+
+impl Dest {
+    pub(crate) const NONE: Dest = Dest::new(false, false, false);
+    pub(crate) const A: Dest = Dest::new(true, false, false);
+    pub(crate) const D: Dest = Dest::new(false, true, false);
+    pub(crate) const M: Dest = Dest::new(false, false, true);
+    pub(crate) const AM: Dest = Dest::new(true, false, true);
+
+    const fn new(a: bool, d: bool, m: bool) -> Self {
+        Self { a, d, m }
+    }
+}
+
+///The computations of the Hack ALU that the generators use, in the order of the Hack specification
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum Comp {
+    Zero,
+    One,
+    MinusOne,
+    D,
+    A,
+    M,
+    NotD,
+    NotA,
+    NotM,
+    NegD,
+    NegM,
+    DPlusOne,
+    APlusOne,
+    MPlusOne,
+    DMinusOne,
+    AMinusOne,
+    MMinusOne,
+    DPlusA,
+    DPlusM,
+    DMinusA,
+    DMinusM,
+    MMinusD,
+    DAndM,
+    DOrM,
+}
+
+///The jumps the generators use
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum Jump {
+    Null,
+    Jgt,
+    Jeq,
+    Jge,
+    Jlt,
+    Jmp,
+}
+
+impl Instruction {
+    ///Whether the instruction takes a word of ROM, which labels don't
+    pub(crate) fn is_emitted(&self) -> bool {
+        !matches!(self, Instruction::Label(_))
+    }
+}
+
+///`@symbol`
+pub(crate) fn at(symbol: &str) -> Instruction {
+    Instruction::A(Address::Symbol(symbol.to_owned()))
+}
+
+///`@value`, for a value an A instruction can load
+pub(crate) fn at_value(value: u16) -> Instruction {
+    debug_assert!(
+        value <= i16::MAX as u16,
+        "@{} doesn't fit an A instruction",
+        value
+    );
+    Instruction::A(Address::Value(value))
+}
+
+///`dest=comp`
+pub(crate) fn compute(dest: Dest, comp: Comp) -> Instruction {
+    Instruction::C(dest, comp, Jump::Null)
+}
+
+///`comp;jump`
+pub(crate) fn jump(comp: Comp, jump: Jump) -> Instruction {
+    Instruction::C(Dest::NONE, comp, jump)
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::A(Address::Value(value)) => write!(f, "@{}", value),
+            Instruction::A(Address::Symbol(symbol)) => write!(f, "@{}", symbol),
+            Instruction::C(dest, comp, jump) => {
+                if *dest != Dest::NONE {
+                    write!(f, "{}=", dest)?;
+                }
+                write!(f, "{}", comp)?;
+                if *jump != Jump::Null {
+                    write!(f, ";{}", jump)?;
+                }
+                Ok(())
+            }
+            Instruction::Label(label) => write!(f, "({})", label),
+        }
+    }
+}
+
+impl Display for Dest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (set, register) in [(self.a, 'A'), (self.d, 'D'), (self.m, 'M')] {
+            if set {
+                write!(f, "{}", register)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for Comp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mnemonic = match self {
+            Comp::Zero => "0",
+            Comp::One => "1",
+            Comp::MinusOne => "-1",
+            Comp::D => "D",
+            Comp::A => "A",
+            Comp::M => "M",
+            Comp::NotD => "!D",
+            Comp::NotA => "!A",
+            Comp::NotM => "!M",
+            Comp::NegD => "-D",
+            Comp::NegM => "-M",
+            Comp::DPlusOne => "D+1",
+            Comp::APlusOne => "A+1",
+            Comp::MPlusOne => "M+1",
+            Comp::DMinusOne => "D-1",
+            Comp::AMinusOne => "A-1",
+            Comp::MMinusOne => "M-1",
+            Comp::DPlusA => "D+A",
+            Comp::DPlusM => "D+M",
+            Comp::DMinusA => "D-A",
+            Comp::DMinusM => "D-M",
+            Comp::MMinusD => "M-D",
+            Comp::DAndM => "D&M",
+            Comp::DOrM => "D|M",
+        };
+        write!(f, "{}", mnemonic)
+    }
+}
+
+impl Display for Jump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mnemonic = match self {
+            Jump::Null => "",
+            Jump::Jgt => "JGT",
+            Jump::Jeq => "JEQ",
+            Jump::Jge => "JGE",
+            Jump::Jlt => "JLT",
+            Jump::Jmp => "JMP",
+        };
+        write!(f, "{}", mnemonic)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(at("SP"), "@SP"; "symbol")]
+    #[test_case(at_value(32767), "@32767"; "value")]
+    #[test_case(compute(Dest::AM, Comp::MMinusOne), "AM=M-1"; "two destinations")]
+    #[test_case(compute(Dest::D, Comp::NotA), "D=!A"; "unary")]
+    #[test_case(jump(Comp::Zero, Jump::Jmp), "0;JMP"; "unconditional jump")]
+    #[test_case(Instruction::C(Dest::M, Comp::DPlusOne, Jump::Jge), "M=D+1;JGE"; "store and jump")]
+    #[test_case(Instruction::Label("Main.main".to_owned()), "(Main.main)"; "label")]
+    fn it_renders_instructions(instruction: Instruction, text: &str) {
+        assert_eq!(instruction.to_string(), text);
+    }
+
+    #[test]
+    fn it_counts_all_but_labels() {
+        assert!(at("SP").is_emitted());
+        assert!(jump(Comp::D, Jump::Jeq).is_emitted());
+        assert!(!Instruction::Label("LOOP".to_owned()).is_emitted());
+    }
+}
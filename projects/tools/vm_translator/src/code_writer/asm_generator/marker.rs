@@ -1,33 +1,40 @@
 use crate::{code_writer::label_manager::LabelManager, parser::Marker};
 
-use super::{flatten, register::set_d_reg_to_constant, stack::push_d_reg_to_stack, MemoryError};
+use super::{
+    flatten,
+    instruction::{compute, jump, Comp, Dest, Instruction, Jump},
+    register::{set_a_reg_to_constant, set_alias, set_d_reg_to_a_reg, set_d_reg_to_constant},
+    stack::{push_d_reg_to_stack, SEGMENT_STACK},
+};
 
-#[derive(thiserror::Error, Debug)]
-pub enum MarkerError {
-    #[error("Memory error: {0}")]
-    Memory(#[from] MemoryError),
-}
+///Beyond this many locals, they are zeroed by a loop rather than a push each
+const MAX_UNROLLED_LOCALS: u16 = 4;
 
-pub(crate) fn marker(marker_cmd: Marker, label_manager: &mut LabelManager) -> Vec<String> {
+pub(crate) fn marker(marker_cmd: Marker, label_manager: &mut LabelManager) -> Vec<Instruction> {
     match marker_cmd {
-        Marker::Label(ref l) => label(l),
+        Marker::Label(ref l) => label(&label_manager.qualify_label(l)),
         Marker::Function(ref name, local_count) => {
-            let ret = function(name, local_count);
+            let ret = function(name, local_count, label_manager);
             label_manager.start_function(name);
             ret
         }
     }
 }
 
-pub(crate) fn label(label: &str) -> Vec<String> {
-    vec![format!("({})", label)]
+pub(crate) fn label(label: &str) -> Vec<Instruction> {
+    vec![Instruction::Label(label.to_owned())]
 }
 
-fn function(name: &str, local_count: u8) -> Vec<String> {
-    flatten(vec![label(name), initialize_locals(local_count)])
+fn function(name: &str, local_count: u16, label_manager: &mut LabelManager) -> Vec<Instruction> {
+    let locals = if local_count > MAX_UNROLLED_LOCALS {
+        initialize_locals_in_loop(local_count, &label_manager.generate_static())
+    } else {
+        initialize_locals(local_count)
+    };
+    flatten(vec![label(name), locals])
 }
 
-fn initialize_locals(local_count: u8) -> Vec<String> {
+fn initialize_locals(local_count: u16) -> Vec<Instruction> {
     if local_count > 0 {
         (0..local_count)
             .flat_map(|_| flatten(vec![set_d_reg_to_constant(0), push_d_reg_to_stack()]))
@@ -36,3 +43,21 @@ fn initialize_locals(local_count: u8) -> Vec<String> {
         vec![]
     }
 }
+
+///Pushes `local_count` zeros, counting down in D
+fn initialize_locals_in_loop(local_count: u16, loop_lbl: &str) -> Vec<Instruction> {
+    flatten(vec![
+        set_a_reg_to_constant(local_count as i16),
+        set_d_reg_to_a_reg(),
+        label(loop_lbl),
+        set_alias(SEGMENT_STACK),
+        vec![
+            compute(Dest::AM, Comp::MPlusOne),
+            compute(Dest::A, Comp::AMinusOne),
+            compute(Dest::M, Comp::Zero),
+            compute(Dest::D, Comp::DMinusOne),
+        ],
+        set_alias(loop_lbl),
+        vec![jump(Comp::D, Jump::Jgt)],
+    ])
+}
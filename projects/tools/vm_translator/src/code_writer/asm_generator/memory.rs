@@ -1,4 +1,4 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::collections::HashMap;
 
 use lazy_static::lazy_static;
 
@@ -9,6 +9,7 @@ use crate::{
 
 use super::{
     flatten,
+    instruction::{compute, Comp, Dest, Instruction},
     register::{
         set_a_reg_to_address, set_a_reg_to_alias, set_a_reg_to_constant, set_alias,
         set_d_reg_to_alias, set_d_reg_to_constant, set_d_reg_to_mem, set_mem_at_alias_to_d_reg,
@@ -30,10 +31,20 @@ lazy_static! {
 
 const TMP_BASE_ADDR: u16 = 5;
 const AVAILABLE_TMP_BLOCKS: u16 = 8;
+///Beyond this index, stepping A to a segment entry takes longer than computing its address
+const MAX_STEPPED_IDX: u16 = 8;
+
+fn pointer_segment(idx: u16) -> Segment {
+    if idx == 0 {
+        Segment::This
+    } else {
+        Segment::That
+    }
+}
 
 pub(crate) struct MemCmdWriter {
     namespace: String,
-    gen_purp_reg: Rc<RefCell<RegMgr>>,
+    gen_purp_reg: RegMgr,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -42,64 +53,128 @@ pub enum MemoryError {
     Temp(#[from] RegMgrError),
     #[error("Memory out of bounds: {0} is out of bounds of segment {1}")]
     OutOfBounds(u16, Segment),
+    #[error("Cannot pop to segment {0}")]
+    PopConstant(Segment),
 }
 
 impl MemCmdWriter {
-    pub fn new(namespace: String, gen_purp_reg: Rc<RefCell<RegMgr>>) -> Self {
+    pub fn new(namespace: String, gen_purp_reg: RegMgr) -> Self {
         Self {
-            namespace: namespace.to_uppercase(),
+            namespace,
             gen_purp_reg,
         }
     }
 
-    pub fn push_constant(&self, value: i16) -> Vec<String> {
+    pub fn push_constant(&self, value: i16) -> Vec<Instruction> {
         flatten(vec![set_d_reg_to_constant(value), push_d_reg_to_stack()])
     }
 
-    pub fn push_to_stack(&self, segment: Segment, idx: u16) -> Result<Vec<String>, MemoryError> {
+    pub fn push_to_stack(
+        &self,
+        segment: Segment,
+        idx: u16,
+    ) -> Result<Vec<Instruction>, MemoryError> {
         Ok(flatten(vec![
-            match segment {
-                Segment::Static => flatten(vec![set_d_reg_to_alias(
-                    format!("{}.{}", self.namespace, idx).as_str(),
-                    None,
-                )]),
-                Segment::Temp => {
-                    if idx > AVAILABLE_TMP_BLOCKS - 1 {
-                        Err(MemoryError::OutOfBounds(idx, segment))?
-                    } else {
-                        flatten(vec![
-                            set_a_reg_to_address(TMP_BASE_ADDR + idx),
-                            set_d_reg_to_mem(),
-                        ])
-                    }
-                }
-                Segment::Pointer => {
-                    if idx > 1 {
-                        Err(MemoryError::OutOfBounds(idx, segment))?
-                    } else {
-                        let segment = if idx == 0 {
-                            Segment::This
-                        } else {
-                            Segment::That
-                        };
-                        set_d_reg_to_alias(get_segment_alias(&segment), None)
-                    }
-                }
-                segment => flatten(vec![
-                    set_a_reg_to_segment_idx(segment, idx),
-                    set_d_reg_to_mem(),
-                ]),
-            },
+            self.load_to_d_reg(segment, idx)?,
             push_d_reg_to_stack(),
         ]))
     }
 
+    pub(crate) fn load_to_d_reg(
+        &self,
+        segment: Segment,
+        idx: u16,
+    ) -> Result<Vec<Instruction>, MemoryError> {
+        Ok(match segment {
+            Segment::Static => flatten(vec![set_d_reg_to_alias(
+                format!("{}.{}", self.namespace, idx).as_str(),
+                None,
+            )]),
+            Segment::Temp => {
+                if idx > AVAILABLE_TMP_BLOCKS - 1 {
+                    Err(MemoryError::OutOfBounds(idx, segment))?
+                } else {
+                    flatten(vec![
+                        set_a_reg_to_address(TMP_BASE_ADDR + idx),
+                        set_d_reg_to_mem(),
+                    ])
+                }
+            }
+            Segment::Pointer => {
+                if idx > 1 {
+                    Err(MemoryError::OutOfBounds(idx, segment))?
+                } else {
+                    set_d_reg_to_alias(get_segment_alias(&pointer_segment(idx)), None)
+                }
+            }
+            Segment::Constant => set_d_reg_to_constant(idx as i16),
+            segment => flatten(vec![
+                set_a_reg_to_segment_idx(segment, idx),
+                set_d_reg_to_mem(),
+            ]),
+        })
+    }
+
+    ///Stores D, which holds the value rather than the stack, into the segment. Nearby entries are
+    ///reached by stepping A, others by spilling the value while the address is worked out
+    pub(crate) fn store_d_reg_to(
+        &self,
+        segment: Segment,
+        idx: u16,
+    ) -> Result<Vec<Instruction>, MemoryError> {
+        Ok(match segment {
+            Segment::Static => flatten(vec![
+                set_alias(format!("{}.{}", self.namespace, idx).as_str()),
+                set_mem_to_d_reg(),
+            ]),
+            Segment::Temp => {
+                if idx > AVAILABLE_TMP_BLOCKS - 1 {
+                    Err(MemoryError::OutOfBounds(idx, segment))?
+                } else {
+                    flatten(vec![
+                        set_a_reg_to_address(TMP_BASE_ADDR + idx),
+                        set_mem_to_d_reg(),
+                    ])
+                }
+            }
+            Segment::Pointer => {
+                if idx > 1 {
+                    Err(MemoryError::OutOfBounds(idx, segment))?
+                } else {
+                    flatten(vec![
+                        set_alias(get_segment_alias(&pointer_segment(idx))),
+                        set_mem_to_d_reg(),
+                    ])
+                }
+            }
+            Segment::Constant => Err(MemoryError::PopConstant(segment))?,
+            segment if idx <= MAX_STEPPED_IDX => flatten(vec![
+                set_a_reg_to_alias(get_segment_alias(&segment)),
+                (0..idx).map(|_| compute(Dest::A, Comp::APlusOne)).collect(),
+                set_mem_to_d_reg(),
+            ]),
+            segment => {
+                let value = self.gen_purp_reg.next()?;
+                let addr = self.gen_purp_reg.next()?;
+                flatten(vec![
+                    set_alias(&value.to_string()),
+                    set_mem_to_d_reg(),
+                    set_d_reg_to_segment_idx(segment, idx),
+                    set_alias(&addr.to_string()),
+                    set_mem_to_d_reg(),
+                    set_d_reg_to_alias(&value.to_string(), None),
+                    set_a_reg_to_alias(&addr.to_string()),
+                    set_mem_to_d_reg(),
+                ])
+            }
+        })
+    }
+
     pub(crate) fn pop_stack_to(
         &self,
         segment: Segment,
         idx: u16,
-    ) -> Result<Vec<String>, MemoryError> {
-        let tmp = self.gen_purp_reg.borrow_mut().next()?;
+    ) -> Result<Vec<Instruction>, MemoryError> {
         Ok(flatten(vec![match segment {
             Segment::Static => flatten(vec![
                 pop_stack_to_d_reg(),
@@ -121,30 +196,29 @@ impl MemCmdWriter {
                 if idx > 1 {
                     Err(MemoryError::OutOfBounds(idx, segment))?
                 } else {
-                    let segment = if idx == 0 {
-                        Segment::This
-                    } else {
-                        Segment::That
-                    };
                     flatten(vec![
                         pop_stack_to_d_reg(),
-                        set_alias(get_segment_alias(&segment)),
+                        set_alias(get_segment_alias(&pointer_segment(idx))),
                         set_mem_to_d_reg(),
                     ])
                 }
             }
-            segment => flatten(vec![
-                set_d_reg_to_segment_idx(segment, idx),
-                set_alias(tmp.to_string().as_str()),
-                set_mem_to_d_reg(),
-                pop_stack_to_d_reg(),
-                set_mem_at_alias_to_d_reg(tmp.to_string().as_str()),
-            ]),
+            Segment::Constant => Err(MemoryError::PopConstant(segment))?,
+            segment => {
+                let tmp = self.gen_purp_reg.next()?;
+                flatten(vec![
+                    set_d_reg_to_segment_idx(segment, idx),
+                    set_alias(tmp.to_string().as_str()),
+                    set_mem_to_d_reg(),
+                    pop_stack_to_d_reg(),
+                    set_mem_at_alias_to_d_reg(tmp.to_string().as_str()),
+                ])
+            }
         }]))
     }
 }
 
-pub(super) fn set_d_reg_to_segment_idx(segment: Segment, idx: u16) -> Vec<String> {
+pub(super) fn set_d_reg_to_segment_idx(segment: Segment, idx: u16) -> Vec<Instruction> {
     let asm = flatten(vec![set_d_reg_to_alias(
         get_segment_alias(&segment),
         Some(idx as i16),
@@ -152,28 +226,27 @@ pub(super) fn set_d_reg_to_segment_idx(segment: Segment, idx: u16) -> Vec<String
     asm
 }
 
-pub(super) fn set_a_reg_to_segment_idx(segment: Segment, idx: u16) -> Vec<String> {
+pub(super) fn set_a_reg_to_segment_idx(segment: Segment, idx: u16) -> Vec<Instruction> {
     let alias = get_segment_alias(&segment);
-    let asm = flatten(vec![if idx == 0 {
+    flatten(vec![if idx == 0 {
         set_a_reg_to_alias(alias)
     } else {
         flatten(vec![
             set_d_reg_to_alias(alias, None),
             if idx == 1 {
-                vec![format!("A=D+1")]
+                vec![compute(Dest::A, Comp::DPlusOne)]
             } else {
                 flatten(vec![
                     set_a_reg_to_constant(idx as i16),
-                    vec![format!("A=D+A")],
+                    vec![compute(Dest::A, Comp::DPlusA)],
                 ])
             },
         ])
-    }]);
-    asm
+    }])
 }
 
 pub(super) fn get_segment_alias(segment: &Segment) -> &str {
-    *SEGMENT_MEM_MAP.get(segment).unwrap()
+    SEGMENT_MEM_MAP.get(segment).unwrap()
 }
 
 #[cfg(test)]
@@ -182,10 +255,7 @@ mod test {
     use test_case::test_case;
 
     fn make_mem_cmd_writer() -> MemCmdWriter {
-        MemCmdWriter::new(
-            "test".to_string(),
-            Rc::new(RefCell::new(RegMgr::new(5, 8).unwrap())),
-        )
+        MemCmdWriter::new("test".to_string(), RegMgr::new(5, 8).unwrap())
     }
 
     #[test_case(
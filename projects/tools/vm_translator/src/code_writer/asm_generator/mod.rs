@@ -1,18 +1,31 @@
 mod arithmetic;
+mod check;
 mod flow;
+mod instruction;
 mod marker;
 mod memory;
 mod register;
+mod shared;
 mod stack;
 
-pub(super) use arithmetic::arithmetic;
+pub(super) use arithmetic::{arithmetic, arithmetic_on_d_reg};
+pub(super) use check::{
+    check_argument, check_stack_depth, check_stack_room, trap, trap_routine, FRAME_SIZE,
+};
+pub use check::{RuntimeError, TRAP_CELL};
 pub(crate) use flow::flow;
 pub(crate) use flow::FlowError;
+pub(super) use flow::{if_goto_on_d_reg, if_zero_goto_on_d_reg};
+pub(crate) use instruction::Instruction;
 pub(super) use marker::label;
 pub(super) use marker::marker;
 pub(crate) use memory::MemCmdWriter;
 pub(crate) use memory::MemoryError;
+pub(super) use register::{set_d_reg_to_constant, set_mem_at_alias_to_address};
+pub(crate) use shared::Routine;
+pub(super) use shared::{call_site, cmp_site, halt, return_site, routine};
+pub(super) use stack::{push_d_reg_to_stack, take_stack_top};
 
-fn flatten(asm: Vec<Vec<String>>) -> Vec<String> {
+fn flatten(asm: Vec<Vec<Instruction>>) -> Vec<Instruction> {
     asm.into_iter().flatten().collect()
 }
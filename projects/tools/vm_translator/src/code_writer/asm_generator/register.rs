@@ -1,110 +1,90 @@
-use std::fmt::Display;
+use super::{
+    flatten,
+    instruction::{at, at_value, compute, Comp, Dest, Instruction},
+};
 
-use super::flatten;
-
-pub(super) enum Reg {
-    A,
-    D,
-    Mem,
-}
-
-pub(super) enum CmpVal {
-    D,
-    Zero,
-}
-
-impl Display for Reg {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                Reg::A => "A",
-                Reg::D => "D",
-                Reg::Mem => "M",
-            }
-        )
-    }
-}
-
-impl Display for CmpVal {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                CmpVal::D => "D",
-                CmpVal::Zero => "0",
-            },
-        )
-    }
-}
-
-pub(super) fn set_alias(alias: &str) -> Vec<String> {
-    vec![format!("@{}", alias)]
+pub(super) fn set_alias(alias: &str) -> Vec<Instruction> {
+    vec![at(alias)]
 }
 
-pub(super) fn set_a_reg_to_alias(alias: &str) -> Vec<String> {
+pub(super) fn set_a_reg_to_alias(alias: &str) -> Vec<Instruction> {
     flatten(vec![set_alias(alias), set_a_reg_to_pointer()])
 }
 
-pub(super) fn set_d_reg_to_alias(alias: &str, relative: Option<i16>) -> Vec<String> {
+pub(super) fn set_d_reg_to_alias(alias: &str, relative: Option<i16>) -> Vec<Instruction> {
     match relative {
-        Some(idx) if idx == -1 => flatten(vec![set_alias(alias), vec!["D=M-1".to_owned()]]),
-        Some(idx) if idx == 1 => flatten(vec![set_alias(alias), vec!["D=M+1".to_owned()]]),
+        Some(-1) => flatten(vec![
+            set_alias(alias),
+            vec![compute(Dest::D, Comp::MMinusOne)],
+        ]),
+        Some(1) => flatten(vec![
+            set_alias(alias),
+            vec![compute(Dest::D, Comp::MPlusOne)],
+        ]),
         Some(idx) if idx != 0 => flatten(vec![
             set_d_reg_to_alias(alias, None),
             set_a_reg_to_constant(idx.abs()),
             if idx > 0 {
-                vec!["D=D+A".to_owned()]
+                vec![compute(Dest::D, Comp::DPlusA)]
             } else {
-                vec!["D=D-A".to_owned()]
+                vec![compute(Dest::D, Comp::DMinusA)]
             },
         ]),
         _ => flatten(vec![set_alias(alias), set_d_reg_to_mem()]),
     }
 }
 
-pub(super) fn set_a_reg_to_pointer() -> Vec<String> {
-    set_reg_to(Reg::A, Reg::Mem)
+pub(super) fn set_a_reg_to_pointer() -> Vec<Instruction> {
+    vec![compute(Dest::A, Comp::M)]
 }
 
-pub(super) fn set_d_reg_to_constant(value: i16) -> Vec<String> {
+pub(crate) fn set_d_reg_to_constant(value: i16) -> Vec<Instruction> {
     if value == 0 {
-        vec![format!("D=0")]
+        vec![compute(Dest::D, Comp::Zero)]
     } else if value == 1 {
-        vec![format!("D=1")]
+        vec![compute(Dest::D, Comp::One)]
     } else if value == -1 {
-        vec![format!("D=-1")]
+        vec![compute(Dest::D, Comp::MinusOne)]
+    } else if value < 0 {
+        //An A instruction only loads 15 bits, so a negative value is loaded as its complement
+        flatten(vec![
+            set_a_reg_to_constant(!value),
+            vec![compute(Dest::D, Comp::NotA)],
+        ])
     } else {
         flatten(vec![set_a_reg_to_constant(value), set_d_reg_to_a_reg()])
     }
 }
 
-pub(super) fn set_a_reg_to_constant(value: i16) -> Vec<String> {
-    vec![format!("@{}", value)]
+pub(super) fn set_a_reg_to_constant(value: i16) -> Vec<Instruction> {
+    vec![at_value(value as u16)]
 }
 
-pub(super) fn set_a_reg_to_address(value: u16) -> Vec<String> {
-    vec![format!("@{}", value)]
+pub(super) fn set_a_reg_to_address(value: u16) -> Vec<Instruction> {
+    vec![at_value(value)]
 }
 
-pub(super) fn set_d_reg_to_a_reg() -> Vec<String> {
-    set_reg_to(Reg::D, Reg::A)
+pub(super) fn set_d_reg_to_a_reg() -> Vec<Instruction> {
+    vec![compute(Dest::D, Comp::A)]
 }
 
-pub(super) fn set_d_reg_to_mem() -> Vec<String> {
-    set_reg_to(Reg::D, Reg::Mem)
+pub(super) fn set_d_reg_to_mem() -> Vec<Instruction> {
+    vec![compute(Dest::D, Comp::M)]
 }
 
-pub(super) fn set_mem_to_d_reg() -> Vec<String> {
-    set_reg_to(Reg::Mem, Reg::D)
+pub(super) fn set_mem_to_d_reg() -> Vec<Instruction> {
+    vec![compute(Dest::M, Comp::D)]
 }
 
-fn set_reg_to(a: Reg, b: Reg) -> Vec<String> {
-    vec![format!("{}={}", a, b)]
+pub(super) fn set_mem_at_alias_to_d_reg(alias: &str) -> Vec<Instruction> {
+    flatten(vec![set_a_reg_to_alias(alias), set_mem_to_d_reg()])
 }
 
-pub(super) fn set_mem_at_alias_to_d_reg(alias: &str) -> Vec<String> {
-    flatten(vec![set_a_reg_to_alias(alias), set_mem_to_d_reg()])
+pub(crate) fn set_mem_at_alias_to_address(alias: &str, value: u16) -> Vec<Instruction> {
+    flatten(vec![
+        set_a_reg_to_address(value),
+        set_d_reg_to_a_reg(),
+        set_alias(alias),
+        set_mem_to_d_reg(),
+    ])
 }
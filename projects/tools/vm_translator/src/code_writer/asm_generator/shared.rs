@@ -0,0 +1,188 @@
+use super::{
+    arithmetic::signed_difference,
+    flatten,
+    flow::{return_cmd, save_local_frame, FlowError},
+    instruction::{compute, jump, Comp, Dest, Instruction, Jump},
+    label,
+    memory::get_segment_alias,
+    register::{
+        set_a_reg_to_alias, set_a_reg_to_constant, set_alias, set_d_reg_to_a_reg,
+        set_d_reg_to_alias, set_mem_to_d_reg,
+    },
+    stack::{pop_and_prep_stack, push_d_reg_to_stack, SEGMENT_STACK},
+    MemCmdWriter,
+};
+use crate::{
+    code_writer::reg_mgr::{Reg, RegMgr, RegMgrError},
+    parser::{Arithmetic, Segment},
+};
+
+const CALL: &str = "$$call";
+const RETURN: &str = "$$return";
+const CMP: &str = "$$cmp";
+const HALT: &str = "$$halt";
+
+///A subroutine emitted once after the program, which call, return and comparison sites jump into
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub(crate) enum Routine {
+    Call,
+    Return,
+    Cmp,
+}
+
+///Where a call site leaves the argument count and the callee for `$$call`. Sites and routine take
+///them from an idle pool alike, so they get the same registers
+fn call_regs(regs: &RegMgr) -> Result<(Reg, Reg), RegMgrError> {
+    Ok((regs.next()?, regs.next()?))
+}
+
+///Where a comparison site leaves its return address for `$$cmp`
+fn cmp_return_reg(regs: &RegMgr) -> Result<Reg, RegMgrError> {
+    regs.next()
+}
+
+///Sets up `$$call` with the return address in D, and the argument count and callee in registers
+pub(crate) fn call_site(
+    name: &str,
+    arg_count: u16,
+    ret_label: &str,
+    regs: &RegMgr,
+) -> Result<Vec<Instruction>, RegMgrError> {
+    let (arg_count_reg, callee_reg) = call_regs(regs)?;
+    Ok(flatten(vec![
+        set_a_reg_to_constant(arg_count as i16),
+        set_d_reg_to_a_reg(),
+        set_alias(&arg_count_reg.to_string()),
+        set_mem_to_d_reg(),
+        set_alias(name),
+        set_d_reg_to_a_reg(),
+        set_alias(&callee_reg.to_string()),
+        set_mem_to_d_reg(),
+        set_alias(ret_label),
+        set_d_reg_to_a_reg(),
+        set_alias(CALL),
+        vec![jump(Comp::Zero, Jump::Jmp)],
+        label(ret_label),
+    ]))
+}
+
+pub(crate) fn return_site() -> Vec<Instruction> {
+    flatten(vec![set_alias(RETURN), vec![jump(Comp::Zero, Jump::Jmp)]])
+}
+
+///Jumps into the `$$cmp` entry for `cmp` with the return address in D, which the entry keeps in
+///a register while it compares
+pub(crate) fn cmp_site(
+    cmp: Arithmetic,
+    ret_label: &str,
+    regs: &RegMgr,
+) -> Result<Vec<Instruction>, RegMgrError> {
+    let _return_reg = cmp_return_reg(regs)?;
+    Ok(flatten(vec![
+        set_alias(ret_label),
+        set_d_reg_to_a_reg(),
+        set_alias(&cmp_entry(cmp)),
+        vec![jump(Comp::Zero, Jump::Jmp)],
+        label(ret_label),
+    ]))
+}
+
+fn cmp_entry(cmp: Arithmetic) -> String {
+    format!("{}.{}", CMP, format!("{:?}", cmp).to_lowercase())
+}
+
+///Stops a program that runs off its end from falling into the routines after it
+pub(crate) fn halt() -> Vec<Instruction> {
+    flatten(vec![
+        label(HALT),
+        set_alias(HALT),
+        vec![jump(Comp::Zero, Jump::Jmp)],
+    ])
+}
+
+pub(crate) fn routine(
+    routine: Routine,
+    gen_purp_reg: &RegMgr,
+    mem_cmd_writer: &MemCmdWriter,
+    overflow_safe: bool,
+) -> Result<Vec<Instruction>, FlowError> {
+    Ok(match routine {
+        Routine::Call => call_routine(gen_purp_reg)?,
+        Routine::Return => flatten(vec![
+            label(RETURN),
+            return_cmd(gen_purp_reg, mem_cmd_writer)?,
+        ]),
+        Routine::Cmp => cmp_routine(gen_purp_reg, overflow_safe)?,
+    })
+}
+
+fn call_routine(regs: &RegMgr) -> Result<Vec<Instruction>, RegMgrError> {
+    let (arg_count_reg, callee_reg) = call_regs(regs)?;
+    Ok(flatten(vec![
+        label(CALL),
+        push_d_reg_to_stack(),
+        save_local_frame(),
+        set_d_reg_to_alias(&arg_count_reg.to_string(), Some(5)),
+        set_alias(SEGMENT_STACK),
+        vec![compute(Dest::D, Comp::MMinusD)],
+        set_alias(get_segment_alias(&Segment::Argument)),
+        set_mem_to_d_reg(),
+        set_d_reg_to_alias(SEGMENT_STACK, None),
+        set_alias(get_segment_alias(&Segment::Local)),
+        set_mem_to_d_reg(),
+        set_a_reg_to_alias(&callee_reg.to_string()),
+        vec![jump(Comp::Zero, Jump::Jmp)],
+    ]))
+}
+
+///An entry per comparison, each leaving true in x's slot unless the jump says otherwise, then a
+///shared exit back to the site
+fn cmp_routine(regs: &RegMgr, overflow_safe: bool) -> Result<Vec<Instruction>, RegMgrError> {
+    let return_reg = cmp_return_reg(regs)?;
+    let exit = format!("{}.exit", CMP);
+    let entries = [
+        (Arithmetic::Eq, Jump::Jeq),
+        (Arithmetic::Gt, Jump::Jgt),
+        (Arithmetic::Lt, Jump::Jlt),
+    ];
+    Ok(flatten(vec![
+        entries
+            .into_iter()
+            .flat_map(|(cmp, jmp_cmd)| {
+                let entry = cmp_entry(cmp);
+                let difference = match cmp {
+                    Arithmetic::Gt | Arithmetic::Lt if overflow_safe => flatten(vec![
+                        signed_difference(
+                            &format!("{}.y_neg", entry),
+                            &format!("{}.same_sign", entry),
+                            &format!("{}.done", entry),
+                        ),
+                        set_a_reg_to_alias(SEGMENT_STACK),
+                    ]),
+                    _ => flatten(vec![
+                        pop_and_prep_stack(),
+                        vec![compute(Dest::D, Comp::MMinusD)],
+                    ]),
+                };
+                flatten(vec![
+                    label(&entry),
+                    set_alias(&return_reg.to_string()),
+                    set_mem_to_d_reg(),
+                    difference,
+                    vec![compute(Dest::M, Comp::MinusOne)],
+                    set_alias(&exit),
+                    vec![jump(Comp::D, jmp_cmd)],
+                    set_alias(SEGMENT_STACK),
+                    vec![compute(Dest::A, Comp::M), compute(Dest::M, Comp::Zero)],
+                    set_alias(&exit),
+                    vec![jump(Comp::Zero, Jump::Jmp)],
+                ])
+            })
+            .collect(),
+        label(&exit),
+        set_alias(SEGMENT_STACK),
+        vec![compute(Dest::M, Comp::MPlusOne)],
+        set_a_reg_to_alias(&return_reg.to_string()),
+        vec![jump(Comp::Zero, Jump::Jmp)],
+    ]))
+}
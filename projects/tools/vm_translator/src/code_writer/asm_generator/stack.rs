@@ -1,19 +1,20 @@
 use super::{
     flatten,
+    instruction::{at, compute, Comp, Dest, Instruction},
     register::{set_a_reg_to_alias, set_a_reg_to_pointer, set_d_reg_to_mem},
 };
 
 pub(super) const SEGMENT_STACK: &str = "SP";
 
-pub(super) fn push_d_reg_to_stack() -> Vec<String> {
+pub(crate) fn push_d_reg_to_stack() -> Vec<Instruction> {
     flatten(vec![
         set_a_reg_to_alias(SEGMENT_STACK),
-        vec!["M=D".to_owned()],
+        vec![compute(Dest::M, Comp::D)],
         inc_stack_pointer(),
     ])
 }
 
-pub(super) fn pop_stack_to_d_reg() -> Vec<String> {
+pub(super) fn pop_stack_to_d_reg() -> Vec<Instruction> {
     flatten(vec![
         dec_stack_pointer(),
         set_a_reg_to_pointer(),
@@ -21,7 +22,17 @@ pub(super) fn pop_stack_to_d_reg() -> Vec<String> {
     ])
 }
 
-pub(crate) fn pop_and_prep_stack() -> Vec<String> {
+///Pops into D, decrementing SP and addressing the old top in one instruction
+pub(crate) fn take_stack_top() -> Vec<Instruction> {
+    flatten(vec![address_stack_top(), set_d_reg_to_mem()])
+}
+
+///Decrements SP and addresses the old top, leaving D alone
+pub(super) fn address_stack_top() -> Vec<Instruction> {
+    vec![at(SEGMENT_STACK), compute(Dest::AM, Comp::MMinusOne)]
+}
+
+pub(crate) fn pop_and_prep_stack() -> Vec<Instruction> {
     flatten(vec![
         pop_stack_to_d_reg(),
         dec_stack_pointer(),
@@ -29,10 +40,10 @@ pub(crate) fn pop_and_prep_stack() -> Vec<String> {
     ])
 }
 
-pub(super) fn dec_stack_pointer() -> Vec<String> {
-    vec!["@SP".to_owned(), "M=M-1".to_owned()]
+pub(super) fn dec_stack_pointer() -> Vec<Instruction> {
+    vec![at(SEGMENT_STACK), compute(Dest::M, Comp::MMinusOne)]
 }
 
-pub(super) fn inc_stack_pointer() -> Vec<String> {
-    vec!["@SP".to_owned(), "M=M+1".to_owned()]
+pub(super) fn inc_stack_pointer() -> Vec<Instruction> {
+    vec![at(SEGMENT_STACK), compute(Dest::M, Comp::MPlusOne)]
 }
@@ -17,9 +17,11 @@ impl LabelGenerator {
         }
     }
 
+    ///Generates `PREFIX$.<id>`, the `$` keeps it apart from the `<FileStem>.<i>` statics when the
+    ///file name is in upper case
     pub(super) fn generate(&mut self) -> String {
         self.id += 1;
-        self.last = self.create(format!("{}", &self.id).as_str());
+        self.last = format!("{}$.{}", self.prefix, self.id);
         self.get_last()
     }
 
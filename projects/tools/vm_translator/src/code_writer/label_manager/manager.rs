@@ -2,20 +2,25 @@ use super::label_generator::LabelGenerator;
 
 pub struct LabelManager {
     generators: Vec<LabelGenerator>,
+    function: Option<String>,
 }
 
 impl LabelManager {
     pub fn new(filename: &str) -> Self {
         Self {
             generators: vec![LabelGenerator::new(filename)],
+            function: None,
         }
     }
 
     pub fn set_filename(&mut self, filename: &str) {
         self.generators = vec![LabelGenerator::new(filename)];
+        self.function = None;
     }
 
+    ///A function lasts until the next one starts, as it may return from several places
     pub fn start_function(&mut self, function_name: &str) {
+        self.generators.truncate(1);
         let last_label = self
             .generators
             .last()
@@ -23,10 +28,16 @@ impl LabelManager {
         self.generators.push(LabelGenerator::new(
             format!("{}.{}$", last_label, function_name).as_str(),
         ));
+        self.function = Some(function_name.to_owned());
     }
 
-    pub fn end_function(&mut self) {
-        self.generators.pop();
+    ///Scopes a VM `label`, `goto` or `if-goto` target to the current function as
+    ///`functionName$label`, labels outside of any function are left as they are
+    pub fn qualify_label(&self, label: &str) -> String {
+        self.function.as_ref().map_or_else(
+            || label.to_owned(),
+            |function| format!("{}${}", function, label),
+        )
     }
 
     pub fn generate_static(&mut self) -> String {
@@ -47,3 +58,37 @@ impl LabelManager {
             })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_leaves_labels_outside_functions_as_they_are() {
+        let manager = LabelManager::new("Main");
+        assert_eq!(manager.qualify_label("LOOP"), "LOOP");
+    }
+
+    #[test]
+    fn it_scopes_labels_to_the_current_function() {
+        let mut manager = LabelManager::new("Main");
+        manager.start_function("Main.first");
+        assert_eq!(manager.qualify_label("LOOP"), "Main.first$LOOP");
+        manager.start_function("Main.second");
+        assert_eq!(manager.qualify_label("LOOP"), "Main.second$LOOP");
+        manager.set_filename("Other");
+        assert_eq!(manager.qualify_label("LOOP"), "LOOP");
+    }
+
+    #[test]
+    fn it_keeps_generating_labels_after_a_function_returns() {
+        let mut manager = LabelManager::new("Main");
+        manager.start_function("Main.first");
+        manager.start_function("Main.second");
+        assert_eq!(manager.generate_static(), "MAIN$.1");
+        assert_eq!(
+            manager.generate_label("Sys.halt$ret", true),
+            "MAIN.MAIN.SECOND$.Sys.halt$ret.1"
+        );
+    }
+}
@@ -3,4 +3,5 @@ mod reg_mgr;
 mod label_manager;
 
 pub(crate) mod writer;
+pub use asm_generator::{RuntimeError, TRAP_CELL};
 pub(crate) use writer::{CodeWriter, CodeWriterError};
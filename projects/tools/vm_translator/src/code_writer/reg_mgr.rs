@@ -1,4 +1,4 @@
-use std::{fmt::Display, rc::Rc};
+use std::{cell::RefCell, fmt::Display, rc::Rc};
 
 #[derive(thiserror::Error, Debug)]
 pub enum RegMgrError {
@@ -6,43 +6,109 @@ pub enum RegMgrError {
     InvalidRange(String),
     #[error("No temp space available")]
     NoFreeTmpSpace,
+    #[error("Temp register {0} is given more than once")]
+    DuplicateRegister(String),
+    #[error("Temp register {0} holds the stack or a segment pointer")]
+    ReservedRegister(String),
 }
 
-pub(crate) struct RegMgr {
-    registers: Vec<Rc<String>>,
+///The symbols for the stack and segment pointers, which can't be used as temp registers
+const POINTERS: [&str; 10] = [
+    "SP", "LCL", "ARG", "THIS", "THAT", "R0", "R1", "R2", "R3", "R4",
+];
+
+struct Pool {
+    registers: Vec<String>,
+    in_use: Vec<bool>,
 }
 
+///Hands out temp registers as guards, each free again as soon as its guard drops, so generators
+///can nest as deeply as the registers go. Allocation always takes the first free register, so
+///code generated separately from an idle pool, such as a shared routine and its call sites,
+///agrees on which registers it uses
 #[derive(Clone)]
-pub(crate) struct Reg(Rc<String>);
+pub(crate) struct RegMgr(Rc<RefCell<Pool>>);
+
+///A temp register held until dropped
+pub(crate) struct Reg {
+    pool: Rc<RefCell<Pool>>,
+    idx: usize,
+    name: String,
+}
 
 impl RegMgr {
     pub(super) fn new(start: u8, end: u8) -> Result<Self, RegMgrError> {
         if start > end {
             Err(RegMgrError::InvalidRange(format!("{}:{}", start, end)))
         } else {
-            Ok(Self {
-                registers: (start..=end).map(|i| Rc::new(format!("R{}", i))).collect(),
-            })
+            Self::from_registers((start..=end).map(|i| format!("R{}", i)).collect())
+        }
+    }
+
+    ///Uses any symbols but the pointers as temp registers, which the assembler allocates as
+    ///variables unless they are predefined
+    pub(crate) fn with_registers(registers: Vec<String>) -> Result<Self, RegMgrError> {
+        if let Some(pointer) = registers.iter().find(|r| POINTERS.contains(&r.as_str())) {
+            return Err(RegMgrError::ReservedRegister(pointer.clone()));
         }
+        Self::from_registers(registers)
     }
 
-    pub(super) fn next(&mut self) -> Result<Reg, RegMgrError> {
-        self.registers
+    fn from_registers(registers: Vec<String>) -> Result<Self, RegMgrError> {
+        if registers.is_empty() {
+            return Err(RegMgrError::InvalidRange("no registers".to_owned()));
+        }
+        for (i, register) in registers.iter().enumerate() {
+            if registers[..i].contains(register) {
+                return Err(RegMgrError::DuplicateRegister(register.clone()));
+            }
+        }
+        let in_use = vec![false; registers.len()];
+        Ok(Self(Rc::new(RefCell::new(Pool { registers, in_use }))))
+    }
+
+    pub(crate) fn next(&self) -> Result<Reg, RegMgrError> {
+        let mut pool = self.0.borrow_mut();
+        let idx = pool
+            .in_use
             .iter()
-            .find(|i| Rc::strong_count(i) < 2)
-            .map(|i| Reg(i.clone()))
-            .ok_or(RegMgrError::NoFreeTmpSpace)
+            .position(|in_use| !in_use)
+            .ok_or(RegMgrError::NoFreeTmpSpace)?;
+        pool.in_use[idx] = true;
+        Ok(Reg {
+            pool: self.0.clone(),
+            idx,
+            name: pool.registers[idx].clone(),
+        })
+    }
+
+    ///How many registers are held, which is none between VM commands
+    pub(crate) fn in_use(&self) -> usize {
+        self.0
+            .borrow()
+            .in_use
+            .iter()
+            .filter(|in_use| **in_use)
+            .count()
+    }
+}
+
+impl Drop for Reg {
+    fn drop(&mut self) {
+        self.pool.borrow_mut().in_use[self.idx] = false;
     }
 }
 
 impl Display for Reg {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0.clone())
+        write!(f, "{}", self.name)
     }
 }
 
 #[cfg(test)]
 mod test {
+    use assert_matches::assert_matches;
+
     use super::*;
 
     #[test]
@@ -53,14 +119,14 @@ mod test {
 
     #[test]
     fn it_generates_register_for_one_register_item() {
-        let mut mgr = RegMgr::new(0, 1).unwrap();
+        let mgr = RegMgr::new(0, 1).unwrap();
         let reg = mgr.next().unwrap();
-        assert_eq!(reg.0.to_string(), "R0".to_owned());
+        assert_eq!(reg.to_string(), "R0".to_owned());
     }
 
     #[test]
     fn it_generates_register_for_entire_range() {
-        let mut mgr = RegMgr::new(0, 9).unwrap();
+        let mgr = RegMgr::new(0, 9).unwrap();
         let mut regs = Vec::new();
         for i in 0..=9 {
             let next = mgr.next();
@@ -73,7 +139,7 @@ mod test {
 
     #[test]
     fn it_reuses_released_regs() {
-        let mut mgr = RegMgr::new(0, 9).unwrap();
+        let mgr = RegMgr::new(0, 9).unwrap();
         for _ in 0..=9 {
             let next = mgr.next();
             assert!(next.is_ok());
@@ -84,7 +150,7 @@ mod test {
 
     #[test]
     fn it_raises_an_error_when_no_more_regs_available() {
-        let mut mgr = RegMgr::new(0, 9).unwrap();
+        let mgr = RegMgr::new(0, 9).unwrap();
         let mut regs = Vec::new();
         for i in 0..=10 {
             let next = mgr.next();
@@ -95,4 +161,70 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn it_takes_any_symbols_but_no_duplicates() {
+        let mgr = RegMgr::with_registers(vec!["R5".to_owned(), "scratch".to_owned()]).unwrap();
+        let first = mgr.next().unwrap();
+        assert_eq!(mgr.next().unwrap().to_string(), "scratch");
+        assert_eq!(first.to_string(), "R5");
+        assert!(RegMgr::with_registers(vec![]).is_err());
+        assert!(RegMgr::with_registers(vec!["R5".to_owned(), "R5".to_owned()]).is_err());
+    }
+
+    #[test]
+    fn it_rejects_the_stack_and_segment_pointers() {
+        for pointer in ["SP", "LCL", "ARG", "THIS", "THAT", "R0", "R4"] {
+            let result = RegMgr::with_registers(vec!["R5".to_owned(), pointer.to_owned()]);
+            assert_matches!(result.err(), Some(RegMgrError::ReservedRegister(r)) if r == pointer);
+        }
+    }
+
+    ///Stands in for a generator holding one register while it calls `depth` nested generators,
+    ///returning the registers seen from the outermost in
+    fn nest(mgr: &RegMgr, depth: usize) -> Result<Vec<String>, RegMgrError> {
+        let reg = mgr.next()?;
+        let mut seen = vec![reg.to_string()];
+        if depth > 0 {
+            seen.extend(nest(mgr, depth - 1)?);
+            //A sibling after the nested call gets the register it freed
+            assert_eq!(mgr.next()?.to_string(), seen[1]);
+        }
+        Ok(seen)
+    }
+
+    #[test]
+    fn it_hands_nested_generators_distinct_registers() {
+        let mgr = RegMgr::new(0, 15).unwrap();
+        for depth in 0..16 {
+            let seen = nest(&mgr, depth).unwrap();
+            let expected: Vec<_> = (0..=depth).map(|i| format!("R{}", i)).collect();
+            assert_eq!(seen, expected);
+            assert_eq!(mgr.in_use(), 0);
+        }
+        assert!(nest(&mgr, 16).is_err());
+        assert_eq!(mgr.in_use(), 0);
+    }
+
+    #[test]
+    fn it_frees_registers_dropped_in_any_order() {
+        let mgr = RegMgr::new(13, 15).unwrap();
+        let mut held: Vec<Option<Reg>> = Vec::new();
+        //A fixed pseudo random walk of allocations and drops
+        let mut state = 7_u32;
+        for _ in 0..1000 {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let slot = (state >> 16) as usize % 3;
+            held.resize_with(3, || None);
+            held[slot] = match held[slot].take() {
+                Some(_) => None,
+                None => Some(mgr.next().unwrap()),
+            };
+            let names: Vec<_> = held.iter().flatten().map(ToString::to_string).collect();
+            assert_eq!(mgr.in_use(), names.len());
+            for (i, name) in names.iter().enumerate() {
+                assert!(!names[..i].contains(name));
+            }
+        }
+    }
 }
@@ -1,9 +1,13 @@
-use std::{io::{self, Write}, rc::Rc, cell::RefCell};
+use std::{io::{self, Write}, rc::Rc, collections::BTreeSet};
 
-use crate::parser::{Command, ParsedCmd, Flow};
+use source_map::{SourceMap, SourceRange};
+
+use crate::{parser::{Arithmetic, Command, ParsedCmd, Flow, Goto, Location, Marker, Segment}, translator::{Bootstrap, CodegenOptions}};
 
 use super::{
-    asm_generator::{arithmetic, MemoryError, MemCmdWriter, flow, marker, FlowError},
+    asm_generator::{arithmetic, MemoryError, MemCmdWriter, flow, marker, FlowError, Routine, call_site, cmp_site, halt, return_site, routine,
+        arithmetic_on_d_reg, if_goto_on_d_reg, if_zero_goto_on_d_reg, push_d_reg_to_stack, set_d_reg_to_constant, set_mem_at_alias_to_address, take_stack_top, Instruction,
+        check_argument, check_stack_depth, check_stack_room, trap, trap_routine, RuntimeError, FRAME_SIZE, TRAP_CELL},
     reg_mgr::{RegMgr, RegMgrError}, label_manager::LabelManager,
 };
 
@@ -16,37 +20,180 @@ pub enum CodeWriterError {
     #[error("Memory manipulation asm error: {0}")]
     Memory(#[from] MemoryError),
     #[error("Control flow error: {0}")]
-    Flow(#[from] FlowError)
+    Flow(#[from] FlowError),
+    #[error("Bootstrap {0} address {1} can't be loaded into the A register")]
+    BootstrapAddress(&'static str, u16),
+    #[error("runtime checks need the whole stack in RAM, so they can't be combined with stack caching")]
+    ChecksWithStackCaching,
+    #[error("runtime checks keep their error code in {0}, so it can't be a temp register")]
+    TrapCellAsTempRegister(String),
+    #[error("{0}: {1}")]
+    Command(Location, Box<CodeWriterError>),
 }
 
+///What the source map calls code that isn't translated from any VM function
+const BOOTSTRAP: &str = "bootstrap";
+const SHARED_ROUTINES: &str = "shared routines";
+
 pub struct CodeWriter<W: Write> {
     out_stream: W,
     label_manager: LabelManager,
-    gen_purp_reg: Rc<RefCell<RegMgr>>,
+    gen_purp_reg: RegMgr,
     mem_cmd_writer: Rc<MemCmdWriter>,
+    options: CodegenOptions,
+    used_routines: BTreeSet<Routine>,
+    ///Whether the top of the stack is held in D rather than RAM, when stack caching
+    tos_in_d: bool,
+    instructions: usize,
+    ///The function being written, or the module outside of functions
+    function: String,
+    ///The locals of the function being written, when there is one, which runtime checks need
+    locals: Option<u16>,
+    used_traps: BTreeSet<RuntimeError>,
+    source_ranges: Vec<SourceRange>,
 }
 
-impl<'a, W: Write> CodeWriter<W> {
+impl<W: Write> CodeWriter<W> {
     pub fn new(out_stream: W) -> Result<Self, CodeWriterError> {
-        let gen_purp_reg = Rc::new(RefCell::new(RegMgr::new(13,15)?));
+        Self::with_options(out_stream, &CodegenOptions::default())
+    }
+
+    pub fn with_options(out_stream: W, options: &CodegenOptions) -> Result<Self, CodeWriterError> {
+        if options.runtime_checks && options.stack_caching {
+            return Err(CodeWriterError::ChecksWithStackCaching);
+        }
+        let trap_cell = format!("R{}", TRAP_CELL);
+        let gen_purp_reg = match &options.temp_registers {
+            Some(registers) if options.runtime_checks && registers.contains(&trap_cell) => {
+                return Err(CodeWriterError::TrapCellAsTempRegister(trap_cell));
+            }
+            Some(registers) => RegMgr::with_registers(registers.clone())?,
+            None if options.runtime_checks => RegMgr::new(13, 14)?,
+            None => RegMgr::new(13, 15)?,
+        };
         let mem_cmd_writer = Rc::new(MemCmdWriter::new("asm".to_owned(), gen_purp_reg.clone()));
         let label_manager = LabelManager::new("asm");
         Ok(Self {
             out_stream,
             label_manager,
             gen_purp_reg,
-            mem_cmd_writer
+            mem_cmd_writer,
+            options: options.clone(),
+            used_routines: BTreeSet::new(),
+            tos_in_d: false,
+            instructions: 0,
+            function: BOOTSTRAP.to_owned(),
+            locals: None,
+            used_traps: BTreeSet::new(),
+            source_ranges: Vec::new(),
         })
     }
 
-    pub fn init(&mut self) -> Result<(), CodeWriterError> {
-        writeln!(self.out_stream, "@256\nD=A\n@SP\nM=D")?;
-        self.write(Command::new("call Sys.init 0".to_string(), ParsedCmd::Flow(Flow::Call("Sys.init".to_string(), 0))))
+    ///Gives back the stream written to, such as a buffer to assemble
+    pub fn into_inner(self) -> W {
+        self.out_stream
+    }
+
+    pub fn options(&self) -> &CodegenOptions {
+        &self.options
+    }
+
+    ///How many Hack instructions have been written so far, leaving out labels and comments
+    pub fn instruction_count(&self) -> usize {
+        self.instructions
+    }
+
+    ///Maps the ROM addresses of each VM command written so far, along with the bootstrap and shared
+    ///routines, to where they came from
+    pub fn source_map(&self) -> SourceMap {
+        SourceMap::new(self.source_ranges.clone())
+    }
+
+    ///Maps the instructions written since `start` to `location` in the current function
+    fn map_source(&mut self, start: usize, location: Option<&Location>) {
+        if start == self.instructions {
+            return;
+        }
+        self.source_ranges.push(SourceRange {
+            start: start as u16,
+            end: self.instructions as u16,
+            function: self.function.clone(),
+            file: location.map(|location| location.file.to_string()),
+            line: location.map(|location| location.line),
+        });
+    }
+
+    ///Renders the generated instructions, the only place they are turned into text
+    fn write_asm(&mut self, asm: Vec<Instruction>) -> Result<(), CodeWriterError> {
+        for instruction in asm {
+            if instruction.is_emitted() {
+                self.instructions += 1;
+            }
+            writeln!(self.out_stream, "{}", instruction)?;
+        }
+        Ok(())
+    }
+
+    ///Writes the shared routines the program used after it, behind a halt loop so that code
+    ///running off the end of the program doesn't fall into them
+    pub fn finish(&mut self) -> Result<(), CodeWriterError> {
+        if self.tos_in_d {
+            self.tos_in_d = false;
+            let start = self.instructions;
+            self.write_asm(push_d_reg_to_stack())?;
+            self.map_source(start, None);
+        }
+        if self.used_routines.is_empty() && self.used_traps.is_empty() {
+            return Ok(());
+        }
+        self.comment("shared routines")?;
+        let mut asm = halt();
+        for used in &self.used_routines {
+            asm.extend(routine(*used, &self.gen_purp_reg, &self.mem_cmd_writer, self.options.safe_comparisons)?);
+        }
+        if !self.used_traps.is_empty() {
+            asm.extend(trap_routine(&self.used_traps));
+        }
+        let start = self.instructions;
+        self.write_asm(asm)?;
+        self.function = SHARED_ROUTINES.to_owned();
+        self.map_source(start, None);
+        Ok(())
+    }
+
+    pub fn init(&mut self, bootstrap: &Bootstrap) -> Result<(), CodeWriterError> {
+        let start = self.instructions;
+        let pointers = [
+            ("SP", Some(bootstrap.sp)),
+            ("LCL", bootstrap.lcl),
+            ("ARG", bootstrap.arg),
+            ("THIS", bootstrap.this),
+            ("THAT", bootstrap.that),
+        ];
+        for (pointer, addr) in pointers {
+            match addr {
+                Some(addr) if addr > i16::MAX as u16 => {
+                    return Err(CodeWriterError::BootstrapAddress(pointer, addr))
+                }
+                Some(addr) => self.write_asm(set_mem_at_alias_to_address(pointer, addr))?,
+                None => {}
+            }
+        }
+        let entry = bootstrap.entry.clone();
+        let location = Location { file: BOOTSTRAP.into(), line: 0 };
+        self.write(Command::new(location, format!("call {} 0", entry), ParsedCmd::Flow(Flow::Call(entry, 0))))?;
+        //The call maps itself, leaving the pointers set up before it
+        self.source_ranges.pop();
+        self.function = BOOTSTRAP.to_owned();
+        self.map_source(start, None);
+        Ok(())
     }
 
     pub fn set_namespace(&mut self, namespace: &str) {
         self.mem_cmd_writer = Rc::new(MemCmdWriter::new(namespace.to_owned(), self.gen_purp_reg.clone()));
         self.label_manager.set_filename(namespace);
+        self.function = namespace.to_owned();
+        self.locals = None;
     }
 
     pub fn comment(&mut self, comment: &str) -> Result<(), CodeWriterError> {
@@ -54,18 +201,42 @@ impl<'a, W: Write> CodeWriter<W> {
     }
 
     pub fn write(&mut self, cmd: Command) -> Result<(), CodeWriterError> {
+        self.write_located(&cmd)
+            .map_err(|err| CodeWriterError::Command(cmd.location().clone(), Box::new(err)))
+    }
+
+    fn write_located(&mut self, cmd: &Command) -> Result<(), CodeWriterError> {
         self.comment(cmd.original())?;
-        if let Some(asm) = self.cmd_to_asm(cmd.parsed().clone())? {
-            for line in asm {
-                writeln!(self.out_stream, "{}", line)?;
-            }
+        if let ParsedCmd::Marker(Marker::Function(name, _)) = cmd.parsed() {
+            self.function = name.clone();
+        }
+        let start = self.instructions;
+        let (before, after) = if self.options.runtime_checks {
+            self.runtime_checks(cmd.parsed())
+        } else {
+            (vec![], vec![])
+        };
+        let asm = if self.options.stack_caching {
+            self.cached_cmd_to_asm(cmd.parsed().clone())?
+        } else {
+            self.cmd_to_asm(cmd.parsed().clone())?
         };
+        debug_assert_eq!(self.gen_purp_reg.in_use(), 0, "temp registers held past {:?}", cmd);
+        if let Some(asm) = asm {
+            self.write_asm([before, asm, after].concat())?;
+        };
+        self.map_source(start, Some(cmd.location()));
         Ok(())
     }
 
-    fn cmd_to_asm(&mut self, cmd: ParsedCmd) -> Result<Option<Vec<String>>, CodeWriterError> {
+    fn cmd_to_asm(&mut self, cmd: ParsedCmd) -> Result<Option<Vec<Instruction>>, CodeWriterError> {
+        if self.options.shared_routines {
+            if let Some(asm) = self.shared_routine_site(&cmd)? {
+                return Ok(Some(asm));
+            }
+        }
         match cmd {
-            ParsedCmd::Arithmetic(arr) => Ok(Some(arithmetic(arr, &mut self.label_manager))),
+            ParsedCmd::Arithmetic(arr) => Ok(Some(arithmetic(arr, &mut self.label_manager, self.options.safe_comparisons))),
             ParsedCmd::PushConstant(value) => Ok(Some(self.mem_cmd_writer.push_constant(value))),
             ParsedCmd::Push(segment, idx) => Ok(Some(self.mem_cmd_writer.push_to_stack(segment, idx)?)),
             ParsedCmd::Pop(segment, idx) => Ok(Some(self.mem_cmd_writer.pop_stack_to(segment, idx)?)),
@@ -74,6 +245,101 @@ impl<'a, W: Write> CodeWriter<W> {
             ParsedCmd::Noop => Ok(None),
         }
     }
+
+    ///The checks to run before and after `cmd`, noting the traps they jump to. The stack is only
+    ///checked against the frame, and arguments and locals against their bounds, within functions
+    fn runtime_checks(&mut self, cmd: &ParsedCmd) -> (Vec<Instruction>, Vec<Instruction>) {
+        let (growth, pops) = match cmd {
+            ParsedCmd::PushConstant(_) | ParsedCmd::Push(..) => (1, 0),
+            ParsedCmd::Arithmetic(Arithmetic::Neg | Arithmetic::Not | Arithmetic::Inc | Arithmetic::Dec) => (0, 1),
+            ParsedCmd::Arithmetic(_) => (0, 2),
+            ParsedCmd::Pop(..) | ParsedCmd::Flow(Flow::Goto(Goto::Conditional | Goto::IfZero, _) | Flow::Return) => (0, 1),
+            ParsedCmd::Flow(Flow::Call(_, args)) => (FRAME_SIZE, *args),
+            //The locals are only checked for room once they are pushed, as the check must come
+            //after the function's label
+            ParsedCmd::Marker(Marker::Function(_, locals)) => {
+                self.locals = Some(*locals);
+                self.used_traps.insert(RuntimeError::StackOverflow);
+                return (vec![], check_stack_room(0));
+            }
+            ParsedCmd::Flow(Flow::Goto(Goto::Direct, _)) | ParsedCmd::Marker(Marker::Label(_)) | ParsedCmd::Noop => {
+                return (vec![], vec![])
+            }
+        };
+        let mut checks = Vec::new();
+        if growth > 0 {
+            self.used_traps.insert(RuntimeError::StackOverflow);
+            checks.extend(check_stack_room(growth));
+        }
+        if let Some(locals) = self.locals {
+            if pops > 0 {
+                self.used_traps.insert(RuntimeError::StackUnderflow);
+                checks.extend(check_stack_depth(locals, pops));
+            }
+            match cmd {
+                ParsedCmd::Push(Segment::Local, idx) | ParsedCmd::Pop(Segment::Local, idx) if *idx >= locals => {
+                    self.used_traps.insert(RuntimeError::LocalOutOfBounds);
+                    checks.extend(trap(RuntimeError::LocalOutOfBounds));
+                }
+                ParsedCmd::Push(Segment::Argument, idx) | ParsedCmd::Pop(Segment::Argument, idx) => {
+                    self.used_traps.insert(RuntimeError::ArgumentOutOfBounds);
+                    checks.extend(check_argument(*idx));
+                }
+                _ => {}
+            }
+        }
+        (checks, vec![])
+    }
+
+    ///Generates `cmd` for a stack whose top may be in D, leaving it there whenever the command
+    ///ends by pushing a value. Anything that other code may jump to or from sees the whole stack
+    ///in RAM, so the cached value is spilled before labels, jumps, calls and returns
+    fn cached_cmd_to_asm(&mut self, cmd: ParsedCmd) -> Result<Option<Vec<Instruction>>, CodeWriterError> {
+        let (take, spill) = if self.tos_in_d {
+            (vec![], push_d_reg_to_stack())
+        } else {
+            (take_stack_top(), vec![])
+        };
+        let (asm, tos_in_d) = match cmd {
+            ParsedCmd::Noop => return Ok(None),
+            ParsedCmd::PushConstant(value) => ([spill, set_d_reg_to_constant(value)].concat(), true),
+            ParsedCmd::Push(segment, idx) => ([spill, self.mem_cmd_writer.load_to_d_reg(segment, idx)?].concat(), true),
+            ParsedCmd::Pop(segment, idx) => ([take, self.mem_cmd_writer.store_d_reg_to(segment, idx)?].concat(), false),
+            ParsedCmd::Arithmetic(Arithmetic::Eq | Arithmetic::Gt | Arithmetic::Lt) if self.options.shared_routines => {
+                ([spill, self.cmd_to_asm(cmd)?.unwrap_or_default()].concat(), false)
+            }
+            ParsedCmd::Arithmetic(arr) => ([take, arithmetic_on_d_reg(arr, &mut self.label_manager, self.options.safe_comparisons)].concat(), true),
+            ParsedCmd::Flow(Flow::Goto(Goto::Conditional, ref label)) => {
+                let label = self.label_manager.qualify_label(label);
+                ([take, if_goto_on_d_reg(&label)].concat(), false)
+            }
+            ParsedCmd::Flow(Flow::Goto(Goto::IfZero, ref label)) => {
+                let label = self.label_manager.qualify_label(label);
+                ([take, if_zero_goto_on_d_reg(&label)].concat(), false)
+            }
+            cmd => ([spill, self.cmd_to_asm(cmd)?.unwrap_or_default()].concat(), false),
+        };
+        self.tos_in_d = tos_in_d;
+        Ok(Some(asm))
+    }
+
+    ///The jump into a shared routine that stands in for `cmd`, for commands that have one
+    fn shared_routine_site(&mut self, cmd: &ParsedCmd) -> Result<Option<Vec<Instruction>>, CodeWriterError> {
+        let (used, asm) = match cmd {
+            ParsedCmd::Arithmetic(cmp @ (Arithmetic::Eq | Arithmetic::Gt | Arithmetic::Lt)) => {
+                let ret_label = self.label_manager.generate_static();
+                (Routine::Cmp, cmp_site(*cmp, &ret_label, &self.gen_purp_reg)?)
+            }
+            ParsedCmd::Flow(Flow::Call(name, args)) => {
+                let ret_label = self.label_manager.generate_label(format!("{}$ret", name).as_str(), true);
+                (Routine::Call, call_site(name, *args, &ret_label, &self.gen_purp_reg)?)
+            }
+            ParsedCmd::Flow(Flow::Return) => (Routine::Return, return_site()),
+            _ => return Ok(None),
+        };
+        self.used_routines.insert(used);
+        Ok(Some(asm))
+    }
 }
 
 #[cfg(test)]
@@ -95,7 +361,7 @@ mod test {
     }
 
     fn make_command(cmd: ParsedCmd) -> Command {
-        Command::new("".to_owned(), cmd)
+        Command::new(Location::default(), "".to_owned(), cmd)
     }
 
     #[test_case(
@@ -103,6 +369,16 @@ mod test {
         "//\n@5\nD=A\n@SP\nA=M\nM=D\n@SP\nM=M+1\n";
         "push constant to stack"
     )]
+    #[test_case(
+        ParsedCmd::PushConstant(-1),
+        "//\nD=-1\n@SP\nA=M\nM=D\n@SP\nM=M+1\n";
+        "push minus one to stack"
+    )]
+    #[test_case(
+        ParsedCmd::PushConstant(i16::MIN),
+        "//\n@32767\nD=!A\n@SP\nA=M\nM=D\n@SP\nM=M+1\n";
+        "push negative constant to stack"
+    )]
     #[test_case(
         ParsedCmd::Push(Segment::Argument, 0),
         "//\n@ARG\nA=M\nD=M\n@SP\nA=M\nM=D\n@SP\nM=M+1\n";
@@ -135,22 +411,22 @@ mod test {
     )]
     #[test_case(
         ParsedCmd::Push(Segment::Static, 1),
-        "//\n@ASM.1\nD=M\n@SP\nA=M\nM=D\n@SP\nM=M+1\n"; 
+        "//\n@asm.1\nD=M\n@SP\nA=M\nM=D\n@SP\nM=M+1\n"; 
         "push first static to stack"
     )]
     #[test_case(
         ParsedCmd::Pop(Segment::Static, 1),
-        "//\n@SP\nM=M-1\nA=M\nD=M\n@ASM.1\nM=D\n"; 
+        "//\n@SP\nM=M-1\nA=M\nD=M\n@asm.1\nM=D\n"; 
         "pop stack to first static"
     )]
     #[test_case(
         ParsedCmd::Push(Segment::Static, 5),
-        "//\n@ASM.5\nD=M\n@SP\nA=M\nM=D\n@SP\nM=M+1\n"; 
+        "//\n@asm.5\nD=M\n@SP\nA=M\nM=D\n@SP\nM=M+1\n"; 
         "push fifth static to stack"
     )]
     #[test_case(
         ParsedCmd::Pop(Segment::Static, 5),
-        "//\n@SP\nM=M-1\nA=M\nD=M\n@ASM.5\nM=D\n"; 
+        "//\n@SP\nM=M-1\nA=M\nD=M\n@asm.5\nM=D\n"; 
         "pop stack to fifth static"
     )]
     #[test_case(
@@ -170,7 +446,7 @@ mod test {
     )]
     #[test_case(
         ParsedCmd::Arithmetic(Arithmetic::Add),
-        "//\n@SP\nM=M-1\nA=M\nD=M\n@SP\nM=M-1\nA=M\nM=M+D\n@SP\nM=M+1\n"; 
+        "//\n@SP\nM=M-1\nA=M\nD=M\n@SP\nM=M-1\nA=M\nM=D+M\n@SP\nM=M+1\n"; 
         "add"
     )]
     #[test_case(
@@ -180,12 +456,12 @@ mod test {
     )]
     #[test_case(
         ParsedCmd::Arithmetic(Arithmetic::And),
-        "//\n@SP\nM=M-1\nA=M\nD=M\n@SP\nM=M-1\nA=M\nM=M&D\n@SP\nM=M+1\n";
+        "//\n@SP\nM=M-1\nA=M\nD=M\n@SP\nM=M-1\nA=M\nM=D&M\n@SP\nM=M+1\n";
         "and"
     )]
     #[test_case(
         ParsedCmd::Arithmetic(Arithmetic::Or),
-        "//\n@SP\nM=M-1\nA=M\nD=M\n@SP\nM=M-1\nA=M\nM=M|D\n@SP\nM=M+1\n";
+        "//\n@SP\nM=M-1\nA=M\nD=M\n@SP\nM=M-1\nA=M\nM=D|M\n@SP\nM=M+1\n";
         "or"
     )]
     #[test_case(
@@ -195,17 +471,17 @@ mod test {
     )]
     #[test_case(
         ParsedCmd::Arithmetic(Arithmetic::Lt),
-        "//\n@SP\nM=M-1\nA=M\nD=M\n@SP\nM=M-1\nA=M\nD=M-D\n@ASM.1\nD;JLT\nD=0\n@ASM.2\n0;JMP\n(ASM.1)\nD=-1\n(ASM.2)\n@SP\nA=M\nM=D\n@SP\nM=M+1\n";
+        "//\n@SP\nM=M-1\nA=M\nD=M\n@SP\nM=M-1\nA=M\nD=M-D\n@ASM$.1\nD;JLT\nD=0\n@ASM$.2\n0;JMP\n(ASM$.1)\nD=-1\n(ASM$.2)\n@SP\nA=M\nM=D\n@SP\nM=M+1\n";
         "lt"
     )]
     #[test_case(
         ParsedCmd::Arithmetic(Arithmetic::Gt),
-        "//\n@SP\nM=M-1\nA=M\nD=M\n@SP\nM=M-1\nA=M\nD=M-D\n@ASM.1\nD;JGT\nD=0\n@ASM.2\n0;JMP\n(ASM.1)\nD=-1\n(ASM.2)\n@SP\nA=M\nM=D\n@SP\nM=M+1\n";
+        "//\n@SP\nM=M-1\nA=M\nD=M\n@SP\nM=M-1\nA=M\nD=M-D\n@ASM$.1\nD;JGT\nD=0\n@ASM$.2\n0;JMP\n(ASM$.1)\nD=-1\n(ASM$.2)\n@SP\nA=M\nM=D\n@SP\nM=M+1\n";
         "gt"
     )]
     #[test_case(
         ParsedCmd::Arithmetic(Arithmetic::Eq),
-        "//\n@SP\nM=M-1\nA=M\nD=M\n@SP\nM=M-1\nA=M\nD=M-D\n@ASM.1\nD;JEQ\nD=0\n@ASM.2\n0;JMP\n(ASM.1)\nD=-1\n(ASM.2)\n@SP\nA=M\nM=D\n@SP\nM=M+1\n";
+        "//\n@SP\nM=M-1\nA=M\nD=M\n@SP\nM=M-1\nA=M\nD=M-D\n@ASM$.1\nD;JEQ\nD=0\n@ASM$.2\n0;JMP\n(ASM$.1)\nD=-1\n(ASM$.2)\n@SP\nA=M\nM=D\n@SP\nM=M+1\n";
         "eq"
     )]
     #[test_case(
@@ -220,7 +496,7 @@ mod test {
     )]
     #[test_case(
         ParsedCmd::Flow(Flow::Return),
-        "//\n@LCL\nD=M\n@R13\nM=D\n@5\nA=D-A\nD=M\n@R14\nM=D\n@ARG\nD=M\n@R15\nM=D\n@SP\nM=M-1\nA=M\nD=M\n@R15\nA=M\nM=D\n@ARG\nD=M+1\n@SP\nM=D\n@R13\nA=M-1\nD=M\n@THAT\nM=D\n@R13\nD=M\n@2\nA=D-A\nD=M\n@THIS\nM=D\n@R13\nD=M\n@3\nA=D-A\nD=M\n@ARG\nM=D\n@R13\nD=M\n@4\nA=D-A\nD=M\n@LCL\nM=D\n@R14\nA=M\n0;JMP\n";
+        "//\n@LCL\nD=M\n@R13\nM=D\n@5\nA=D-A\nD=M\n@R14\nM=D\n@SP\nM=M-1\nA=M\nD=M\n@ARG\nA=M\nM=D\n@ARG\nD=M+1\n@SP\nM=D\n@R13\nA=M-1\nD=M\n@THAT\nM=D\n@R13\nD=M\n@2\nA=D-A\nD=M\n@THIS\nM=D\n@R13\nD=M\n@3\nA=D-A\nD=M\n@ARG\nM=D\n@R13\nD=M\n@4\nA=D-A\nD=M\n@LCL\nM=D\n@R14\nA=M\n0;JMP\n";
         "return from function"
     )]
     #[test_case(
@@ -238,6 +514,11 @@ mod test {
         "//\n(test)\nD=0\n@SP\nA=M\nM=D\n@SP\nM=M+1\nD=0\n@SP\nA=M\nM=D\n@SP\nM=M+1\nD=0\n@SP\nA=M\nM=D\n@SP\nM=M+1\nD=0\n@SP\nA=M\nM=D\n@SP\nM=M+1\n";
         "declare function"
     )]
+    #[test_case(
+        ParsedCmd::Marker(Marker::Function("test".to_owned(), 300)),
+        "//\n(test)\n@300\nD=A\n(ASM$.1)\n@SP\nAM=M+1\nA=A-1\nM=0\nD=D-1\n@ASM$.1\nD;JGT\n";
+        "declare function with many locals"
+    )]
     fn test_asm_generation(cmd: ParsedCmd, expected_asm: &str) {
         let mut buff = make_buff();
         let mut writer = make_writer(&mut buff);
@@ -245,4 +526,135 @@ mod test {
         writer.write(cmd).unwrap();
         assert_eq!(expected_asm, std::str::from_utf8(&buff).unwrap())
     }
+
+    #[test]
+    fn it_locates_errors_at_their_command() {
+        let mut buff = make_buff();
+        let mut writer = make_writer(&mut buff);
+        let location = Location { file: "Main.vm".into(), line: 7 };
+        let cmd = Command::new(location, "pop constant 1".to_owned(), ParsedCmd::Pop(Segment::Constant, 1));
+        let err = writer.write(cmd).unwrap_err();
+        assert_eq!(err.to_string(), "Main.vm:7: Memory manipulation asm error: Cannot pop to segment Constant");
+    }
+
+    ///Every kind of command, with the indexes and counts that take the most temp registers
+    fn every_command() -> Vec<ParsedCmd> {
+        let mut cmds = vec![
+            ParsedCmd::Marker(Marker::Function("Main.f".to_owned(), 10)),
+            ParsedCmd::Marker(Marker::Label("LOOP".to_owned())),
+            ParsedCmd::PushConstant(-2),
+            ParsedCmd::Push(Segment::That, 100),
+            ParsedCmd::Pop(Segment::Local, 100),
+            ParsedCmd::Pop(Segment::Argument, 0),
+            ParsedCmd::Pop(Segment::Static, 3),
+            ParsedCmd::Flow(Flow::Call("Main.f".to_owned(), 3)),
+            ParsedCmd::Flow(Flow::Goto(Goto::Conditional, "LOOP".to_owned())),
+            ParsedCmd::Flow(Flow::Goto(Goto::IfZero, "LOOP".to_owned())),
+            ParsedCmd::Flow(Flow::Goto(Goto::Direct, "LOOP".to_owned())),
+            ParsedCmd::Flow(Flow::Return),
+        ];
+        let arithmetic = [Arithmetic::Add, Arithmetic::Sub, Arithmetic::Neg, Arithmetic::Eq, Arithmetic::Gt,
+            Arithmetic::Lt, Arithmetic::And, Arithmetic::Or, Arithmetic::Not, Arithmetic::Inc, Arithmetic::Dec];
+        cmds.extend(arithmetic.into_iter().map(ParsedCmd::Arithmetic));
+        cmds
+    }
+
+    #[test]
+    fn it_needs_no_more_than_two_temp_registers() {
+        for (shared_routines, stack_caching, safe_comparisons) in
+            [(false, false, false), (true, false, true), (false, true, true), (true, true, false)]
+        {
+            let options = CodegenOptions {
+                shared_routines,
+                stack_caching,
+                safe_comparisons,
+                temp_registers: Some(vec!["T0".to_owned(), "T1".to_owned()]),
+                ..CodegenOptions::default()
+            };
+            let mut writer = CodeWriter::with_options(io::sink(), &options).unwrap();
+            //Interleaving every pair of commands, each of which must leave the pool idle
+            for first in every_command() {
+                for second in every_command() {
+                    writer.write(make_command(first.clone())).unwrap();
+                    writer.write(make_command(second)).unwrap();
+                }
+            }
+            writer.finish().unwrap();
+            assert_eq!(writer.gen_purp_reg.in_use(), 0);
+        }
+    }
+
+    #[test]
+    fn it_locates_running_out_of_temp_registers() {
+        let options = CodegenOptions { temp_registers: Some(vec!["T0".to_owned()]), ..CodegenOptions::default() };
+        let mut writer = CodeWriter::with_options(io::sink(), &options).unwrap();
+        writer.write(make_command(ParsedCmd::Pop(Segment::Local, 100))).unwrap();
+        let location = Location { file: "Main.vm".into(), line: 3 };
+        let err = writer.write(Command::new(location, "return".to_owned(), ParsedCmd::Flow(Flow::Return))).unwrap_err();
+        assert_eq!(err.to_string(), "Main.vm:3: Control flow error: RegMgr: No temp space available");
+    }
+
+    fn write_cached(cmds: Vec<ParsedCmd>) -> String {
+        let mut buff = Vec::new();
+        let options = CodegenOptions { stack_caching: true, ..CodegenOptions::default() };
+        let mut writer = CodeWriter::with_options(&mut buff, &options).unwrap();
+        for cmd in cmds {
+            writer.write(make_command(cmd)).unwrap();
+        }
+        writer.finish().unwrap();
+        drop(writer);
+        String::from_utf8(buff).unwrap()
+    }
+
+    #[test_case(
+        vec![ParsedCmd::PushConstant(5), ParsedCmd::Arithmetic(Arithmetic::Add)],
+        "//
+@5
+D=A
+//
+@SP
+AM=M-1
+D=D+M
+@SP
+A=M
+M=D
+@SP
+M=M+1
+";
+        "push constant then add"
+    )]
+    #[test_case(
+        vec![ParsedCmd::Push(Segment::Local, 0), ParsedCmd::Pop(Segment::That, 1)],
+        "//
+@LCL
+A=M
+D=M
+//
+@THAT
+A=M
+A=A+1
+M=D
+";
+        "push local then pop that"
+    )]
+    #[test_case(
+        vec![ParsedCmd::Push(Segment::Argument, 1), ParsedCmd::Marker(Marker::Label("L".to_owned()))],
+        "//
+@ARG
+D=M
+A=D+1
+D=M
+//
+@SP
+A=M
+M=D
+@SP
+M=M+1
+(L)
+";
+        "spills before a label"
+    )]
+    fn it_keeps_the_top_of_the_stack_in_d(cmds: Vec<ParsedCmd>, expected_asm: &str) {
+        assert_eq!(write_cached(cmds), expected_asm);
+    }
 }
@@ -0,0 +1,108 @@
+use std::io::{self, BufRead, BufReader, Write};
+
+use hack_assembler::{assemble, ParseError};
+
+///What a translation is written out as
+#[derive(clap::ArgEnum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Emit {
+    ///Hack assembly, for the assembler
+    Asm,
+    ///A ROM image of one binary instruction per line, as the assembler writes it
+    Hack,
+}
+
+impl Emit {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Emit::Asm => "asm",
+            Emit::Hack => "hack",
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum EmitError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("assembly error: {0}")]
+    Assembly(#[from] ParseError),
+}
+
+///Assembles generated `asm` in memory, writing the ROM image to `hack` and, if given, a listing
+///of every line of `asm` with the address and encoding of each instruction to `listing`.
+///Returns the number of instructions
+pub fn write_hack<W: Write, L: Write>(
+    asm: &[u8],
+    mut hack: W,
+    listing: Option<L>,
+) -> Result<usize, EmitError> {
+    let assembly = assemble(&mut BufReader::new(asm))?;
+    for instruction in &assembly.instructions {
+        writeln!(hack, "{:016b}", instruction)?;
+    }
+    if let Some(listing) = listing {
+        write_listing(asm, &assembly.instructions, listing)?;
+    }
+    hack.flush()?;
+    Ok(assembly.instructions.len())
+}
+
+///Lines up each instruction with the line it was assembled from. Labels and comments, which
+///take no room in ROM, are listed without an address
+fn write_listing<L: Write>(asm: &[u8], instructions: &[u16], mut listing: L) -> io::Result<()> {
+    let mut instructions = instructions.iter().enumerate();
+    for line in asm.lines() {
+        let line = line?;
+        let source = line.trim();
+        if source.is_empty() || source.starts_with("//") || source.starts_with('(') {
+            writeln!(listing, "{:24}{}", "", source)?;
+        } else if let Some((address, instruction)) = instructions.next() {
+            writeln!(listing, "{:5}  {:016b}  {}", address, instruction, source)?;
+        }
+    }
+    listing.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use assert_matches::assert_matches;
+
+    use super::*;
+
+    const ASM: &str = "//push constant 7\n@7\nD=A\n(LOOP)\n@LOOP\n0;JMP\n";
+
+    #[test]
+    fn it_writes_the_rom_image_the_assembler_would() {
+        let mut hack = Vec::new();
+        let count = write_hack(ASM.as_bytes(), &mut hack, None::<Vec<u8>>).unwrap();
+        assert_eq!(count, 4);
+        assert_eq!(
+            String::from_utf8(hack).unwrap(),
+            "0000000000000111\n1110110000010000\n0000000000000010\n1110101010000111\n"
+        );
+    }
+
+    #[test]
+    fn it_lists_each_instruction_beside_its_source() {
+        let mut listing = Vec::new();
+        write_hack(ASM.as_bytes(), io::sink(), Some(&mut listing)).unwrap();
+        assert_eq!(
+            String::from_utf8(listing).unwrap(),
+            format!(
+                "{0}//push constant 7\n\
+                 \x20   0  0000000000000111  @7\n\
+                 \x20   1  1110110000010000  D=A\n\
+                 {0}(LOOP)\n\
+                 \x20   2  0000000000000010  @LOOP\n\
+                 \x20   3  1110101010000111  0;JMP\n",
+                " ".repeat(24)
+            )
+        );
+    }
+
+    #[test]
+    fn it_reports_assembly_errors() {
+        let result = write_hack(b"(LOOP\n", io::sink(), None::<Vec<u8>>);
+        assert_matches!(result, Err(EmitError::Assembly(ParseError::TokenError(_))));
+    }
+}
@@ -1,3 +1,6 @@
 mod code_writer;
-mod parser;
+pub mod emit;
+pub mod optimiser;
+pub mod parser;
 pub mod translator;
+pub mod validator;
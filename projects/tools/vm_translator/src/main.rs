@@ -1,18 +1,166 @@
-use std::{error::Error, path::Path};
+use std::{error::Error, io, path::PathBuf, process};
 
 use clap::Parser;
-use vm_translator::translator::{create_code_writer, translate};
+use vm_translator::{
+    emit::Emit,
+    translator::{
+        default_output, translate, translate_reader, translate_to, Bootstrap, BootstrapMode,
+        CodegenOptions, Target, Translation,
+    },
+};
 
 ///A translator for the Jack VM to Hack assembly language from the nand-to-tetris course
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about=None)]
 struct Args {
-    #[clap(name = "input file or directory")]
-    input_path: String,
+    ///VM files or directories, each translated next to itself unless --output combines them
+    #[clap(name = "input files or directories", required_unless_present = "stdin")]
+    inputs: Vec<PathBuf>,
+    ///Writes a single program made of all the inputs to this file, `-` for stdout
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+    ///Translates a single VM module read from stdin, written to stdout by default
+    #[clap(long, conflicts_with = "input files or directories")]
+    stdin: bool,
+    ///Module name of the stdin input, which its statics are named after
+    #[clap(long, default_value = "Stdin")]
+    name: String,
+    ///Emits the bootstrap code even when the entry function isn't defined
+    #[clap(long, conflicts_with = "no-bootstrap")]
+    bootstrap: bool,
+    ///Leaves out the bootstrap code, as the single file tests of projects/07 expect
+    #[clap(long)]
+    no_bootstrap: bool,
+    ///Initial stack pointer set by the bootstrap
+    #[clap(long, default_value_t = 256)]
+    sp: u16,
+    ///Initial LCL set by the bootstrap, left as it is by default
+    #[clap(long)]
+    lcl: Option<u16>,
+    ///Initial ARG set by the bootstrap, left as it is by default
+    #[clap(long)]
+    arg: Option<u16>,
+    ///Initial THIS set by the bootstrap, left as it is by default
+    #[clap(long)]
+    this: Option<u16>,
+    ///Initial THAT set by the bootstrap, left as it is by default
+    #[clap(long)]
+    that: Option<u16>,
+    ///Function called by the bootstrap
+    #[clap(long, default_value = "Sys.init")]
+    entry: String,
+    ///Jumps into shared call, return and comparison routines rather than inlining them, for a
+    ///slightly slower program with each of those several times smaller, about 30% off the Jack OS
+    #[clap(long)]
+    shared_routines: bool,
+    ///Optimises by keeping the top of the stack in the D register between commands
+    #[clap(long)]
+    stack_caching: bool,
+    ///Folds constants and simplifies common command sequences, reporting the savings per file
+    #[clap(short = 'O', long)]
+    optimise: bool,
+    ///Gets `gt` and `lt` right for operands far apart enough for their difference to overflow,
+    ///at the cost of a few more instructions per comparison
+    #[clap(long)]
+    safe_comparisons: bool,
+    ///Comma separated symbols to keep scratch values in instead of R13, R14 and R15, where any
+    ///that isn't predefined, such as `SCRATCH`, becomes a variable. SP, LCL, ARG, THIS, THAT and
+    ///R0 to R4 hold the stack and segment pointers, so they can't be used
+    #[clap(long, use_value_delimiter = true)]
+    temp_registers: Option<Vec<String>>,
+    ///Checks for stack overflow and underflow and for locals and arguments out of bounds as the
+    ///program runs, halting with an error code in R15, which is kept out of the temp registers,
+    ///when a check fails
+    #[clap(long, conflicts_with = "stack-caching")]
+    runtime_checks: bool,
+    ///Writes Hack assembly, or assembles it in memory into a .hack ROM image
+    #[clap(long, arg_enum, default_value = "asm")]
+    emit: Emit,
+    ///Keeps the assembly of a ROM image in a .asm file beside it
+    #[clap(long)]
+    keep_asm: bool,
+    ///Writes a .lst listing of each instruction's address and encoding beside the output
+    #[clap(long)]
+    listing: bool,
+    ///Writes a .map.json source map beside the output, giving the VM file, line and function
+    ///each range of ROM addresses was translated from
+    #[clap(long)]
+    source_map: bool,
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let args = Args::parse();
-    let mut code_writer = create_code_writer(Path::new(&args.input_path))?;
-    Ok(translate(&args.input_path, &mut code_writer)?)
+fn main() {
+    //Printed with Display rather than returned, as that would print the Debug form
+    if let Err(err) = run(Args::parse()) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    let bootstrap = Bootstrap {
+        mode: match (args.bootstrap, args.no_bootstrap) {
+            (true, _) => BootstrapMode::Always,
+            (_, true) => BootstrapMode::Never,
+            _ => BootstrapMode::Auto,
+        },
+        sp: args.sp,
+        lcl: args.lcl,
+        arg: args.arg,
+        this: args.this,
+        that: args.that,
+        entry: args.entry,
+    };
+    let options = CodegenOptions {
+        shared_routines: args.shared_routines,
+        stack_caching: args.stack_caching,
+        optimise: args.optimise,
+        safe_comparisons: args.safe_comparisons,
+        temp_registers: args.temp_registers.clone(),
+        runtime_checks: args.runtime_checks,
+    };
+    let target = |output: PathBuf| -> Result<Target, Box<dyn Error>> {
+        if (args.keep_asm || args.listing || args.source_map) && output.as_os_str() == "-" {
+            return Err(
+                "--keep-asm, --listing and --source-map need an output file rather than stdout"
+                    .into(),
+            );
+        }
+        Ok(Target {
+            asm: (args.keep_asm && args.emit == Emit::Hack).then(|| output.with_extension("asm")),
+            listing: args.listing.then(|| output.with_extension("lst")),
+            source_map: args.source_map.then(|| output.with_extension("map.json")),
+            emit: args.emit,
+            output,
+        })
+    };
+    if args.stdin {
+        let target = target(args.output.clone().unwrap_or_else(|| PathBuf::from("-")))?;
+        let translation = translate_to(&target, &options, |code_writer| {
+            translate_reader(&args.name, io::stdin(), code_writer, &bootstrap)
+        })?;
+        report(&args.name, &translation);
+    } else if let Some(output) = &args.output {
+        let translation = translate_to(&target(output.clone())?, &options, |code_writer| {
+            translate(&args.inputs, code_writer, &bootstrap)
+        })?;
+        report(&output.display().to_string(), &translation);
+    } else {
+        for input in &args.inputs {
+            let output = default_output(input)?.with_extension(args.emit.extension());
+            let translation = translate_to(&target(output)?, &options, |code_writer| {
+                translate(&[input], code_writer, &bootstrap)
+            })?;
+            report(&input.display().to_string(), &translation);
+        }
+    }
+    Ok(())
+}
+
+fn report(target: &str, translation: &Translation) {
+    for warning in &translation.warnings {
+        eprintln!("warning: {}: {}", target, warning);
+    }
+    for savings in &translation.savings {
+        eprintln!("{}", savings);
+    }
 }
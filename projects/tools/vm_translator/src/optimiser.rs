@@ -0,0 +1,238 @@
+use std::fmt;
+
+use crate::parser::{Arithmetic, Command, Flow, Goto, ParsedCmd};
+
+///How much a module's translation shrank by optimising it
+#[derive(Debug, PartialEq, Clone)]
+pub struct Savings {
+    pub module: String,
+    pub before: usize,
+    pub after: usize,
+}
+
+impl fmt::Display for Savings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let saved = self.before.saturating_sub(self.after);
+        let percent = match self.before {
+            0 => 0.0,
+            before => saved as f64 * 100.0 / before as f64,
+        };
+        write!(
+            f,
+            "{}: {} instructions, down from {}, saving {} ({:.1}%)",
+            self.module, self.after, self.before, saved, percent
+        )
+    }
+}
+
+///Rewrites a module's commands into fewer or cheaper ones with the same effect. Each command is
+///matched against the end of what has been rewritten so far, so folded results fold further, as
+///in `push constant 1; push constant 2; add; push constant 3; add`
+pub(crate) fn optimise(commands: Vec<Command>) -> Vec<Command> {
+    let mut optimised: Vec<Command> = Vec::with_capacity(commands.len());
+    for command in commands {
+        if *command.parsed() == ParsedCmd::Noop {
+            continue;
+        }
+        optimised.push(command);
+        while let Some((len, parsed)) = rewrite(&optimised) {
+            let start = optimised.len() - len;
+            let replaced: Vec<_> = optimised.drain(start..).collect();
+            if let Some(parsed) = parsed {
+                let original = replaced
+                    .iter()
+                    .map(|command| command.original().trim())
+                    .collect::<Vec<_>>()
+                    .join("; ");
+                optimised.push(Command::new(
+                    replaced[0].location().clone(),
+                    original,
+                    parsed,
+                ));
+            }
+        }
+    }
+    optimised
+}
+
+type Rewrite = Option<(usize, Option<ParsedCmd>)>;
+
+///How many commands at the end of `commands` to replace, and with what if anything
+fn rewrite(commands: &[Command]) -> Rewrite {
+    let tail: Vec<_> = commands
+        .iter()
+        .rev()
+        .take(3)
+        .rev()
+        .map(Command::parsed)
+        .collect();
+    let rules: [fn(&[&ParsedCmd]) -> Rewrite; 4] = [
+        fold_constants,
+        step_in_place,
+        drop_round_trip,
+        fuse_zero_test,
+    ];
+    rules.iter().find_map(|rule| rule(&tail))
+}
+
+fn fold_constants(tail: &[&ParsedCmd]) -> Rewrite {
+    match tail {
+        [.., ParsedCmd::PushConstant(x), ParsedCmd::PushConstant(y), ParsedCmd::Arithmetic(arr)] => {
+            fold_binary(*arr, *x, *y).map(|value| (3, Some(ParsedCmd::PushConstant(value))))
+        }
+        [.., ParsedCmd::PushConstant(x), ParsedCmd::Arithmetic(arr)] => {
+            fold_unary(*arr, *x).map(|value| (2, Some(ParsedCmd::PushConstant(value))))
+        }
+        _ => None,
+    }
+}
+
+fn step_in_place(tail: &[&ParsedCmd]) -> Rewrite {
+    let step = match tail {
+        [.., ParsedCmd::PushConstant(1), ParsedCmd::Arithmetic(Arithmetic::Add)] => Arithmetic::Inc,
+        [.., ParsedCmd::PushConstant(1), ParsedCmd::Arithmetic(Arithmetic::Sub)] => Arithmetic::Dec,
+        _ => return None,
+    };
+    Some((2, Some(ParsedCmd::Arithmetic(step))))
+}
+
+fn drop_round_trip(tail: &[&ParsedCmd]) -> Rewrite {
+    match tail {
+        [.., ParsedCmd::Push(push_segment, push_idx), ParsedCmd::Pop(pop_segment, pop_idx)]
+            if push_segment == pop_segment && push_idx == pop_idx =>
+        {
+            Some((2, None))
+        }
+        _ => None,
+    }
+}
+
+fn fuse_zero_test(tail: &[&ParsedCmd]) -> Rewrite {
+    match tail {
+        [ParsedCmd::PushConstant(0), ParsedCmd::Arithmetic(Arithmetic::Eq), ParsedCmd::Flow(Flow::Goto(Goto::Conditional, label))] => {
+            Some((
+                3,
+                Some(ParsedCmd::Flow(Flow::Goto(Goto::IfZero, label.clone()))),
+            ))
+        }
+        _ => None,
+    }
+}
+
+fn fold_binary(arr: Arithmetic, x: i16, y: i16) -> Option<i16> {
+    Some(match arr {
+        Arithmetic::Add => x.wrapping_add(y),
+        Arithmetic::Sub => x.wrapping_sub(y),
+        Arithmetic::And => x & y,
+        Arithmetic::Or => x | y,
+        Arithmetic::Eq => truth(x == y),
+        //The generated code compares the sign of x - y, so only fold where that can't overflow
+        Arithmetic::Gt => truth(x.checked_sub(y)? > 0),
+        Arithmetic::Lt => truth(x.checked_sub(y)? < 0),
+        _ => return None,
+    })
+}
+
+fn fold_unary(arr: Arithmetic, x: i16) -> Option<i16> {
+    Some(match arr {
+        Arithmetic::Neg => x.wrapping_neg(),
+        Arithmetic::Not => !x,
+        Arithmetic::Inc => x.wrapping_add(1),
+        Arithmetic::Dec => x.wrapping_sub(1),
+        _ => return None,
+    })
+}
+
+fn truth(value: bool) -> i16 {
+    if value {
+        -1
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::BufReader;
+
+    use test_case::test_case;
+
+    use super::*;
+    use crate::parser::{Parser, Segment};
+
+    fn optimise_source(source: &str) -> Vec<ParsedCmd> {
+        let commands = Parser::new("Test.vm", BufReader::new(source.as_bytes()))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        optimise(commands)
+            .into_iter()
+            .map(|command| command.parsed().clone())
+            .collect()
+    }
+
+    #[test_case("push constant 2\npush constant 3\nadd", 5; "add")]
+    #[test_case("push constant 2\npush constant 3\nsub", -1; "sub to minus one")]
+    #[test_case("push constant 6\npush constant 3\nand", 2; "and")]
+    #[test_case("push constant 6\npush constant 3\nor", 7; "or")]
+    #[test_case("push constant 3\npush constant 3\neq", -1; "eq")]
+    #[test_case("push constant 3\npush constant 2\ngt", -1; "gt")]
+    #[test_case("push constant 3\npush constant 2\nlt", 0; "lt")]
+    #[test_case("push constant 0\nnot", -1; "not")]
+    #[test_case("push constant 1\nneg", -1; "neg")]
+    #[test_case("push constant 2\nneg", -2; "neg to any negative")]
+    #[test_case("push constant 32767\npush constant 1\nadd", i16::MIN; "add wrapping around")]
+    #[test_case("push constant 1\npush constant 2\nadd\npush constant 3\nadd\n\npush constant 4\nadd", 10; "chained")]
+    fn it_folds_constants(source: &str, value: i16) {
+        assert_eq!(optimise_source(source), [ParsedCmd::PushConstant(value)]);
+    }
+
+    #[test_case("push constant 32767\npush constant 1\nneg\ngt"; "comparisons that overflow")]
+    fn it_leaves_what_it_cannot_fold(source: &str) {
+        assert!(optimise_source(source).len() > 1);
+    }
+
+    #[test]
+    fn it_drops_pushes_popped_straight_back() {
+        assert_eq!(
+            optimise_source("push local 2\npop local 2\npush this 1\npop that 1\n"),
+            [
+                ParsedCmd::Push(Segment::This, 1),
+                ParsedCmd::Pop(Segment::That, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn it_fuses_tests_for_zero_and_steps() {
+        assert_eq!(
+            optimise_source(
+                "push local 0\npush constant 0\neq\nif-goto END\n\
+                 push local 0\npush constant 1\nadd\npush local 1\npush constant 1\nsub\n"
+            ),
+            [
+                ParsedCmd::Push(Segment::Local, 0),
+                ParsedCmd::Flow(Flow::Goto(Goto::IfZero, "END".to_owned())),
+                ParsedCmd::Push(Segment::Local, 0),
+                ParsedCmd::Arithmetic(Arithmetic::Inc),
+                ParsedCmd::Push(Segment::Local, 1),
+                ParsedCmd::Arithmetic(Arithmetic::Dec),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_keeps_where_rewritten_commands_came_from() {
+        let commands = Parser::new(
+            "Main.vm",
+            BufReader::new("\npush constant 2\npush constant 3\nadd\n".as_bytes()),
+        )
+        .collect::<Result<_, _>>()
+        .unwrap();
+        let optimised = optimise(commands);
+        assert_eq!(optimised[0].location().to_string(), "Main.vm:2");
+        assert_eq!(
+            optimised[0].original(),
+            "push constant 2; push constant 3; add"
+        );
+    }
+}
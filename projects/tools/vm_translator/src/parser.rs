@@ -1,27 +1,54 @@
 use std::{
-    fmt::Display,
+    fmt::{self, Display},
     io::{self, BufRead, BufReader, Lines, Read},
-    iter::Peekable,
+    iter::Enumerate,
     num::ParseIntError,
+    rc::Rc,
 };
 
 use phf::phf_map;
 
 pub type HackMemSize = u16;
 
-#[derive(Debug, PartialEq)]
+///The most locals a function can declare or arguments a call can pass, as the call frame below
+///the arguments must stay within what an A instruction can load
+pub const MAX_COUNT: u16 = i16::MAX as u16 - 5;
+///The largest segment index, as an A instruction must be able to load it
+pub const MAX_INDEX: HackMemSize = i16::MAX as HackMemSize;
+
+///Where a command came from, shown as `File.vm:42`
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Location {
+    pub file: Rc<str>,
+    pub line: usize,
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub struct Command {
+    location: Location,
     original: String,
     parsed: ParsedCmd,
 }
 
 impl Command {
-    pub fn new(original: String, parsed_command: ParsedCmd) -> Self {
+    pub fn new(location: Location, original: String, parsed_command: ParsedCmd) -> Self {
         Self {
+            location,
             original,
             parsed: parsed_command,
         }
     }
+
+    pub fn location(&self) -> &Location {
+        &self.location
+    }
+
     pub fn original(&self) -> &String {
         &self.original
     }
@@ -32,15 +59,35 @@ impl Command {
 }
 
 #[derive(Debug, thiserror::Error)]
-pub enum ParseError {
+#[error("{location}: {kind}")]
+pub struct ParseError {
+    pub location: Location,
+    pub kind: ParseErrorKind,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ParseErrorKind {
     #[error("file error: {0}")]
     IoError(#[from] io::Error),
-    #[error("unknown command: {0}")]
-    UnknownCommandError(String),
-    #[error("unknown segment: {0}")]
-    UnknownSegmentError(String),
+    #[error("unknown command '{0}'{}", did_you_mean(.1))]
+    UnknownCommandError(String, Option<&'static str>),
+    #[error("unknown segment '{0}'{}", did_you_mean(.1))]
+    UnknownSegmentError(String, Option<&'static str>),
     #[error("invalid memory location: {0}")]
     InvalidMemoryLocation(#[from] ParseIntError),
+    #[error(
+        "invalid constant '{0}', expected a number from -32768 to 65535, a hex number up to \
+         0xFFFF or a character such as 'A'"
+    )]
+    InvalidConstant(String),
+    #[error("invalid {0} count '{1}', expected a number from 0 to {MAX_COUNT}")]
+    InvalidCount(&'static str, String),
+    #[error("invalid index '{0}', expected a number from 0 to {MAX_INDEX}")]
+    InvalidIndex(String),
+}
+
+fn did_you_mean(suggestion: &Option<&str>) -> String {
+    suggestion.map_or_else(String::new, |s| format!(", did you mean '{}'?", s))
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -54,25 +101,32 @@ pub enum Arithmetic {
     And,
     Or,
     Not,
+    ///Adds one in place, which the optimiser makes of `push constant 1; add`
+    Inc,
+    ///Subtracts one in place, which the optimiser makes of `push constant 1; sub`
+    Dec,
 }
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum Flow {
     Goto(Goto, String),
-    Call(String, u8),
+    Call(String, u16),
     Return,
 }
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum Marker {
     Label(String),
-    Function(String, u8),
+    Function(String, u16),
 }
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum Goto {
     Direct,
     Conditional,
+    ///Jumps when the popped value is zero, which the optimiser makes of
+    ///`push constant 0; eq; if-goto`
+    IfZero,
 }
 
 #[derive(PartialEq, Debug, Clone)]
@@ -95,6 +149,8 @@ pub enum Segment {
     That,
     Pointer,
     Temp,
+    ///Only ever popped to, which the validator rejects, as pushes parse as `PushConstant`
+    Constant,
 }
 
 impl Display for Segment {
@@ -105,9 +161,15 @@ impl Display for Segment {
 
 #[derive(Debug)]
 pub struct Parser<R: Read> {
-    in_stream: Peekable<Lines<BufReader<R>>>,
+    file: Rc<str>,
+    in_stream: Enumerate<Lines<BufReader<R>>>,
 }
 
+///The commands besides arithmetic, which a misspelt first word is matched against
+const COMMANDS: [&str; 8] = [
+    "push", "pop", "label", "goto", "if-goto", "function", "call", "return",
+];
+
 static STR_ARITHMETIC: phf::Map<&str, Arithmetic> = phf_map! {
     "add" =>  Arithmetic::Add,
     "sub" =>  Arithmetic::Sub,
@@ -128,20 +190,90 @@ static STR_SEGMENT: phf::Map<&str, Segment> = phf_map! {
     "that" =>  Segment::That,
     "pointer" =>  Segment::Pointer,
     "temp" =>  Segment::Temp,
+    "constant" =>  Segment::Constant,
 };
 
+///The closest known word to a misspelt one, if it is only a couple of edits away
+fn suggest<'a>(
+    word: &str,
+    candidates: impl Iterator<Item = &'a &'static str>,
+) -> Option<&'static str> {
+    let mut candidates: Vec<_> = candidates.copied().collect();
+    candidates.sort_unstable();
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(word, candidate), candidate))
+        .filter(|(distance, _)| (1..=2).contains(distance) && *distance < word.len())
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<_> = b.chars().collect();
+    let mut row: Vec<_> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(a != *b);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+fn unknown_command(tokens: &[&str]) -> ParseErrorKind {
+    let suggestion = suggest(tokens[0], STR_ARITHMETIC.keys().chain(COMMANDS.iter()));
+    ParseErrorKind::UnknownCommandError(tokens.join(" "), suggestion)
+}
+
+///Reads any 16 bit pattern, given as a signed or unsigned number, in hex or as a character of
+///the Hack character set that a literal can hold, from space to `~`
+fn parse_constant(literal: &str) -> Result<i16, ParseErrorKind> {
+    let mut chars = literal.chars();
+    let value = match (chars.next(), chars.next(), chars.next(), chars.next()) {
+        (Some('\''), Some(c @ ' '..='~'), Some('\''), None) => Some(c as u16),
+        _ => match literal.strip_prefix("0x") {
+            Some(hex) => u16::from_str_radix(hex, 16).ok(),
+            None => literal
+                .parse::<i16>()
+                .ok()
+                .map(|value| value as u16)
+                .or_else(|| literal.parse::<u16>().ok()),
+        },
+    };
+    value
+        .map(|value| value as i16)
+        .ok_or_else(|| ParseErrorKind::InvalidConstant(literal.to_owned()))
+}
+
+fn parse_count(kind: &'static str, count: &str) -> Result<u16, ParseErrorKind> {
+    count
+        .parse::<u16>()
+        .ok()
+        .filter(|count| *count <= MAX_COUNT)
+        .ok_or_else(|| ParseErrorKind::InvalidCount(kind, count.to_owned()))
+}
+
+fn parse_index(index: &str) -> Result<HackMemSize, ParseErrorKind> {
+    HackMemSize::try_from(index.parse::<u32>()?)
+        .ok()
+        .filter(|index| *index <= MAX_INDEX)
+        .ok_or_else(|| ParseErrorKind::InvalidIndex(index.to_owned()))
+}
+
 impl TryFrom<&str> for ParsedCmd {
-    type Error = ParseError;
+    type Error = ParseErrorKind;
     fn try_from(value: &str) -> Result<Self, Self::Error> {
         let value = &value[..value.find("//").unwrap_or(value.len())];
         match value.split_ascii_whitespace().collect::<Vec<_>>()[..] {
             [] => Ok(ParsedCmd::Noop),
             ["return"] => Ok(ParsedCmd::Flow(Flow::Return)),
             [arithmetic_cmd] => Ok(ParsedCmd::Arithmetic(
-                STR_ARITHMETIC.get(arithmetic_cmd).map_or_else(
-                    || Err(ParseError::UnknownCommandError(arithmetic_cmd.to_string())),
-                    |cmd| Ok(*cmd),
-                )?,
+                STR_ARITHMETIC
+                    .get(arithmetic_cmd)
+                    .map_or_else(|| Err(unknown_command(&[arithmetic_cmd])), |cmd| Ok(*cmd))?,
             )),
             ["label", label] => Ok(ParsedCmd::Marker(Marker::Label(label.to_string()))),
             ["if-goto", label] => Ok(ParsedCmd::Flow(Flow::Goto(
@@ -151,17 +283,28 @@ impl TryFrom<&str> for ParsedCmd {
             ["goto", label] => Ok(ParsedCmd::Flow(Flow::Goto(Goto::Direct, label.to_string()))),
             ["function", name, local_count] => Ok(ParsedCmd::Marker(Marker::Function(
                 name.to_string(),
-                local_count.parse::<u8>()?,
+                parse_count("local", local_count)?,
             ))),
             ["call", name, arg_count] => Ok(ParsedCmd::Flow(Flow::Call(
                 name.to_string(),
-                arg_count.parse::<u8>()?,
+                parse_count("argument", arg_count)?,
             ))),
-            ["push", "constant", value] => Ok(ParsedCmd::PushConstant(str::parse::<i16>(value)?)),
+            ["push", "constant", value] => Ok(ParsedCmd::PushConstant(parse_constant(value)?)),
+            //Splitting on whitespace takes apart a literal space, or any other whitespace
+            ["push", "constant", "'", "'"] => {
+                let literal =
+                    &value[value.find('\'').unwrap_or(0)..=value.rfind('\'').unwrap_or(0)];
+                Ok(ParsedCmd::PushConstant(parse_constant(literal)?))
+            }
             [op, segment, location] if op == "push" || op == "pop" => {
-                let location = str::parse::<HackMemSize>(location)?;
+                let location = parse_index(location)?;
                 let segment = *STR_SEGMENT.get(segment).map_or_else(
-                    || Err(ParseError::UnknownSegmentError(segment.to_string())),
+                    || {
+                        Err(ParseErrorKind::UnknownSegmentError(
+                            segment.to_string(),
+                            suggest(segment, STR_SEGMENT.keys()),
+                        ))
+                    },
                     Ok,
                 )?;
                 if op == "push" {
@@ -170,15 +313,17 @@ impl TryFrom<&str> for ParsedCmd {
                     Ok(ParsedCmd::Pop(segment, location))
                 }
             }
-            ref tokens => Err(ParseError::UnknownCommandError(tokens.join(" "))),
+            ref tokens => Err(unknown_command(tokens)),
         }
     }
 }
 
 impl<R: Read> Parser<R> {
-    pub fn new(in_stream: BufReader<R>) -> Self {
+    ///Parses `in_stream`, locating its commands and errors in `file`
+    pub fn new(file: &str, in_stream: BufReader<R>) -> Self {
         Parser {
-            in_stream: in_stream.lines().peekable(),
+            file: file.into(),
+            in_stream: in_stream.lines().enumerate(),
         }
     }
 }
@@ -187,17 +332,18 @@ impl<R: Read> Iterator for Parser<R> {
     type Item = Result<Command, ParseError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(line) = self.in_stream.next() {
-            match line {
-                Ok(line) => match line.as_str().try_into() {
-                    Ok(parsed_cmd) => Some(Ok(Command::new(line.clone(), parsed_cmd))),
-                    Err(err) => Some(Err(err)),
-                },
-                Err(line) => Some(Err(line.into())),
-            }
-        } else {
-            None
-        }
+        let (i, line) = self.in_stream.next()?;
+        let location = Location {
+            file: self.file.clone(),
+            line: i + 1,
+        };
+        let parsed = line
+            .map_err(ParseErrorKind::from)
+            .and_then(|line| Ok((line.as_str().try_into()?, line)));
+        Some(match parsed {
+            Ok((parsed_cmd, line)) => Ok(Command::new(location, line, parsed_cmd)),
+            Err(kind) => Err(ParseError { location, kind }),
+        })
     }
 }
 
@@ -208,10 +354,10 @@ mod test {
     use test_case::test_case;
 
     fn make_cmd(cmd: &str) -> Command {
-        let v = format!("{}", cmd).to_string();
+        let v = cmd.to_string();
         let c = io::Cursor::new(v);
         let r = BufReader::new(c);
-        let mut parser = Parser::new(r);
+        let mut parser = Parser::new("Test.vm", r);
         parser.next().transpose().unwrap().unwrap()
     }
 
@@ -219,7 +365,7 @@ mod test {
     fn it_should_return_none_when_no_more_commands_available() {
         let c = io::Cursor::new(Vec::new());
         let r = BufReader::new(c);
-        let mut parser = Parser::new(r);
+        let mut parser = Parser::new("Test.vm", r);
         assert!(parser.next().is_none())
     }
 
@@ -285,6 +431,11 @@ mod test {
         Flow::Goto(Goto::Direct, "LOOP_START".to_string());
         "goto"
     )]
+    #[test_case(
+        "call Main.sum 1000",
+        Flow::Call("Main.sum".to_string(), 1000);
+        "call with many arguments"
+    )]
     #[test_case(
         "return",
         Flow::Return;
@@ -301,9 +452,14 @@ mod test {
     )]
     #[test_case(
         "function test 3",
-        Marker::Function("test".to_string(), 3_u8);
+        Marker::Function("test".to_string(), 3);
         "function"
     )]
+    #[test_case(
+        "function test 300",
+        Marker::Function("test".to_string(), 300);
+        "function with many locals"
+    )]
     fn it_should_return_marker_command_for_marker_string(marker_string: &str, marker_cmd: Marker) {
         assert_eq!(
             make_cmd(marker_string).parsed(),
@@ -410,10 +566,10 @@ mod test {
         let v = "wrong".to_string();
         let c = io::Cursor::new(v);
         let r = BufReader::new(c);
-        let mut parser = Parser::new(r);
+        let mut parser = Parser::new("Test.vm", r);
         assert_matches!(
             parser.next().transpose(),
-            Err(ParseError::UnknownCommandError(s)) => {
+            Err(ParseError { kind: ParseErrorKind::UnknownCommandError(s, None), .. }) => {
                 assert_eq!(s, "wrong".to_owned())
             }
         );
@@ -421,17 +577,71 @@ mod test {
 
     #[test]
     fn it_should_return_error_when_unknown_segment_supplied() {
-        for segment in vec!["nosegment", "constant"] {
-            let v = format!("pop {} 3", segment);
-            let c = io::Cursor::new(v);
-            let r = BufReader::new(c);
-            let mut parser = Parser::new(r);
-            assert_matches!(
-                parser.next().transpose(),
-                Err(ParseError::UnknownSegmentError(s)) => {
-                    assert_eq!(s, segment)
-                }
-            );
-        }
+        let v = "pop nosegment 3".to_string();
+        let c = io::Cursor::new(v);
+        let r = BufReader::new(c);
+        let mut parser = Parser::new("Test.vm", r);
+        assert_matches!(
+            parser.next().transpose(),
+            Err(ParseError { kind: ParseErrorKind::UnknownSegmentError(s, None), .. }) => {
+                assert_eq!(s, "nosegment")
+            }
+        );
+    }
+
+    #[test_case("push constant 32767", 32767; "largest positive")]
+    #[test_case("push constant -1", -1; "minus one")]
+    #[test_case("push constant -32768", i16::MIN; "most negative")]
+    #[test_case("push constant 65535", -1; "largest unsigned")]
+    #[test_case("push constant 0x7FFF", 32767; "hex")]
+    #[test_case("push constant 0x8000", i16::MIN; "hex with the sign bit")]
+    #[test_case("push constant 'A'", 65; "character")]
+    #[test_case("push constant ' '", 32; "space")]
+    #[test_case("push constant '~'", 126; "last printable character")]
+    fn it_should_read_any_16_bit_constant(cmd: &str, value: i16) {
+        assert_eq!(make_cmd(cmd).parsed(), &ParsedCmd::PushConstant(value));
+    }
+
+    #[test]
+    fn it_should_leave_pop_constant_to_the_validator() {
+        assert_eq!(
+            make_cmd("pop constant 3").parsed(),
+            &ParsedCmd::Pop(Segment::Constant, 3)
+        );
+        assert_eq!(
+            make_cmd("push constant 3").parsed(),
+            &ParsedCmd::PushConstant(3)
+        );
+    }
+
+    #[test]
+    fn it_locates_commands_by_line() {
+        let source = "// comment\n\npush local 2\n";
+        let commands: Vec<_> = Parser::new("Main.vm", BufReader::new(source.as_bytes()))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(commands[2].location().to_string(), "Main.vm:3");
+        assert_eq!(commands[2].original(), "push local 2");
+    }
+
+    #[test_case("push constant 0\npush locl 3", "Main.vm:2: unknown segment 'locl', did you mean 'local'?"; "segment")]
+    #[test_case("ad", "Main.vm:1: unknown command 'ad', did you mean 'add'?"; "arithmetic")]
+    #[test_case("\n\npsh local 1", "Main.vm:3: unknown command 'psh local 1', did you mean 'push'?"; "command")]
+    #[test_case("if-goto", "Main.vm:1: unknown command 'if-goto'"; "missing label")]
+    #[test_case("pop local x", "Main.vm:1: invalid memory location: invalid digit found in string"; "bad index")]
+    #[test_case("push local 40000", "Main.vm:1: invalid index '40000', expected a number from 0 to 32767"; "index too large")]
+    #[test_case("pop argument 70000", "Main.vm:1: invalid index '70000', expected a number from 0 to 32767"; "index beyond u16")]
+    #[test_case("function Main.main 40000", "Main.vm:1: invalid local count '40000', expected a number from 0 to 32762"; "too many locals")]
+    #[test_case("call Main.f -1", "Main.vm:1: invalid argument count '-1', expected a number from 0 to 32762"; "negative arguments")]
+    #[test_case("call Main.f 70000", "Main.vm:1: invalid argument count '70000', expected a number from 0 to 32762"; "arguments beyond u16")]
+    #[test_case("push constant 65536", "Main.vm:1: invalid constant '65536', expected a number from -32768 to 65535, a hex number up to 0xFFFF or a character such as 'A'"; "constant too large")]
+    #[test_case("push constant 'AB'", "Main.vm:1: invalid constant ''AB'', expected a number from -32768 to 65535, a hex number up to 0xFFFF or a character such as 'A'"; "two characters")]
+    #[test_case("push constant 'é'", "Main.vm:1: invalid constant ''é'', expected a number from -32768 to 65535, a hex number up to 0xFFFF or a character such as 'A'"; "outside the Hack character set")]
+    #[test_case("push constant '\t'", "Main.vm:1: invalid constant ''\t'', expected a number from -32768 to 65535, a hex number up to 0xFFFF or a character such as 'A'"; "tab")]
+    fn it_reports_errors_with_their_line(source: &str, message: &str) {
+        let error = Parser::new("Main.vm", BufReader::new(source.as_bytes()))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap_err();
+        assert_eq!(error.to_string(), message);
     }
 }
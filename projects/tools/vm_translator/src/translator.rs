@@ -1,12 +1,18 @@
 use std::{
-    fs::{read_dir, File},
-    io::{self, BufReader, BufWriter, Write},
+    fs::{self, read_dir, File},
+    io::{self, BufReader, BufWriter, Read, Write},
     path::{Path, PathBuf},
 };
 
+use source_map::{SourceMap, SourceMapError};
+
+pub use crate::code_writer::{RuntimeError, TRAP_CELL};
 use crate::{
     code_writer::{CodeWriter, CodeWriterError},
-    parser::{ParseError, Parser},
+    emit::{write_hack, Emit, EmitError},
+    optimiser::{optimise, Savings},
+    parser::{Command, Marker, ParseError, ParsedCmd, Parser},
+    validator::{validate, ValidationError},
 };
 
 #[derive(thiserror::Error, Debug)]
@@ -15,61 +21,355 @@ pub enum TranslatorError {
     InvalidPathError(PathBuf),
     #[error("file error: {0}")]
     FileError(#[from] io::Error),
-    #[error("parse error: {0}")]
+    #[error("{0}")]
     ParseError(#[from] ParseError),
-    #[error("code writer error: {0}")]
+    #[error("{0}")]
     CodeWriter(#[from] CodeWriterError),
+    #[error("{0} and {1} only differ by case, so their statics would clash")]
+    CaseClash(PathBuf, PathBuf),
+    #[error("{0} and {1} are both named {2}")]
+    DuplicateModule(PathBuf, PathBuf, String),
+    #[error("{0}")]
+    Emit(#[from] EmitError),
+    #[error("{0}")]
+    SourceMap(#[from] SourceMapError),
+    #[error("{}", .0.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n"))]
+    Validation(Vec<ValidationError>),
+}
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum TranslatorWarning {
+    #[error("no {0} function is defined, so the bootstrap code was left out")]
+    NoEntryFunction(String),
+    #[error("{0}")]
+    Validation(ValidationError),
+}
+
+const SYS_FILE: &str = "Sys";
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BootstrapMode {
+    ///Bootstraps when the entry function is defined, as single files such as the `projects/07`
+    ///tests expect to run without it
+    Auto,
+    Always,
+    Never,
+}
+
+///The code that sets up the stack and segment pointers and then calls the entry function
+#[derive(Debug, PartialEq, Clone)]
+pub struct Bootstrap {
+    pub mode: BootstrapMode,
+    pub sp: u16,
+    pub lcl: Option<u16>,
+    pub arg: Option<u16>,
+    pub this: Option<u16>,
+    pub that: Option<u16>,
+    pub entry: String,
+}
+
+impl Default for Bootstrap {
+    fn default() -> Self {
+        Self {
+            mode: BootstrapMode::Auto,
+            sp: 256,
+            lcl: None,
+            arg: None,
+            this: None,
+            that: None,
+            entry: "Sys.init".to_owned(),
+        }
+    }
+}
+
+///How the code writer generates assembly
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct CodegenOptions {
+    ///Jumps into single `$$call`, `$$return` and `$$cmp` routines instead of expanding calls,
+    ///returns and comparisons inline, trading some speed for sites several times smaller. As the
+    ///pushes and pops in between stay inline, the whole Jack OS only shrinks by about 30%
+    pub shared_routines: bool,
+    ///Keeps the top of the stack in D between commands where it can, rather than writing every
+    ///pushed value to RAM only to read it back
+    pub stack_caching: bool,
+    ///Folds constants and rewrites common command sequences into cheaper ones before generating
+    ///code, reporting what that saved per module
+    pub optimise: bool,
+    ///Compares operands of opposite signs by their signs rather than the sign of their
+    ///difference, which overflows for e.g. `-20000 gt 20000`, at the cost of a few instructions
+    ///per `gt` and `lt`
+    pub safe_comparisons: bool,
+    ///Symbols generated code may keep scratch values in, R13 to R15 unless given, or R13 and R14
+    ///with runtime checks. The assembler allocates a variable for any that isn't predefined
+    pub temp_registers: Option<Vec<String>>,
+    ///Checks at runtime that the stack stays between the current frame and the heap and that
+    ///`local` and `argument` stay within what the function declares and was called with. A
+    ///failed check records a `RuntimeError` code in the `TRAP_CELL` and halts
+    pub runtime_checks: bool,
+}
+
+///What a translation found besides the assembly it wrote
+#[derive(Debug, Default, PartialEq)]
+pub struct Translation {
+    pub warnings: Vec<TranslatorWarning>,
+    ///Per module, when optimising
+    pub savings: Vec<Savings>,
+    ///Where each VM command ended up in ROM
+    pub source_map: SourceMap,
 }
 
-pub fn translate<W: Write>(
-    input_path: &str,
+///Translates `.vm` files and directories of them into a single program
+pub fn translate<P: AsRef<Path>, W: Write>(
+    inputs: &[P],
     code_writer: &mut CodeWriter<W>,
-) -> Result<(), TranslatorError> {
-    let path = Path::new(input_path);
-    code_writer.init()?;
-
-    if path.is_dir() {
-        let entries = read_dir(path)?
-            .filter_map(|res| match res.map(|entry| entry.path()) {
-                Ok(path) => {
-                    if let Some("vm") = path.extension().and_then(|p| p.to_str()) {
-                        Some(Ok(path))
-                    } else {
-                        None
-                    }
+    bootstrap: &Bootstrap,
+) -> Result<Translation, TranslatorError> {
+    let sources = parse_inputs(inputs)?;
+    translate_sources(sources, code_writer, bootstrap)
+}
+
+///Parses `.vm` files and directories of them into modules named after their files, in the order
+///they are translated in
+pub fn parse_inputs<P: AsRef<Path>>(
+    inputs: &[P],
+) -> Result<Vec<(String, Vec<Command>)>, TranslatorError> {
+    let mut files = Vec::new();
+    for input in inputs {
+        let path = input.as_ref();
+        if path.is_dir() {
+            files.extend(vm_files(path)?);
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+    check_module_names(&files)?;
+    files
+        .iter()
+        .map(|file| Ok((get_path_name(file)?.to_owned(), parse_file(file)?)))
+        .collect()
+}
+
+///Translates a single VM module, such as one piped through stdin, whose statics are named after
+///`name`
+pub fn translate_reader<R: Read, W: Write>(
+    name: &str,
+    source: R,
+    code_writer: &mut CodeWriter<W>,
+    bootstrap: &Bootstrap,
+) -> Result<Translation, TranslatorError> {
+    let file = format!("{}.vm", name);
+    let commands = Parser::new(&file, BufReader::new(source)).collect::<Result<_, _>>()?;
+    translate_sources(vec![(name, commands)], code_writer, bootstrap)
+}
+
+///Translates parsed modules, named after their files, into assembly kept in memory
+pub fn translate_in_memory<S: AsRef<str>>(
+    sources: Vec<(S, Vec<Command>)>,
+    options: &CodegenOptions,
+    bootstrap: &Bootstrap,
+) -> Result<(Vec<u8>, Translation), TranslatorError> {
+    let mut code_writer = CodeWriter::with_options(Vec::new(), options)?;
+    let translation = translate_sources(sources, &mut code_writer, bootstrap)?;
+    Ok((code_writer.into_inner(), translation))
+}
+
+fn translate_sources<S: AsRef<str>, W: Write>(
+    sources: Vec<(S, Vec<Command>)>,
+    code_writer: &mut CodeWriter<W>,
+    bootstrap: &Bootstrap,
+) -> Result<Translation, TranslatorError> {
+    let warnings = validate(&sources).map_err(TranslatorError::Validation)?;
+    let mut translation = Translation {
+        warnings: warnings
+            .into_iter()
+            .map(TranslatorWarning::Validation)
+            .collect(),
+        ..Translation::default()
+    };
+    let has_entry = sources.iter().flat_map(|(_, commands)| commands).any(|command| {
+        matches!(command.parsed(), ParsedCmd::Marker(Marker::Function(name, _)) if *name == bootstrap.entry)
+    });
+    match bootstrap.mode {
+        BootstrapMode::Never => {}
+        BootstrapMode::Auto if !has_entry => translation
+            .warnings
+            .push(TranslatorWarning::NoEntryFunction(bootstrap.entry.clone())),
+        BootstrapMode::Auto | BootstrapMode::Always => code_writer.init(bootstrap)?,
+    }
+    for (namespace, commands) in sources {
+        let namespace = namespace.as_ref();
+        if code_writer.options().optimise {
+            let before = count_instructions(namespace, commands.clone(), code_writer.options())?;
+            let start = code_writer.instruction_count();
+            write_file(namespace, optimise(commands), code_writer)?;
+            translation.savings.push(Savings {
+                module: namespace.to_owned(),
+                before,
+                after: code_writer.instruction_count() - start,
+            });
+        } else {
+            write_file(namespace, commands, code_writer)?;
+        }
+    }
+    code_writer.finish()?;
+    translation.source_map = code_writer.source_map();
+    Ok(translation)
+}
+
+///How many instructions a module translates to without optimising it
+fn count_instructions(
+    namespace: &str,
+    commands: Vec<Command>,
+    options: &CodegenOptions,
+) -> Result<usize, TranslatorError> {
+    let options = CodegenOptions {
+        optimise: false,
+        ..options.clone()
+    };
+    let mut code_writer = CodeWriter::with_options(io::sink(), &options)?;
+    write_file(namespace, commands, &mut code_writer)?;
+    Ok(code_writer.instruction_count())
+}
+
+///The `.vm` files of a directory sorted by name, with `Sys.vm` first so that `Sys.init` follows
+///the bootstrap code as it does with the reference tools
+fn vm_files(dir: &Path) -> Result<Vec<PathBuf>, TranslatorError> {
+    let mut entries = read_dir(dir)?
+        .filter_map(|res| match res.map(|entry| entry.path()) {
+            Ok(path) => {
+                if let Some("vm") = path.extension().and_then(|p| p.to_str()) {
+                    Some(Ok(path))
+                } else {
+                    None
                 }
-                Err(e) => Some(Err(e)),
-            })
-            .collect::<Result<Vec<_>, io::Error>>()?;
-        for entry in entries {
-            parse_file(&*entry, code_writer)?;
+            }
+            Err(e) => Some(Err(e)),
+        })
+        .collect::<Result<Vec<_>, io::Error>>()?;
+    entries.sort_by_key(|path| (path.file_stem() != Some(SYS_FILE.as_ref()), path.clone()));
+    Ok(entries)
+}
+
+///Each file becomes the namespace of its statics, so no two may share a name, even by case
+fn check_module_names(files: &[PathBuf]) -> Result<(), TranslatorError> {
+    for (i, file) in files.iter().enumerate() {
+        let name = get_path_name(file)?;
+        for other in &files[i + 1..] {
+            let other_name = get_path_name(other)?;
+            if other_name == name {
+                return Err(TranslatorError::DuplicateModule(
+                    file.clone(),
+                    other.clone(),
+                    name.to_owned(),
+                ));
+            } else if other_name.to_lowercase() == name.to_lowercase() {
+                return Err(TranslatorError::CaseClash(file.clone(), other.clone()));
+            }
         }
-    } else {
-        parse_file(path, code_writer)?;
     }
     Ok(())
 }
 
-pub fn create_code_writer(path: &Path) -> Result<CodeWriter<BufWriter<File>>, TranslatorError> {
-    let name = get_path_name(path)?;
-    let out_file = File::options()
-        .write(true)
-        .create(true)
-        .open(format!("{}.asm", name))?;
-    out_file.set_len(0)?;
-    let out_buffer = BufWriter::new(out_file);
-    Ok(CodeWriter::new(out_buffer)?)
+///Where the course tools put the translation of `input`: `Foo.vm` becomes `Foo.asm` beside it
+///and a directory `Foo` becomes `Foo/Foo.asm`
+pub fn default_output(input: &Path) -> Result<PathBuf, TranslatorError> {
+    if input.is_dir() {
+        let dir = input.canonicalize()?;
+        let name = dir
+            .file_name()
+            .ok_or_else(|| TranslatorError::InvalidPathError(input.to_path_buf()))?;
+        Ok(input.join(format!("{}.asm", name.to_string_lossy())))
+    } else {
+        Ok(input.with_extension("asm"))
+    }
+}
+
+///Creates a code writer for the file at `output`, or for stdout when it is `-`
+pub fn create_code_writer(
+    output: &Path,
+    options: &CodegenOptions,
+) -> Result<CodeWriter<BufWriter<Box<dyn Write>>>, TranslatorError> {
+    Ok(CodeWriter::with_options(open_output(output)?, options)?)
+}
+
+///Where and in what form to write a translation
+#[derive(Debug, PartialEq, Clone)]
+pub struct Target {
+    ///The file to write, or stdout when it is `-`
+    pub output: PathBuf,
+    pub emit: Emit,
+    ///Keeps the assembly a ROM image is assembled from in this file
+    pub asm: Option<PathBuf>,
+    ///Writes a listing of each instruction's address and encoding beside its assembly here
+    pub listing: Option<PathBuf>,
+    ///Writes the JSON source map of the ROM addresses of each VM command here
+    pub source_map: Option<PathBuf>,
+}
+
+///Runs `translate` against an in memory code writer, then writes the result to `target`,
+///assembling it first for a ROM image. Nothing is written when translating fails
+pub fn translate_to<F>(
+    target: &Target,
+    options: &CodegenOptions,
+    translate: F,
+) -> Result<Translation, TranslatorError>
+where
+    F: FnOnce(&mut CodeWriter<Vec<u8>>) -> Result<Translation, TranslatorError>,
+{
+    let mut code_writer = CodeWriter::with_options(Vec::new(), options)?;
+    let translation = translate(&mut code_writer)?;
+    let asm = code_writer.into_inner();
+    if let Some(path) = &target.asm {
+        fs::write(path, &asm)?;
+    }
+    if let Some(path) = &target.source_map {
+        translation
+            .source_map
+            .write(BufWriter::new(File::create(path)?))?;
+    }
+    let listing = match &target.listing {
+        Some(path) => Some(BufWriter::new(File::create(path)?)),
+        None => None,
+    };
+    let mut out = open_output(&target.output)?;
+    match target.emit {
+        Emit::Asm => {
+            out.write_all(&asm)?;
+            if listing.is_some() {
+                write_hack(&asm, io::sink(), listing)?;
+            }
+        }
+        Emit::Hack => {
+            write_hack(&asm, &mut out, listing)?;
+        }
+    }
+    out.flush()?;
+    Ok(translation)
+}
+
+fn open_output(output: &Path) -> Result<BufWriter<Box<dyn Write>>, TranslatorError> {
+    let out_stream: Box<dyn Write> = if output.as_os_str() == "-" {
+        Box::new(io::stdout())
+    } else {
+        Box::new(File::create(output)?)
+    };
+    Ok(BufWriter::new(out_stream))
 }
 
-fn parse_file<W: Write>(
-    in_file: &Path,
+fn parse_file(in_file: &Path) -> Result<Vec<Command>, TranslatorError> {
+    let file = File::open(in_file)?;
+    let name = in_file.display().to_string();
+    Ok(Parser::new(&name, BufReader::new(file)).collect::<Result<_, _>>()?)
+}
+
+fn write_file<W: Write>(
+    namespace: &str,
+    commands: Vec<Command>,
     code_writer: &mut CodeWriter<W>,
 ) -> Result<(), TranslatorError> {
-    let file = File::open(in_file)?;
-    let parser = Parser::new(BufReader::new(file));
-    code_writer.set_namespace(get_path_name(in_file)?);
-    for command in parser {
-        code_writer.write(command?)?
+    code_writer.set_namespace(namespace);
+    for command in commands {
+        code_writer.write(command)?
     }
     Ok(())
 }
@@ -80,3 +380,844 @@ fn get_path_name(path: &Path) -> Result<&'_ str, TranslatorError> {
         .to_str()
         .ok_or_else(|| TranslatorError::InvalidPathError(path.to_path_buf()))
 }
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use assert_matches::assert_matches;
+    use hack_emulator::{Emulator, Rom};
+    use test_case::test_case;
+
+    use super::*;
+
+    const SHARED_ROUTINES: CodegenOptions = CodegenOptions {
+        shared_routines: true,
+        stack_caching: false,
+        optimise: false,
+        safe_comparisons: false,
+        temp_registers: None,
+        runtime_checks: false,
+    };
+    const STACK_CACHING: CodegenOptions = CodegenOptions {
+        shared_routines: false,
+        stack_caching: true,
+        optimise: false,
+        safe_comparisons: false,
+        temp_registers: None,
+        runtime_checks: false,
+    };
+    const OPTIMISE: CodegenOptions = CodegenOptions {
+        shared_routines: false,
+        stack_caching: false,
+        optimise: true,
+        safe_comparisons: false,
+        temp_registers: None,
+        runtime_checks: false,
+    };
+    const RUNTIME_CHECKS: CodegenOptions = CodegenOptions {
+        shared_routines: false,
+        stack_caching: false,
+        optimise: false,
+        safe_comparisons: false,
+        temp_registers: None,
+        runtime_checks: true,
+    };
+    const OPTIMISED: [CodegenOptions; 4] = [
+        SHARED_ROUTINES,
+        STACK_CACHING,
+        OPTIMISE,
+        CodegenOptions {
+            shared_routines: true,
+            stack_caching: true,
+            optimise: true,
+            safe_comparisons: false,
+            temp_registers: None,
+            runtime_checks: false,
+        },
+    ];
+
+    fn run(asm: &[u8], ram: &[(u16, i16)], cycles: u64) -> Emulator {
+        let assembly = hack_assembler::assemble(&mut BufReader::new(asm)).unwrap();
+        let mut emulator = Emulator::new(Rom::new(assembly.instructions).unwrap());
+        for (addr, value) in ram {
+            emulator.ram_mut().write(*addr, *value as u16).unwrap();
+        }
+        emulator.run(cycles).unwrap();
+        emulator
+    }
+
+    fn translate_to_asm(path: &Path, bootstrap: &Bootstrap) -> (Vec<u8>, Vec<TranslatorWarning>) {
+        translate_with_options(path, bootstrap, &CodegenOptions::default())
+    }
+
+    fn translate_with_options(
+        path: &Path,
+        bootstrap: &Bootstrap,
+        options: &CodegenOptions,
+    ) -> (Vec<u8>, Vec<TranslatorWarning>) {
+        let mut asm = Vec::new();
+        let mut code_writer = CodeWriter::with_options(&mut asm, options).unwrap();
+        let warnings = translate(&[path], &mut code_writer, bootstrap)
+            .unwrap()
+            .warnings;
+        drop(code_writer);
+        (asm, warnings)
+    }
+
+    ///Compares the pointers, temp segment, statics and the heap the tests use, leaving out the
+    ///scratch registers and the stack beyond SP, which differ between code generators
+    fn assert_same_result(emulator: &Emulator, unoptimised: &Emulator, options: &CodegenOptions) {
+        for addr in (0..13).chain(16..256).chain(3000..4100) {
+            assert_eq!(
+                emulator.ram().read(addr).unwrap(),
+                unoptimised.ram().read(addr).unwrap(),
+                "RAM[{}] with {:?}",
+                addr,
+                options
+            );
+        }
+    }
+
+    fn assert_ram(emulator: &Emulator, expected: &[(u16, i16)]) {
+        for (addr, value) in expected {
+            assert_eq!(
+                emulator.ram().read(*addr).unwrap() as i16,
+                *value,
+                "RAM[{}]",
+                addr
+            );
+        }
+    }
+
+    #[test_case(
+        "07/StackArithmetic/SimpleAdd/SimpleAdd.vm",
+        &[(0, 256)],
+        60,
+        &[(0, 257), (256, 15)];
+        "simple add"
+    )]
+    #[test_case(
+        "07/StackArithmetic/StackTest/StackTest.vm",
+        &[(0, 256)],
+        1000,
+        &[
+            (0, 266), (256, -1), (257, 0), (258, 0), (259, 0), (260, -1), (261, 0), (262, -1),
+            (263, 0), (264, 0), (265, -91),
+        ];
+        "stack test"
+    )]
+    #[test_case(
+        "07/MemoryAccess/BasicTest/BasicTest.vm",
+        &[(0, 256), (1, 300), (2, 400), (3, 3000), (4, 3010)],
+        600,
+        &[
+            (256, 472), (300, 10), (401, 21), (402, 22), (3006, 36), (3012, 42), (3015, 45),
+            (11, 510),
+        ];
+        "basic test"
+    )]
+    #[test_case(
+        "07/MemoryAccess/PointerTest/PointerTest.vm",
+        &[(0, 256)],
+        450,
+        &[(256, 6084), (3, 3030), (4, 3040), (3032, 32), (3046, 46)];
+        "pointer test"
+    )]
+    #[test_case(
+        "07/MemoryAccess/StaticTest/StaticTest.vm",
+        &[(0, 256)],
+        200,
+        &[(256, 1110)];
+        "static test"
+    )]
+    #[test_case(
+        "08/ProgramFlow/BasicLoop/BasicLoop.vm",
+        &[(0, 256), (1, 300), (2, 400), (400, 3)],
+        600,
+        &[(0, 257), (256, 6)];
+        "basic loop"
+    )]
+    #[test_case(
+        "08/ProgramFlow/FibonacciSeries/FibonacciSeries.vm",
+        &[(0, 256), (1, 300), (2, 400), (400, 6), (401, 3000)],
+        1100,
+        &[(3000, 0), (3001, 1), (3002, 1), (3003, 2), (3004, 3), (3005, 5)];
+        "fibonacci series"
+    )]
+    #[test_case(
+        "08/FunctionCalls/SimpleFunction/SimpleFunction.vm",
+        &[
+            (0, 317), (1, 317), (2, 310), (3, 3000), (4, 4000), (310, 1234), (311, 37),
+            (312, 1000), (313, 305), (314, 300), (315, 3010), (316, 4010),
+        ],
+        300,
+        &[(0, 311), (1, 305), (2, 300), (3, 3010), (4, 4010), (310, 1196)];
+        "simple function"
+    )]
+    fn it_translates_files_without_bootstrap(
+        file: &str,
+        ram: &[(u16, i16)],
+        cycles: u64,
+        expected: &[(u16, i16)],
+    ) {
+        let path = Path::new("../..").join(file);
+        let (asm, warnings) = translate_to_asm(&path, &Bootstrap::default());
+        assert_eq!(
+            warnings,
+            [TranslatorWarning::NoEntryFunction("Sys.init".to_owned())]
+        );
+        let unoptimised = run(&asm, ram, cycles);
+        assert_ram(&unoptimised, expected);
+        for options in OPTIMISED {
+            let (asm, _) = translate_with_options(&path, &Bootstrap::default(), &options);
+            assert_same_result(&run(&asm, ram, cycles * 2), &unoptimised, &options);
+        }
+    }
+
+    #[test_case(
+        "FunctionCalls/FibonacciElement",
+        6000,
+        &[(0, 262), (261, 3)];
+        "fibonacci element"
+    )]
+    #[test_case(
+        "FunctionCalls/NestedCall",
+        4000,
+        &[(0, 261), (1, 261), (2, 256), (5, 135), (6, 246)];
+        "nested call"
+    )]
+    #[test_case(
+        "FunctionCalls/StaticsTest",
+        2500,
+        &[(0, 263), (261, -2), (262, 8)];
+        "statics test"
+    )]
+    fn it_translates_programs(dir: &str, cycles: u64, expected: &[(u16, i16)]) {
+        let path = Path::new("../../08").join(dir);
+        let (asm, warnings) = translate_to_asm(&path, &Bootstrap::default());
+        assert!(warnings.is_empty());
+        let unoptimised = run(&asm, &[], cycles);
+        assert_ram(&unoptimised, expected);
+        for options in OPTIMISED {
+            let (asm, _) = translate_with_options(&path, &Bootstrap::default(), &options);
+            assert_same_result(&run(&asm, &[], cycles * 2), &unoptimised, &options);
+        }
+    }
+
+    #[test]
+    fn it_keeps_scratch_values_in_the_temp_registers_given() {
+        let path = Path::new("../../08/FunctionCalls/NestedCall");
+        for (shared_routines, stack_caching) in [(false, false), (true, true)] {
+            let options = CodegenOptions {
+                shared_routines,
+                stack_caching,
+                temp_registers: Some(vec!["SCRATCH".to_owned(), "SCRATCH2".to_owned()]),
+                ..CodegenOptions::default()
+            };
+            let (asm, _) = translate_with_options(path, &Bootstrap::default(), &options);
+            assert!(!String::from_utf8_lossy(&asm).contains("@R13"));
+            let emulator = run(&asm, &[], 8000);
+            assert_ram(
+                &emulator,
+                &[(0, 261), (1, 261), (2, 256), (5, 135), (6, 246)],
+            );
+        }
+    }
+
+    fn rom_size(asm: &[u8]) -> usize {
+        hack_assembler::assemble(&mut BufReader::new(asm))
+            .unwrap()
+            .instructions
+            .len()
+    }
+
+    #[test]
+    fn it_fits_the_os_in_rom_with_shared_routines() {
+        let dir = make_dir("os", &[]);
+        for entry in read_dir("../../../tools/OS").unwrap() {
+            let path = entry.unwrap().path();
+            fs::copy(&path, dir.join(path.file_name().unwrap())).unwrap();
+        }
+        let main = "function Main.main 0\npush constant 6\npush constant 7\n\
+                    call Math.multiply 2\nreturn\n";
+        fs::write(dir.join("Main.vm"), main).unwrap();
+        let (inline, _) = translate_to_asm(&dir, &Bootstrap::default());
+        let (shared, _) = translate_with_options(&dir, &Bootstrap::default(), &SHARED_ROUTINES);
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(
+            rom_size(&shared) * 10 < rom_size(&inline) * 7,
+            "{} instructions, down from {}",
+            rom_size(&shared),
+            rom_size(&inline)
+        );
+        assert!(rom_size(&inline) > hack_emulator::ROM_SIZE);
+        assert!(rom_size(&shared) <= hack_emulator::ROM_SIZE);
+    }
+
+    #[test]
+    fn it_shrinks_calls_returns_and_comparisons_several_fold() {
+        let mut source = String::from("function Main.f 0\n");
+        for i in 0..20 {
+            source.push_str(&format!(
+                "label L{}\ncall Main.f 1\neq\ngt\nlt\nreturn\n",
+                i
+            ));
+        }
+        let dir = make_dir("sites", &[]);
+        fs::write(dir.join("Main.vm"), source).unwrap();
+        let (inline, _) = translate_to_asm(&dir, &Bootstrap::default());
+        let (shared, _) = translate_with_options(&dir, &Bootstrap::default(), &SHARED_ROUTINES);
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(rom_size(&shared) * 4 < rom_size(&inline));
+    }
+
+    #[test]
+    fn it_reports_the_savings_per_module_when_optimising() {
+        let mut code_writer = CodeWriter::with_options(io::sink(), &OPTIMISE).unwrap();
+        let translation = translate(
+            &["../../08/FunctionCalls/FibonacciElement"],
+            &mut code_writer,
+            &Bootstrap::default(),
+        )
+        .unwrap();
+        let modules: Vec<_> = translation
+            .savings
+            .iter()
+            .map(|s| s.module.as_str())
+            .collect();
+        assert_eq!(modules, ["Sys", "Main"]);
+        let main = &translation.savings[1];
+        assert!(main.after < main.before);
+        assert!(main.to_string().starts_with(&format!(
+            "Main: {} instructions, down from {}, saving",
+            main.after, main.before
+        )));
+    }
+
+    fn no_bootstrap() -> Bootstrap {
+        Bootstrap {
+            mode: BootstrapMode::Never,
+            ..Bootstrap::default()
+        }
+    }
+
+    #[test_case(false, false; "inline")]
+    #[test_case(true, false; "shared routines")]
+    #[test_case(false, true; "stack caching")]
+    #[test_case(true, true; "shared routines and stack caching")]
+    fn it_compares_over_the_full_range_with_safe_comparisons(
+        shared_routines: bool,
+        stack_caching: bool,
+    ) {
+        let values: Vec<i16> = [
+            i16::MIN,
+            i16::MIN + 1,
+            -20000,
+            -1,
+            0,
+            1,
+            20000,
+            i16::MAX - 1,
+        ]
+        .into_iter()
+        .chain((i16::MIN..=i16::MAX).step_by(7919))
+        .chain([i16::MAX])
+        .collect();
+        let options = CodegenOptions {
+            shared_routines,
+            stack_caching,
+            optimise: false,
+            safe_comparisons: true,
+            temp_registers: None,
+            runtime_checks: false,
+        };
+        let truth = |value: bool| if value { -1 } else { 0 };
+        for x in &values {
+            let source: String = values
+                .iter()
+                .flat_map(|y| {
+                    ["eq", "gt", "lt"]
+                        .map(|cmp| format!("push constant {}\npush constant {}\n{}\n", x, y, cmp))
+                })
+                .collect();
+            let mut asm = Vec::new();
+            let mut code_writer = CodeWriter::with_options(&mut asm, &options).unwrap();
+            translate_reader("Cmp", source.as_bytes(), &mut code_writer, &no_bootstrap()).unwrap();
+            drop(code_writer);
+
+            let emulator = run(&asm, &[(0, 256)], 20_000);
+            let results: Vec<_> = values
+                .iter()
+                .flat_map(|y| [truth(x == y), truth(x > y), truth(x < y)])
+                .collect();
+            let expected: Vec<(u16, i16)> = (256..)
+                .zip(results.iter().copied())
+                .chain([(0, 256 + results.len() as i16)])
+                .collect();
+            assert_ram(&emulator, &expected);
+        }
+    }
+
+    #[test]
+    fn it_gets_overflowing_comparisons_wrong_without_safe_comparisons() {
+        let source = "push constant -20000\npush constant 20000\ngt\n";
+        let mut asm = Vec::new();
+        let mut code_writer = CodeWriter::new(&mut asm).unwrap();
+        translate_reader("Cmp", source.as_bytes(), &mut code_writer, &no_bootstrap()).unwrap();
+        drop(code_writer);
+        assert_ram(&run(&asm, &[(0, 256)], 100), &[(256, -1)]);
+    }
+
+    #[test]
+    fn it_passes_hundreds_of_arguments_and_zeroes_hundreds_of_locals() {
+        let pushes: String = (0..300).map(|i| format!("push constant {}\n", i)).collect();
+        let source = format!(
+            "function Sys.init 0\n{}call Sys.sum 300\npop static 0\nlabel HALT\ngoto HALT\n\
+             function Sys.sum 300\n\
+             push argument 0\npush argument 299\nadd\npush local 0\nadd\npush local 299\nadd\n\
+             return\n",
+            pushes
+        );
+        let dirty_stack: Vec<_> = (256..2048).map(|addr| (addr, 7)).collect();
+        for options in [CodegenOptions::default(), SHARED_ROUTINES, STACK_CACHING] {
+            let mut asm = Vec::new();
+            let mut code_writer = CodeWriter::with_options(&mut asm, &options).unwrap();
+            translate_reader(
+                "Sys",
+                source.as_bytes(),
+                &mut code_writer,
+                &Bootstrap::default(),
+            )
+            .unwrap();
+            drop(code_writer);
+            let emulator = run(&asm, &dirty_stack, 20_000);
+            assert_ram(&emulator, &[(16, 299), (0, 261)]);
+        }
+    }
+
+    #[test]
+    fn it_emits_only_the_shared_routines_used() {
+        let source = "push constant 1\npush constant 2\nlt\n";
+        let mut asm = Vec::new();
+        let mut code_writer = CodeWriter::with_options(&mut asm, &SHARED_ROUTINES).unwrap();
+        translate_reader(
+            "Cmp",
+            source.as_bytes(),
+            &mut code_writer,
+            &Bootstrap::default(),
+        )
+        .unwrap();
+        drop(code_writer);
+        let asm = String::from_utf8(asm).unwrap();
+        assert!(asm.contains("@$$cmp.lt\n0;JMP\n"));
+        assert!(asm.contains("($$halt)\n@$$halt\n0;JMP\n"));
+        assert!(!asm.contains("($$call)"));
+        assert!(!asm.contains("($$return)"));
+        assert_ram(
+            &run(asm.as_bytes(), &[(0, 256)], 100),
+            &[(0, 257), (256, -1)],
+        );
+    }
+
+    #[test]
+    fn it_customises_the_bootstrap() {
+        let path = Path::new("../../08/FunctionCalls/FibonacciElement");
+        let bootstrap = Bootstrap {
+            sp: 300,
+            lcl: Some(300),
+            that: Some(4000),
+            entry: "Main.fibonacci".to_owned(),
+            ..Bootstrap::default()
+        };
+        let (asm, _) = translate_to_asm(path, &bootstrap);
+        let asm = String::from_utf8(asm).unwrap();
+        assert!(asm.starts_with("@300\nD=A\n@SP\nM=D\n@300\nD=A\n@LCL\nM=D\n@4000\nD=A\n@THAT\nM=D\n//call Main.fibonacci 0\n"));
+    }
+
+    #[test_case(BootstrapMode::Never, "../../08/FunctionCalls/FibonacciElement", false; "disabled")]
+    #[test_case(BootstrapMode::Always, "../../07/StackArithmetic/SimpleAdd/SimpleAdd.vm", true; "forced")]
+    fn it_follows_the_bootstrap_mode(mode: BootstrapMode, path: &str, bootstrapped: bool) {
+        let bootstrap = Bootstrap {
+            mode,
+            ..Bootstrap::default()
+        };
+        let (asm, warnings) = translate_to_asm(Path::new(path), &bootstrap);
+        assert!(warnings.is_empty());
+        let asm = String::from_utf8(asm).unwrap();
+        assert_eq!(asm.starts_with("@256\nD=A\n@SP\nM=D\n"), bootstrapped);
+    }
+
+    fn make_dir(name: &str, files: &[&str]) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("vm_translator_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for file in files {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn it_orders_files_by_name_with_sys_first() {
+        let dir = make_dir(
+            "order",
+            &["Main.vm", "Sys.vm", "Ball.vm", "notes.txt", "Bat.vm"],
+        );
+        let names: Vec<_> = vm_files(&dir)
+            .unwrap()
+            .iter()
+            .map(|path| get_path_name(path).unwrap().to_owned())
+            .collect();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(names, ["Sys", "Ball", "Bat", "Main"]);
+    }
+
+    #[test]
+    fn it_rejects_files_differing_only_by_case() {
+        let dir = make_dir("case", &["Main.vm", "Ball.vm", "main.vm"]);
+        let result = vm_files(&dir).and_then(|files| check_module_names(&files));
+        fs::remove_dir_all(&dir).unwrap();
+        assert_matches!(result, Err(TranslatorError::CaseClash(a, b))
+            if a.ends_with("Main.vm") && b.ends_with("main.vm"));
+    }
+
+    #[test]
+    fn it_rejects_modules_with_the_same_name() {
+        let mut code_writer = CodeWriter::new(Vec::new()).unwrap();
+        let result = translate(
+            &[
+                "../../08/FunctionCalls/NestedCall",
+                "../../08/FunctionCalls/StaticsTest",
+            ],
+            &mut code_writer,
+            &Bootstrap::default(),
+        );
+        assert_matches!(result, Err(TranslatorError::DuplicateModule(_, _, name)) if name == "Sys");
+    }
+
+    #[test]
+    fn it_combines_several_inputs_into_one_program() {
+        let dir = Path::new("../../08/FunctionCalls/FibonacciElement");
+        let mut asm = Vec::new();
+        let mut code_writer = CodeWriter::new(&mut asm).unwrap();
+        let inputs = [dir.join("Main.vm"), dir.join("Sys.vm")];
+        translate(&inputs, &mut code_writer, &Bootstrap::default()).unwrap();
+        drop(code_writer);
+        assert_ram(&run(&asm, &[], 6000), &[(0, 262), (261, 3)]);
+    }
+
+    #[test]
+    fn it_translates_a_module_from_a_reader() {
+        let source = "push constant 7\npop static 0\n";
+        let mut asm = Vec::new();
+        let mut code_writer = CodeWriter::new(&mut asm).unwrap();
+        let translation = translate_reader(
+            "Piped",
+            source.as_bytes(),
+            &mut code_writer,
+            &Bootstrap::default(),
+        )
+        .unwrap();
+        drop(code_writer);
+        assert_eq!(translation.warnings.len(), 1);
+        assert!(String::from_utf8(asm).unwrap().contains("@Piped.0\nM=D\n"));
+    }
+
+    #[test]
+    fn it_translates_code_the_jack_compiler_writes_with_warnings() {
+        let source =
+            "function Sys.init 0\npush constant 0\ncall Sys.sign 1\nlabel HALT\ngoto HALT\n\
+                      function Sys.sign 0\npush argument 0\nif-goto IF_TRUE0\ngoto IF_FALSE0\n\
+                      label IF_TRUE0\npush constant 1\nreturn\ngoto IF_END0\n\
+                      label IF_FALSE0\npush constant 2\nreturn\nlabel IF_END0\n";
+        let mut asm = Vec::new();
+        let mut code_writer = CodeWriter::new(&mut asm).unwrap();
+        let translation = translate_reader(
+            "Sys",
+            source.as_bytes(),
+            &mut code_writer,
+            &Bootstrap::default(),
+        )
+        .unwrap();
+        drop(code_writer);
+        let warnings: Vec<_> = translation
+            .warnings
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            warnings,
+            [
+                "Sys.vm:13: unreachable code",
+                "Sys.vm:17: function Sys.sign falls through without returning",
+            ]
+        );
+        assert_ram(&run(&asm, &[], 1000), &[(0, 262), (261, 2)]);
+    }
+
+    #[test]
+    fn it_validates_before_writing_anything() {
+        let source = "function Main.main 0\ncall Main.missing 0\nreturn\n";
+        let mut asm = Vec::new();
+        let mut code_writer = CodeWriter::new(&mut asm).unwrap();
+        let result = translate_reader(
+            "Main",
+            source.as_bytes(),
+            &mut code_writer,
+            &Bootstrap::default(),
+        );
+        drop(code_writer);
+        assert_matches!(result, Err(TranslatorError::Validation(errors)) => {
+            assert_eq!(errors[0].to_string(), "Main.vm:2: call to undefined function Main.missing")
+        });
+        assert!(asm.is_empty());
+    }
+
+    #[test]
+    fn it_puts_the_output_next_to_the_input() {
+        let dir = Path::new("../../08/FunctionCalls/FibonacciElement");
+        assert_eq!(
+            default_output(dir).unwrap(),
+            dir.join("FibonacciElement.asm")
+        );
+        assert_eq!(
+            default_output(&dir.join("Main.vm")).unwrap(),
+            dir.join("Main.asm")
+        );
+    }
+
+    #[test]
+    fn it_keeps_dots_in_directory_names_for_the_output() {
+        let dir = make_dir("Prog.v2", &[]);
+        let output = default_output(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let name = dir.file_name().unwrap().to_string_lossy();
+        assert_eq!(output, dir.join(format!("{}.asm", name)));
+    }
+
+    #[test]
+    fn it_assembles_a_rom_image_with_the_assembly_and_listing_beside_it() {
+        let dir = make_dir("emit_hack", &[]);
+        let input = Path::new("../../08/FunctionCalls/FibonacciElement");
+        let target = Target {
+            output: dir.join("Fib.hack"),
+            emit: Emit::Hack,
+            asm: Some(dir.join("Fib.asm")),
+            listing: Some(dir.join("Fib.lst")),
+            source_map: None,
+        };
+        translate_to(&target, &CodegenOptions::default(), |code_writer| {
+            translate(&[input], code_writer, &Bootstrap::default())
+        })
+        .unwrap();
+        let hack = fs::read_to_string(&target.output).unwrap();
+        let asm = fs::read(dir.join("Fib.asm")).unwrap();
+        let listing = fs::read_to_string(dir.join("Fib.lst")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let (expected_asm, _) = translate_to_asm(input, &Bootstrap::default());
+        assert_eq!(asm, expected_asm);
+        let assembly = hack_assembler::assemble(&mut BufReader::new(&asm[..])).unwrap();
+        let rom: Vec<_> = hack
+            .lines()
+            .map(|line| u16::from_str_radix(line, 2).unwrap())
+            .collect();
+        assert_eq!(rom, assembly.instructions);
+        assert_eq!(
+            listing.lines().count(),
+            String::from_utf8(asm).unwrap().lines().count()
+        );
+        assert!(listing.contains("    0  0000000100000000  @256\n"));
+    }
+
+    #[test_case(false, false; "inline")]
+    #[test_case(true, true; "shared routines and stack caching")]
+    fn it_maps_every_instruction_to_its_source(shared_routines: bool, stack_caching: bool) {
+        let dir = make_dir(&format!("source_map_{}", shared_routines), &[]);
+        let input = Path::new("../../08/FunctionCalls/FibonacciElement");
+        let target = Target {
+            output: dir.join("Fib.asm"),
+            emit: Emit::Asm,
+            asm: None,
+            listing: None,
+            source_map: Some(dir.join("Fib.map.json")),
+        };
+        let options = CodegenOptions {
+            shared_routines,
+            stack_caching,
+            ..CodegenOptions::default()
+        };
+        let translation = translate_to(&target, &options, |code_writer| {
+            translate(&[input], code_writer, &Bootstrap::default())
+        })
+        .unwrap();
+        let asm = fs::read(&target.output).unwrap();
+        let written = SourceMap::load(&dir.join("Fib.map.json")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(written, translation.source_map);
+        let assembly = hack_assembler::assemble(&mut BufReader::new(&asm[..])).unwrap();
+        let ranges = written.ranges();
+        assert_eq!(ranges[0].start, 0);
+        assert_eq!(ranges[0].function, "bootstrap");
+        assert_eq!(
+            ranges.last().unwrap().end as usize,
+            assembly.instructions.len()
+        );
+        for pair in ranges.windows(2) {
+            assert_eq!(pair[0].end, pair[1].start);
+        }
+        let fibonacci = ranges
+            .iter()
+            .find(|range| range.function == "Main.fibonacci")
+            .unwrap();
+        assert!(fibonacci.file.as_ref().unwrap().ends_with("Main.vm"));
+        //Without locals, `function Main.fibonacci 0` on line 11 is only a label
+        assert_eq!(fibonacci.line, Some(12));
+        assert_eq!(
+            assembly.symbols.get_line_no("Main.fibonacci"),
+            Some(fibonacci.start)
+        );
+        assert_eq!(
+            ranges.last().unwrap().function,
+            if shared_routines {
+                "shared routines"
+            } else {
+                "Main.fibonacci"
+            }
+        );
+    }
+
+    #[test]
+    fn it_names_statics_after_the_file_stem() {
+        let source = "push constant 7\npop static 3\npush static 3\n";
+        let dir = make_dir("statics", &[]);
+        fs::write(dir.join("FooBar.vm"), source).unwrap();
+        let (asm, _) = translate_to_asm(&dir.join("FooBar.vm"), &Bootstrap::default());
+        fs::remove_dir_all(&dir).unwrap();
+        let asm = String::from_utf8(asm).unwrap();
+        assert!(asm.contains("@FooBar.3\nM=D\n"));
+        assert!(asm.contains("@FooBar.3\nD=M\n"));
+    }
+
+    #[test]
+    fn it_scopes_labels_to_their_function() {
+        let source = "function Main.first 0\nlabel LOOP\ngoto LOOP\n\
+                      function Main.second 0\nlabel LOOP\nif-goto LOOP\n";
+        let mut asm = Vec::new();
+        let mut code_writer = CodeWriter::new(&mut asm).unwrap();
+        for command in Parser::new("Main.vm", BufReader::new(source.as_bytes())) {
+            code_writer.write(command.unwrap()).unwrap();
+        }
+        drop(code_writer);
+        let asm = String::from_utf8(asm).unwrap();
+        assert!(asm.contains("(Main.first$LOOP)\n"));
+        assert!(asm.contains("@Main.first$LOOP\n0;JMP\n"));
+        assert!(asm.contains("(Main.second$LOOP)\n"));
+        assert!(asm.contains("@Main.second$LOOP\nD;JGT\n"));
+        assert!(hack_assembler::assemble(&mut BufReader::new(asm.as_bytes())).is_ok());
+    }
+
+    #[test_case(
+        "function Sys.init 0\ncall Sys.init 0\nreturn\n",
+        RuntimeError::StackOverflow;
+        "stack overflow"
+    )]
+    #[test_case("function Sys.init 1\nadd\nreturn\n", RuntimeError::StackUnderflow; "stack underflow")]
+    #[test_case(
+        "function Sys.init 1\npush local 1\nreturn\n",
+        RuntimeError::LocalOutOfBounds;
+        "local out of bounds"
+    )]
+    #[test_case(
+        "function Sys.init 0\npush constant 1\ncall Sys.first 1\nlabel HALT\ngoto HALT\n\
+         function Sys.first 0\npush argument 0\npush argument 1\nreturn\n",
+        RuntimeError::ArgumentOutOfBounds;
+        "argument out of bounds"
+    )]
+    fn it_traps_runtime_errors_with_runtime_checks(source: &str, error: RuntimeError) {
+        for shared_routines in [false, true] {
+            let options = CodegenOptions {
+                shared_routines,
+                ..RUNTIME_CHECKS
+            };
+            let mut asm = Vec::new();
+            let mut code_writer = CodeWriter::with_options(&mut asm, &options).unwrap();
+            translate_reader(
+                "Sys",
+                source.as_bytes(),
+                &mut code_writer,
+                &Bootstrap::default(),
+            )
+            .unwrap();
+            drop(code_writer);
+            let emulator = run(&asm, &[], 100_000);
+            assert!(emulator.is_halted());
+            let code = emulator.ram().read(TRAP_CELL).unwrap();
+            assert_eq!(RuntimeError::from_code(code), Some(error));
+        }
+    }
+
+    #[test_case("add\nreturn\n", Some(RuntimeError::StackUnderflow); "trapping")]
+    #[test_case("label HALT\ngoto HALT\n", None; "not trapping")]
+    fn it_keeps_the_trap_cell_apart_from_the_statics(end: &str, error: Option<RuntimeError>) {
+        let statics: String = (0..240)
+            .map(|i| format!("push constant {}\npop static {}\n", i + 1, i))
+            .collect();
+        let source = format!("function Sys.init 0\n{}{}", statics, end);
+        let mut asm = Vec::new();
+        let mut code_writer = CodeWriter::with_options(&mut asm, &RUNTIME_CHECKS).unwrap();
+        translate_reader(
+            "Sys",
+            source.as_bytes(),
+            &mut code_writer,
+            &Bootstrap::default(),
+        )
+        .unwrap();
+        drop(code_writer);
+        let emulator = run(&asm, &[], 100_000);
+        let expected: Vec<_> = (16..256).zip(1..).collect();
+        assert_ram(&emulator, &expected);
+        let code = emulator.ram().read(TRAP_CELL).unwrap();
+        assert_eq!(RuntimeError::from_code(code), error);
+        assert_eq!(code == 0, error.is_none());
+    }
+
+    #[test_case("FunctionCalls/FibonacciElement", 6000; "fibonacci element")]
+    #[test_case("FunctionCalls/NestedCall", 4000; "nested call")]
+    #[test_case("FunctionCalls/StaticsTest", 2500; "statics test")]
+    fn it_runs_correct_programs_the_same_with_runtime_checks(dir: &str, cycles: u64) {
+        let path = Path::new("../../08").join(dir);
+        let (asm, _) = translate_to_asm(&path, &Bootstrap::default());
+        let unchecked = run(&asm, &[], cycles);
+        let (asm, _) = translate_with_options(&path, &Bootstrap::default(), &RUNTIME_CHECKS);
+        let checked = run(&asm, &[], cycles * 4);
+        assert_eq!(checked.ram().read(TRAP_CELL).unwrap(), 0);
+        assert_same_result(&checked, &unchecked, &RUNTIME_CHECKS);
+    }
+
+    #[test]
+    fn it_rejects_runtime_checks_with_stack_caching() {
+        let options = CodegenOptions {
+            stack_caching: true,
+            ..RUNTIME_CHECKS
+        };
+        assert_matches!(
+            CodeWriter::with_options(Vec::new(), &options).err(),
+            Some(CodeWriterError::ChecksWithStackCaching)
+        );
+    }
+
+    #[test]
+    fn it_keeps_the_trap_cell_out_of_the_temp_registers() {
+        let options = CodegenOptions {
+            temp_registers: Some(vec!["R14".to_owned(), "R15".to_owned()]),
+            ..RUNTIME_CHECKS
+        };
+        assert_matches!(
+            CodeWriter::with_options(Vec::new(), &options).err(),
+            Some(CodeWriterError::TrapCellAsTempRegister(cell)) if cell == "R15"
+        );
+    }
+}
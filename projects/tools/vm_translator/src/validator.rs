@@ -0,0 +1,357 @@
+use std::collections::{HashMap, HashSet};
+
+pub use crate::parser::Location;
+use crate::parser::{Command, Flow, Goto, Marker, ParsedCmd, Segment};
+
+const POINTER_SIZE: u16 = 2;
+const TEMP_SIZE: u16 = 8;
+
+#[derive(thiserror::Error, Debug, PartialEq, Clone)]
+pub enum Problem {
+    #[error("call to undefined function {0}")]
+    UndefinedFunction(String),
+    #[error("goto to label {0}, which is not defined in {1}")]
+    UndefinedLabel(String, String),
+    #[error("pop to the constant segment")]
+    PopConstant,
+    #[error("{1} {0} is out of bounds")]
+    OutOfBounds(u16, Segment),
+    #[error("{0} is called with {1} arguments here, but with {2} at {3}")]
+    ArgCountMismatch(String, u16, u16, Location),
+    #[error("unreachable code")]
+    Unreachable,
+    #[error("function {0} falls through without returning")]
+    FallThrough(String),
+}
+
+impl Problem {
+    ///Whether the program can be translated as it is regardless, as the Jack compiler leaves a
+    ///`goto` after a `return` and ends functions on a label no branch falls through to
+    pub fn is_warning(&self) -> bool {
+        matches!(self, Problem::Unreachable | Problem::FallThrough(_))
+    }
+}
+
+///A command that parses but is wrong, or suspect, given the rest of the program
+#[derive(thiserror::Error, Debug, PartialEq, Clone)]
+#[error("{location}: {problem}")]
+pub struct ValidationError {
+    pub location: Location,
+    pub problem: Problem,
+}
+
+///The checks made across all modules before anything is translated
+#[derive(Default)]
+struct Validator<'a> {
+    functions: HashSet<&'a str>,
+    labels: HashSet<(usize, Option<&'a str>, &'a str)>,
+    calls: HashMap<&'a str, (u16, Location)>,
+    findings: Vec<ValidationError>,
+}
+
+///Validates the modules as a whole, as calls may cross them, giving the warnings found unless
+///there are errors that stop the program being translated
+pub fn validate<S>(
+    sources: &[(S, Vec<Command>)],
+) -> Result<Vec<ValidationError>, Vec<ValidationError>> {
+    let (warnings, errors): (Vec<_>, _) = findings(sources)
+        .into_iter()
+        .partition(|finding| finding.problem.is_warning());
+    if errors.is_empty() {
+        Ok(warnings)
+    } else {
+        Err(errors)
+    }
+}
+
+///Every error and warning in the modules, in the order they come in
+pub fn findings<S>(sources: &[(S, Vec<Command>)]) -> Vec<ValidationError> {
+    let mut validator = Validator::default();
+    for (module, (_, commands)) in sources.iter().enumerate() {
+        validator.collect_definitions(module, commands);
+    }
+    for (module, (_, commands)) in sources.iter().enumerate() {
+        validator.check_module(module, commands);
+    }
+    validator.findings
+}
+
+impl<'a> Validator<'a> {
+    fn collect_definitions(&mut self, module: usize, commands: &'a [Command]) {
+        let mut function = None;
+        for command in commands {
+            match command.parsed() {
+                ParsedCmd::Marker(Marker::Function(name, _)) => {
+                    self.functions.insert(name.as_str());
+                    function = Some(name.as_str());
+                }
+                ParsedCmd::Marker(Marker::Label(label)) => {
+                    self.labels.insert((module, function, label.as_str()));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn check_module(&mut self, module: usize, commands: &'a [Command]) {
+        let mut function: Option<&str> = None;
+        let mut last = None;
+        let mut reachable = true;
+        let mut reported_unreachable = false;
+        for command in commands {
+            let location = || command.location().clone();
+            let parsed = command.parsed();
+            if let ParsedCmd::Noop = parsed {
+                continue;
+            }
+            if let ParsedCmd::Marker(marker) = parsed {
+                if let (Marker::Function(..), Some(name)) = (marker, function) {
+                    self.check_return(name, reachable, last);
+                }
+                reachable = true;
+                reported_unreachable = false;
+            } else if !reachable && !reported_unreachable {
+                self.report(location(), Problem::Unreachable);
+                reported_unreachable = true;
+            }
+            last = Some(command);
+            match parsed {
+                ParsedCmd::Marker(Marker::Function(name, _)) => function = Some(name.as_str()),
+                ParsedCmd::Pop(Segment::Constant, _) => {
+                    self.report(location(), Problem::PopConstant)
+                }
+                ParsedCmd::Push(segment, idx) | ParsedCmd::Pop(segment, idx) => {
+                    let size = match segment {
+                        Segment::Pointer => Some(POINTER_SIZE),
+                        Segment::Temp => Some(TEMP_SIZE),
+                        _ => None,
+                    };
+                    if matches!(size, Some(size) if *idx >= size) {
+                        self.report(location(), Problem::OutOfBounds(*idx, *segment));
+                    }
+                }
+                ParsedCmd::Flow(Flow::Goto(goto, label)) => {
+                    if !self.labels.contains(&(module, function, label.as_str())) {
+                        let scope = function.unwrap_or(&command.location().file).to_owned();
+                        self.report(location(), Problem::UndefinedLabel(label.clone(), scope));
+                    }
+                    if *goto == Goto::Direct {
+                        reachable = false;
+                    }
+                }
+                ParsedCmd::Flow(Flow::Call(name, arg_count)) => {
+                    if !self.functions.contains(name.as_str()) {
+                        self.report(location(), Problem::UndefinedFunction(name.clone()));
+                    }
+                    match self.calls.get(name.as_str()) {
+                        Some((first_count, first)) if first_count != arg_count => {
+                            let problem = Problem::ArgCountMismatch(
+                                name.clone(),
+                                *arg_count,
+                                *first_count,
+                                first.clone(),
+                            );
+                            self.report(location(), problem);
+                        }
+                        Some(_) => {}
+                        None => {
+                            self.calls.insert(name.as_str(), (*arg_count, location()));
+                        }
+                    }
+                }
+                ParsedCmd::Flow(Flow::Return) => reachable = false,
+                _ => {}
+            }
+        }
+        if let Some(name) = function {
+            self.check_return(name, reachable, last);
+        }
+    }
+
+    ///A function whose end is reachable would run on into whatever code follows it
+    fn check_return(&mut self, function: &str, reachable: bool, last: Option<&Command>) {
+        if let (true, Some(last)) = (reachable, last) {
+            let problem = Problem::FallThrough(function.to_owned());
+            self.report(last.location().clone(), problem);
+        }
+    }
+
+    fn report(&mut self, location: Location, problem: Problem) {
+        self.findings.push(ValidationError { location, problem });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::BufReader;
+
+    use super::*;
+    use crate::parser::Parser;
+
+    fn parse_sources<'a>(sources: &[(&'a str, &str)]) -> Vec<(&'a str, Vec<Command>)> {
+        sources
+            .iter()
+            .map(|(name, source)| {
+                let file = format!("{}.vm", name);
+                let commands = Parser::new(&file, BufReader::new(source.as_bytes()))
+                    .collect::<Result<_, _>>()
+                    .unwrap();
+                (*name, commands)
+            })
+            .collect()
+    }
+
+    fn validate_sources(sources: &[(&str, &str)]) -> Vec<String> {
+        let (Ok(findings) | Err(findings)) = validate(&parse_sources(sources));
+        findings.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn it_accepts_a_valid_program() {
+        let main = "function Main.main 1\n\
+                    label LOOP\n\
+                    push pointer 1\n\
+                    pop temp 7\n\
+                    push constant 1\n\
+                    if-goto LOOP\n\
+                    call Main.double 1\n\
+                    return\n\
+                    function Main.double 0\n\
+                    push argument 0\n\
+                    push argument 0\n\
+                    add\n\
+                    return\n";
+        let sys = "function Sys.init 0\n\
+                   push constant 3\n\
+                   call Main.double 1\n\
+                   label HALT\n\
+                   goto HALT\n";
+        assert!(validate_sources(&[("Main", main), ("Sys", sys)]).is_empty());
+    }
+
+    #[test]
+    fn it_accepts_code_outside_functions() {
+        let source = "push constant 1\nlabel LOOP\nif-goto LOOP\n";
+        assert!(validate_sources(&[("Loop", source)]).is_empty());
+    }
+
+    #[test]
+    fn it_reports_undefined_calls_and_labels() {
+        let main = "function Main.main 0\n\
+                    call Main.missing 0\n\
+                    goto ELSEWHERE\n\
+                    function Main.other 0\n\
+                    label ELSEWHERE\n\
+                    goto ELSEWHERE\n";
+        assert_eq!(
+            validate_sources(&[("Main", main)]),
+            [
+                "Main.vm:2: call to undefined function Main.missing",
+                "Main.vm:3: goto to label ELSEWHERE, which is not defined in Main.main",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_reports_invalid_segment_accesses() {
+        let source = "pop constant 3\npush pointer 2\npop temp 8\npush pointer 1\n";
+        assert_eq!(
+            validate_sources(&[("Mem", source)]),
+            [
+                "Mem.vm:1: pop to the constant segment",
+                "Mem.vm:2: Pointer 2 is out of bounds",
+                "Mem.vm:3: Temp 8 is out of bounds",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_reports_calls_disagreeing_on_the_arg_count() {
+        let main = "function Main.f 0\n\
+                    push constant 0\n\
+                    return\n\
+                    function Main.main 0\n\
+                    \n\
+                    call Main.f 1\n\
+                    call Main.f 1\n\
+                    return\n";
+        let sys = "function Sys.init 0\n\
+                   call Main.f 2\n\
+                   return\n";
+        assert_eq!(
+            validate_sources(&[("Main", main), ("Sys", sys)]),
+            ["Sys.vm:2: Main.f is called with 2 arguments here, but with 1 at Main.vm:6"]
+        );
+    }
+
+    #[test]
+    fn it_reports_unreachable_code_once_per_block() {
+        let source = "function Dead.f 0\n\
+                      push constant 0\n\
+                      return\n\
+                      push constant 1\n\
+                      add\n\
+                      label REACHED\n\
+                      push constant 0\n\
+                      return\n";
+        assert_eq!(
+            validate_sources(&[("Dead", source)]),
+            ["Dead.vm:4: unreachable code"]
+        );
+    }
+
+    #[test]
+    fn it_reports_functions_falling_through() {
+        let source = "function Fall.f 0\n\
+                      push constant 0\n\
+                      function Fall.g 0\n\
+                      label LOOP\n\
+                      if-goto LOOP\n\
+                      // the end\n";
+        assert_eq!(
+            validate_sources(&[("Fall", source)]),
+            [
+                "Fall.vm:2: function Fall.f falls through without returning",
+                "Fall.vm:5: function Fall.g falls through without returning",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_only_warns_of_code_the_jack_compiler_writes() {
+        let source = "function Main.nextMask 0\n\
+                      push argument 0\n\
+                      push constant 0\n\
+                      eq\n\
+                      if-goto IF_TRUE0\n\
+                      goto IF_FALSE0\n\
+                      label IF_TRUE0\n\
+                      push constant 1\n\
+                      return\n\
+                      goto IF_END0\n\
+                      label IF_FALSE0\n\
+                      push argument 0\n\
+                      push argument 0\n\
+                      add\n\
+                      return\n\
+                      label IF_END0\n";
+        let sources = [("Main", source)];
+        assert_eq!(
+            validate_sources(&sources),
+            [
+                "Main.vm:10: unreachable code",
+                "Main.vm:16: function Main.nextMask falls through without returning",
+            ]
+        );
+        assert!(validate(&parse_sources(&sources)).is_ok());
+    }
+
+    #[test]
+    fn it_leaves_out_the_warnings_when_there_are_errors() {
+        let source = "function Main.f 0\npop constant 0\n";
+        assert_eq!(
+            validate_sources(&[("Main", source)]),
+            ["Main.vm:2: pop to the constant segment"]
+        );
+    }
+}